@@ -6,12 +6,15 @@ use hyper::service::service_fn;
 use hyper::{Method, Request, Response, StatusCode};
 use hyper_util::rt::TokioIo;
 use hyper_util::server::conn::auto::Builder;
+use std::collections::{HashMap, HashSet};
 use std::convert::Infallible;
 use std::env;
-use std::net::SocketAddr;
+use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
 use std::time::{Instant, SystemTime};
 use tokio::fs;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
@@ -64,11 +67,100 @@ fn ts() -> String {
     format!("{D}{:02}:{:02}:{:02}{RST}", (now / 3600) % 24, (now / 60) % 60, now % 60)
 }
 
+/// UTC `YYYY-MM-DDTHH:MM:SSZ`, for log files.
+fn iso_ts() -> String {
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (days, secs) = ((now / 86400) as i64, now % 86400);
+    // Civil-from-days (Howard Hinnant)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!("{y:04}-{m:02}-{d:02}T{:02}:{:02}:{:02}Z", secs / 3600, (secs / 60) % 60, secs % 60)
+}
+
+fn peer_label(ip: Option<IpAddr>) -> String {
+    ip.map_or("unix".to_string(), |ip| ip.to_string())
+}
+
+/// Where request lines go: the terminal unless `--quiet`, plus an optional file.
+struct RequestLog {
+    quiet: bool,
+    file: Option<std::sync::Mutex<std::fs::File>>,
+}
+
+impl RequestLog {
+    fn record(&self, code: u16, method: &str, path: &str, ip: Option<IpAddr>, user: Option<&str>) {
+        let ip = match user {
+            Some(u) => format!("{u}@{}", peer_label(ip)),
+            None => peer_label(ip),
+        };
+        if !self.quiet {
+            println!("  {} {} {} {CY}{}{RST} {D}{}{RST}", ts(), status_style(code), method_style(method), path, ip);
+        }
+        if let Some(ref file) = self.file {
+            use std::io::Write;
+            let mut f = file.lock().unwrap_or_else(|e| e.into_inner());
+            let _ = writeln!(f, "{} {code} {method} {path} {ip}", iso_ts());
+        }
+    }
+}
+
 // ── Shared server config ──────────────────────────────────────────────
 
 struct ServerConfig {
-    root: PathBuf,
+    mounts: Vec<Mount>,   // mounts[0] is the main root, with an empty prefix
     auth: Option<String>, // base64 encoded "user:pass"
+    symlinks: SymlinkPolicy,
+    guard: Guard,
+    sessions: Sessions,
+    tokens: Option<TokenStore>,
+    totp: Option<TotpStore>,
+    spa: bool, // unknown non-asset paths get the mount's index.html
+    redirects: Redirects,
+    headers: HeaderRules,
+}
+
+/// Who may use a mount.
+enum Access {
+    Inherit,       // whatever `--auth` says
+    Public,        // no auth, even when `--auth` is set
+    Basic(String), // its own base64 encoded "user:pass"
+}
+
+/// A directory served under a URL prefix (`--mount /builds=~/builds`).
+struct Mount {
+    prefix: String, // a single path segment; empty for the main root
+    root: PathBuf,
+    upload: bool,
+    access: Access,
+    hidden: Arc<HiddenPolicy>,
+    search: Option<Arc<ContentSearch>>,
+}
+
+impl ServerConfig {
+    /// The mount serving a clean relative path, and the path within that mount.
+    fn mount_for(&self, rel: &str) -> (&Mount, String) {
+        let (first, rest) = rel.split_once('/').unwrap_or((rel, ""));
+        match self.mounts.iter().skip(1).find(|m| m.prefix == first) {
+            Some(m) => (m, rest.to_string()),
+            None => (&self.mounts[0], rel.to_string()),
+        }
+    }
+
+    /// The base64 credentials a mount requires, if any.
+    fn required_auth<'a>(&'a self, mount: &'a Mount) -> Option<&'a str> {
+        match mount.access {
+            Access::Inherit => self.auth.as_deref(),
+            Access::Public => None,
+            Access::Basic(ref b) => Some(b),
+        }
+    }
 }
 
 // ── Content types ─────────────────────────────────────────────────────
@@ -173,7 +265,8 @@ fn percent_decode(input: &str) -> String {
     let bytes = input.as_bytes();
     let mut i = 0;
     while i < bytes.len() {
-        if bytes[i] == b'%' && i + 2 < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit() {
             if let Ok(byte) = u8::from_str_radix(
                 std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or(""), 16,
             ) { result.push(byte); i += 3; continue; }
@@ -221,34 +314,103 @@ fn render_qr_terminal(url: &str) {
 
 // ── IP geolocation ────────────────────────────────────────────────────
 
-async fn geolocate_ip(ip: &str) -> Option<String> {
-    // Skip private/local IPs
-    if ip.starts_with("127.") || ip.starts_with("10.") || ip.starts_with("192.168.")
-        || ip.starts_with("172.") || ip == "::1" || ip.starts_with("fe80") {
-        return None;
-    }
-    // Use ip-api.com (free, no key needed, 45 req/min)
-    let url = format!("http://ip-api.com/json/{}?fields=city,country,query", ip);
-    let output = Command::new("curl")
-        .arg("-s").arg("-m").arg("2") // 2 second timeout
-        .arg(&url)
-        .output()
-        .await
-        .ok()?;
-    let body = String::from_utf8_lossy(&output.stdout);
-    // Simple JSON parsing without serde
-    let city = extract_json_string(&body, "city")?;
-    let country = extract_json_string(&body, "country")?;
-    if city.is_empty() && country.is_empty() { return None; }
-    if city.is_empty() { Some(country) }
-    else { Some(format!("{city}, {country}")) }
-}
-
-fn extract_json_string(json: &str, key: &str) -> Option<String> {
-    let pattern = format!("\"{key}\":\"");
-    let start = json.find(&pattern)? + pattern.len();
-    let end = json[start..].find('"')? + start;
-    Some(json[start..end].to_string())
+/// Addresses that can never be on the public internet, so there is nothing
+/// to look up.
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_private() || v4.is_loopback() || v4.is_link_local() || v4.is_unspecified()
+                || v4.is_broadcast() || v4.is_documentation() || v4.is_multicast()
+                || a == 0 || (a == 100 && (64..128).contains(&b))) // 0/8, CGNAT 100.64/10
+        }
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_public_ip(IpAddr::V4(v4)),
+            None => !(v6.is_loopback() || v6.is_unspecified() || v6.is_multicast()
+                || v6.is_unicast_link_local() || (v6.segments()[0] & 0xfe00) == 0xfc00),
+        },
+    }
+}
+
+/// Where distributions and `geoipupdate` put the free databases.
+const GEOIP_PATHS: &[&str] = &[
+    "/usr/share/GeoIP", "/var/lib/GeoIP", "/usr/local/share/GeoIP", "/opt/homebrew/var/GeoIP",
+];
+const GEOIP_FILES: &[&str] = &[
+    "GeoLite2-City.mmdb", "GeoLite2-Country.mmdb", "GeoLite2-ASN.mmdb",
+    "dbip-city-lite.mmdb", "dbip-country-lite.mmdb", "dbip-asn-lite.mmdb",
+];
+
+/// The parts of a GeoIP2/GeoLite2/DB-IP record we print. City, Country and
+/// ASN databases each fill in some of them.
+#[derive(Deserialize)]
+struct GeoRecord {
+    city: Option<GeoNames>,
+    country: Option<GeoNames>,
+    autonomous_system_number: Option<u32>,
+    autonomous_system_organization: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GeoNames {
+    #[serde(default)]
+    names: HashMap<String, String>,
+    iso_code: Option<String>,
+}
+
+impl GeoNames {
+    fn name(&self) -> Option<&str> {
+        self.names.get("en").or_else(|| self.names.values().next()).map(|s| s.as_str())
+            .or(self.iso_code.as_deref())
+    }
+}
+
+/// Local MMDB files, consulted in order. Nothing leaves the machine.
+struct GeoIp {
+    readers: Vec<(PathBuf, maxminddb::Reader<Vec<u8>>)>,
+}
+
+impl GeoIp {
+    fn open(paths: &[PathBuf]) -> Result<Self, String> {
+        let mut readers = Vec::new();
+        for path in paths {
+            let reader = maxminddb::Reader::open_readfile(path).map_err(|e| format!("{}: {e}", path.display()))?;
+            readers.push((path.clone(), reader));
+        }
+        Ok(Self { readers })
+    }
+
+    /// Whatever databases are installed in the usual places.
+    fn discover() -> Self {
+        let paths: Vec<PathBuf> = GEOIP_PATHS.iter()
+            .flat_map(|dir| GEOIP_FILES.iter().map(move |f| Path::new(dir).join(f)))
+            .filter(|p| p.is_file())
+            .collect();
+        // A broken file in a system directory shouldn't stop the server
+        Self { readers: paths.iter().filter_map(|p| Some((p.clone(), maxminddb::Reader::open_readfile(p).ok()?))).collect() }
+    }
+
+    /// "City, Country · AS64500 Example Org", from every database that knows the address.
+    fn locate(&self, ip: IpAddr) -> Option<String> {
+        if !is_public_ip(ip) { return None; }
+        let (mut city, mut country, mut asn, mut org) = (None, None, None, None);
+        for (_, reader) in &self.readers {
+            let Ok(rec) = reader.lookup::<GeoRecord>(ip) else { continue };
+            city = city.or_else(|| rec.city.as_ref().and_then(|c| c.name()).map(str::to_string));
+            country = country.or_else(|| rec.country.as_ref().and_then(|c| c.name()).map(str::to_string));
+            asn = asn.or(rec.autonomous_system_number);
+            org = org.or(rec.autonomous_system_organization);
+        }
+        let place = match (city, country) {
+            (Some(c), Some(n)) => Some(format!("{c}, {n}")),
+            (c, n) => c.or(n),
+        };
+        let network = asn.map(|n| match org { Some(o) => format!("AS{n} {o}"), None => format!("AS{n}") });
+        match (place, network) {
+            (Some(p), Some(n)) => Some(format!("{p} · {n}")),
+            (p, n) => p.or(n),
+        }
+    }
 }
 
 // ── Simple JSON array extraction ──────────────────────────────────────
@@ -357,6 +519,8 @@ thead th {
 .dir a { color: var(--accent-light); font-weight: 500; }
 .size,.modified { text-align:right; color:var(--text-dim); width:100px; font-size:13px; }
 .dim { color: var(--text-dim); }
+.link { color: var(--text-dim); font-size: 12px; margin-left: 4px; }
+.link.blocked { color: var(--red); }
 .empty { padding:48px; text-align:center; color:var(--text-dim); font-size:14px; }
 .upload-zone {
     border: 2px dashed var(--border); border-radius: 8px; padding: 24px;
@@ -364,6 +528,8 @@ thead th {
 }
 .upload-zone:hover,.upload-zone.dragover { border-color:var(--accent); background:rgba(124,108,240,0.05); }
 .upload-zone.dragover { background:rgba(124,108,240,0.1); }
+.upload-zone.off { display:none; }
+th.right { text-align:right; }
 .upload-icon { font-size: 24px; margin-bottom: 6px; }
 .upload-text { font-size: 13px; color: var(--text-dim); }
 .upload-text strong { color: var(--accent-light); }
@@ -401,6 +567,26 @@ thead .cb { vertical-align:middle; }
     background:none; border:1px solid var(--border); color:var(--text-dim);
 }
 .sel-btn.sel-clear:hover { border-color:var(--accent); color:var(--text); }
+.hits { margin-top: 18px; }
+.login-card {
+    max-width:340px; margin:64px auto 0; padding:28px;
+    background:var(--surface); border:1px solid var(--border); border-radius:10px;
+    display:flex; flex-direction:column;
+}
+.login-title { font-size:16px; font-weight:600; margin-bottom:18px; }
+.login-error {
+    margin-bottom:14px; padding:9px 12px; border-radius:8px; font-size:13px;
+    color:var(--red); border:1px solid var(--red);
+}
+.login-btn { margin-top:4px; }
+.login-hint { font-size:13px; color:var(--text-dim); margin-bottom:14px; line-height:1.5; }
+.header-right form { display:flex; }
+.hits-title { font-size:11px; font-weight:600; text-transform:uppercase; letter-spacing:0.5px; color:var(--text-dim); padding:8px 12px; border-bottom:1px solid var(--border); }
+.hit { display:block; padding:10px 12px; border-bottom:1px solid var(--border); text-decoration:none; color:var(--text); transition:background 0.1s; }
+.hit:hover { background: var(--hover); }
+.hit-path { font-size:13px; color:var(--accent-light); font-family:'SF Mono','Cascadia Code','JetBrains Mono',monospace; }
+.hit-snippet { font-size:13px; color:var(--text-dim); margin-top:4px; line-height:1.5; word-break:break-word; }
+.hit-snippet mark { background:rgba(124,108,240,0.25); color:var(--text); border-radius:2px; padding:0 1px; }
 @media (max-width:640px) {
     .header{padding:12px 16px} .container{padding:12px 16px 32px}
     .modified{display:none} .header-inner{flex-direction:column;align-items:flex-start;gap:8px}
@@ -408,24 +594,340 @@ thead .cb { vertical-align:middle; }
 }
 "##;
 
+// ── Path resolution ───────────────────────────────────────────────────
+
+/// What to do with symlinks below the root (`--symlinks`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SymlinkPolicy {
+    Follow,  // serve link targets wherever they point
+    Confine, // serve links only if their target stays inside the root
+    Deny,    // refuse any path that passes through a link
+}
+
+impl SymlinkPolicy {
+    fn parse(s: &str) -> Option<SymlinkPolicy> {
+        match s {
+            "follow" => Some(Self::Follow),
+            "confine" => Some(Self::Confine),
+            "deny" => Some(Self::Deny),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self { Self::Follow => "follow", Self::Confine => "confine", Self::Deny => "deny" }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ResolveError {
+    Invalid,     // traversal, NUL bytes and other malformed paths
+    NotFound,
+    OutsideRoot, // symlink leaving the root under `confine`
+    Symlink,     // any symlink under `deny`
+}
+
+impl ResolveError {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::Invalid => StatusCode::BAD_REQUEST,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::OutsideRoot | Self::Symlink => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            Self::Invalid => "Invalid path",
+            Self::NotFound => "Not Found",
+            Self::OutsideRoot => "Symlink points outside the served directory",
+            Self::Symlink => "Symlinks are disabled on this server",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Resolved {
+    path: PathBuf, // root joined with `rel`; what gets opened
+    real: PathBuf, // fully canonicalized
+    rel: String,   // '/'-separated, no empty, `.` or `..` segments
+}
+
+/// Decode a raw request path into a clean root-relative path. Decoding
+/// happens exactly once, so `%252e%252e` stays a literal `%2e%2e` name,
+/// while `..%2f` and NUL bytes are rejected outright.
+fn decode_request_path(raw: &str) -> Result<String, ResolveError> {
+    let decoded = percent_decode(raw);
+    if decoded.contains('\0') { return Err(ResolveError::Invalid); }
+    let mut parts = Vec::new();
+    for seg in decoded.split('/') {
+        match seg {
+            "" | "." => {}
+            ".." => return Err(ResolveError::Invalid),
+            // Backslashes are separators on Windows; refuse them everywhere
+            s if s.contains('\\') || (cfg!(windows) && s.contains(':')) => return Err(ResolveError::Invalid),
+            s => parts.push(s),
+        }
+    }
+    Ok(parts.join("/"))
+}
+
+/// Resolve a clean relative path (see `decode_request_path`) under `root`.
+fn resolve_path(root: &Path, rel: &str, policy: SymlinkPolicy) -> Result<Resolved, ResolveError> {
+    let mut path = root.to_path_buf();
+    for seg in rel.split('/').filter(|s| !s.is_empty()) {
+        path.push(seg);
+        if policy == SymlinkPolicy::Deny {
+            match std::fs::symlink_metadata(&path) {
+                Ok(m) if m.file_type().is_symlink() => return Err(ResolveError::Symlink),
+                Ok(_) => {}
+                Err(_) => return Err(ResolveError::NotFound),
+            }
+        }
+    }
+    let real = path.canonicalize().map_err(|_| ResolveError::NotFound)?;
+    if policy != SymlinkPolicy::Follow && !real.starts_with(root) {
+        return Err(ResolveError::OutsideRoot);
+    }
+    Ok(Resolved { path, real, rel: rel.to_string() })
+}
+
+/// Whether a tree walk (archives, indexing) may descend into or read `path`.
+fn walk_allowed(root: &Path, path: &Path, policy: SymlinkPolicy) -> bool {
+    match policy {
+        SymlinkPolicy::Follow => path.exists(),
+        SymlinkPolicy::Confine => path.canonicalize().map(|c| c.starts_with(root)).unwrap_or(false),
+        SymlinkPolicy::Deny => std::fs::symlink_metadata(path).map(|m| !m.file_type().is_symlink()).unwrap_or(false),
+    }
+}
+
+impl ServerConfig {
+    /// Resolve a raw request path to a mount and a path within it, applying
+    /// both the symlink and the hidden-file policy. `Resolved::rel` is
+    /// relative to the mount's root.
+    fn resolve(&self, raw: &str) -> Result<(&Mount, Resolved), ResolveError> {
+        let rel = decode_request_path(raw)?;
+        let (mount, rel) = self.mount_for(&rel);
+        let r = resolve_path(&mount.root, &rel, self.symlinks)?;
+        // Check both the requested name and where it resolves to, so neither
+        // `/.env` nor a visible symlink pointing at `.git/` gets through
+        if mount.hidden.is_hidden(&r.rel, r.real.is_dir()) || mount.hidden.hides_path(&mount.root, &r.real) {
+            return Err(ResolveError::NotFound);
+        }
+        Ok((mount, r))
+    }
+}
+
+// ── Hidden files ──────────────────────────────────────────────────────
+
+/// Shell-style glob over '/'-separated paths: `*` and `?` stay within one
+/// segment, `**` crosses segments, `[a-z]`/`[!x]` are character classes.
+fn glob_match(pat: &[u8], text: &[u8]) -> bool {
+    match pat.first() {
+        None => text.is_empty(),
+        Some(b'*') if pat.get(1) == Some(&b'*') => {
+            let rest = &pat[2..];
+            match rest.strip_prefix(b"/") {
+                // `**/` matches zero or more whole directories
+                Some(rest) => (0..=text.len())
+                    .any(|i| (i == 0 || text[i - 1] == b'/') && glob_match(rest, &text[i..])),
+                None => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+            }
+        }
+        Some(b'*') => {
+            for i in 0..=text.len() {
+                if glob_match(&pat[1..], &text[i..]) { return true; }
+                if i < text.len() && text[i] == b'/' { break; }
+            }
+            false
+        }
+        Some(b'?') => !text.is_empty() && text[0] != b'/' && glob_match(&pat[1..], &text[1..]),
+        Some(b'[') => {
+            let close = match pat.iter().skip(2).position(|&c| c == b']') {
+                Some(p) => p + 2,
+                None => return text.first() == Some(&b'[') && glob_match(&pat[1..], &text[1..]),
+            };
+            let c = match text.first() { Some(&c) if c != b'/' => c, _ => return false };
+            let (negate, class) = match pat[1] {
+                b'!' | b'^' => (true, &pat[2..close]),
+                _ => (false, &pat[1..close]),
+            };
+            let mut hit = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == b'-' {
+                    hit |= class[i] <= c && c <= class[i + 2];
+                    i += 3;
+                } else {
+                    hit |= class[i] == c;
+                    i += 1;
+                }
+            }
+            hit != negate && glob_match(&pat[close + 1..], &text[1..])
+        }
+        Some(b'\\') if pat.len() > 1 => text.first() == Some(&pat[1]) && glob_match(&pat[2..], &text[1..]),
+        Some(&c) => text.first() == Some(&c) && glob_match(&pat[1..], &text[1..]),
+    }
+}
+
+/// One line of a `.leakignore` / `.gitignore` file.
+struct IgnoreRule {
+    pattern: String,
+    negate: bool,
+    dir_only: bool,
+    anchored: bool, // contains a '/', so it matches from the root rather than any name
+}
+
+fn parse_ignore_file(contents: &str) -> Vec<IgnoreRule> {
+    let mut rules = Vec::new();
+    for line in contents.lines() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        let (negate, line) = match line.strip_prefix('!') { Some(l) => (true, l), None => (false, line) };
+        let line = line.strip_prefix('\\').filter(|l| l.starts_with(['#', '!'])).unwrap_or(line);
+        let (dir_only, line) = match line.strip_suffix('/') { Some(l) => (true, l), None => (false, line) };
+        let anchored = line.contains('/');
+        let pattern = line.trim_start_matches('/').to_string();
+        if pattern.is_empty() { continue; }
+        rules.push(IgnoreRule { pattern, negate, dir_only, anchored });
+    }
+    rules
+}
+
+struct IgnoreSet {
+    rules: Vec<IgnoreRule>,
+    stamps: Vec<Option<SystemTime>>,
+    checked: Instant,
+}
+
+/// Decides which paths are invisible: dotfiles (unless `--show-hidden`) and
+/// anything matched by `.leakignore` (and `.gitignore` with `--gitignore`).
+/// Hidden paths are left out of listings, search and archives, refused for
+/// uploads, and answered with 404 as if they did not exist.
+struct HiddenPolicy {
+    show_hidden: bool,
+    files: Vec<PathBuf>,
+    ignore: RwLock<IgnoreSet>,
+}
+
+impl HiddenPolicy {
+    fn new(root: &Path, show_hidden: bool, use_gitignore: bool) -> HiddenPolicy {
+        let mut files = vec![root.join(".leakignore")];
+        if use_gitignore { files.push(root.join(".gitignore")); }
+        let policy = HiddenPolicy {
+            show_hidden,
+            files,
+            ignore: RwLock::new(IgnoreSet { rules: vec![], stamps: vec![], checked: Instant::now() }),
+        };
+        policy.reload();
+        policy
+    }
+
+    fn stamps(&self) -> Vec<Option<SystemTime>> {
+        self.files.iter().map(|f| std::fs::metadata(f).and_then(|m| m.modified()).ok()).collect()
+    }
+
+    fn reload(&self) {
+        let stamps = self.stamps();
+        let mut rules = Vec::new();
+        for f in &self.files {
+            if let Ok(contents) = std::fs::read_to_string(f) { rules.extend(parse_ignore_file(&contents)); }
+        }
+        *self.ignore.write().unwrap() = IgnoreSet { rules, stamps, checked: Instant::now() };
+    }
+
+    /// Pick up edits to the ignore files, checking the disk at most once a second.
+    fn refresh(&self) {
+        let stale = self.ignore.read().unwrap().checked.elapsed() >= std::time::Duration::from_secs(1);
+        if !stale { return; }
+        let stamps = self.stamps();
+        if stamps != self.ignore.read().unwrap().stamps { self.reload(); }
+        else { self.ignore.write().unwrap().checked = Instant::now(); }
+    }
+
+    fn rule_count(&self) -> usize { self.ignore.read().unwrap().rules.len() }
+
+    /// `rel` is '/'-separated and relative to the root.
+    fn is_hidden(&self, rel: &str, is_dir: bool) -> bool {
+        let parts: Vec<&str> = rel.split('/').filter(|p| !p.is_empty()).collect();
+        // leak.toml can hold credentials
+        if parts == [".leakignore"] || parts == ["leak.toml"] || parts == ["_redirects"] || parts == ["_headers"] { return true; }
+        if !self.show_hidden && parts.iter().any(|p| p.starts_with('.')) { return true; }
+        self.refresh();
+        let set = self.ignore.read().unwrap();
+        if set.rules.is_empty() { return false; }
+        // A path is hidden once any of its ancestors is: like git, an
+        // excluded directory cannot have children re-included.
+        for k in 1..=parts.len() {
+            let sub = parts[..k].join("/");
+            let dir = k < parts.len() || is_dir;
+            let mut hit = false;
+            for r in &set.rules {
+                if r.dir_only && !dir { continue; }
+                let subject = if r.anchored { sub.as_str() } else { parts[k - 1] };
+                if glob_match(r.pattern.as_bytes(), subject.as_bytes()) { hit = !r.negate; }
+            }
+            if hit { return true; }
+        }
+        false
+    }
+
+    /// Like `is_hidden`, for an absolute path under `root`.
+    fn hides_path(&self, root: &Path, path: &Path) -> bool {
+        match path.strip_prefix(root) {
+            Ok(rel) => {
+                let rel: Vec<String> = rel.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+                self.is_hidden(&rel.join("/"), path.is_dir())
+            }
+            Err(_) => false,
+        }
+    }
+}
+
 // ── Directory listing HTML ────────────────────────────────────────────
 
-async fn render_directory(dir_path: &Path, uri_path: &str, root: &Path) -> String {
-    let mut entries: Vec<(String, bool, u64, u64)> = Vec::new();
+async fn render_directory(cfg: &ServerConfig, mount: &Mount, dir_path: &Path, uri_path: &str, session: Option<&SessionInfo>, nonce: &str, allowed: &HashSet<String>) -> String {
+    let root = &mount.root;
+    let content_search = cfg.mounts.iter().any(|m| m.search.is_some());
+    let at_top = uri_path.trim_matches('/').is_empty();
+    // (name, is_dir, size, seconds since modified, badge html)
+    let mut entries: Vec<(String, bool, u64, u64, &str)> = Vec::new();
     let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
 
     if let Ok(mut rd) = fs::read_dir(dir_path).await {
         while let Ok(Some(entry)) = rd.next_entry().await {
             let name = entry.file_name().to_string_lossy().to_string();
-            if name.starts_with('.') { continue; }
-            let meta = entry.metadata().await.ok();
+            let is_link = entry.file_type().await.map(|t| t.is_symlink()).unwrap_or(false);
+            let meta = if is_link { fs::metadata(entry.path()).await.ok() } else { entry.metadata().await.ok() };
             let is_dir = meta.as_ref().map(|m| m.is_dir()).unwrap_or(false);
+            if mount.hidden.hides_path(root, &entry.path()) { continue; }
+            if let Ok(real) = entry.path().canonicalize() {
+                if mount.hidden.hides_path(root, &real) { continue; }
+            }
+            // Mounts shadow real directories of the same name
+            if at_top && cfg.mounts.iter().skip(1).any(|m| m.prefix == name) { continue; }
+            let badge = match is_link.then(|| walk_allowed(root, &entry.path(), cfg.symlinks)) {
+                None => "",
+                Some(true) => r#" <span class="link" title="symlink">↪</span>"#,
+                Some(false) => r#" <span class="link blocked" title="symlink not served by this server">↪ blocked</span>"#,
+            };
             let size = meta.as_ref().map(|m| m.len()).unwrap_or(0);
             let mod_ago = meta.as_ref()
                 .and_then(|m| m.modified().ok())
                 .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
                 .map(|d| now.saturating_sub(d.as_secs())).unwrap_or(0);
-            entries.push((name, is_dir, size, mod_ago));
+            entries.push((name, is_dir, size, mod_ago, badge));
+        }
+    }
+
+    if at_top {
+        // Mounts behind credentials this client hasn't given stay out of sight
+        for m in cfg.mounts.iter().skip(1).filter(|m| allowed.contains(&m.prefix)) {
+            let mod_ago = fs::metadata(&m.root).await.ok()
+                .and_then(|m| m.modified().ok())
+                .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|d| now.saturating_sub(d.as_secs())).unwrap_or(0);
+            entries.push((m.prefix.clone(), true, 0, mod_ago, r#" <span class="link" title="mounted directory">mount</span>"#));
         }
     }
 
@@ -435,17 +937,17 @@ async fn render_directory(dir_path: &Path, uri_path: &str, root: &Path) -> Strin
     let breadcrumbs = build_breadcrumbs(uri_path);
     let mut rows = String::new();
 
-    if dir_path != root {
+    if !at_top {
         let parent = if uri_path.len() > 1 {
             let t = uri_path.trim_end_matches('/');
             match t.rfind('/') { Some(0) => "/".into(), Some(p) => t[..p].into(), None => "/".into() }
         } else { "/".to_string() };
         rows.push_str(&format!(
-            r#"<tr class="entry" data-name=".." onclick="window.location='{parent}'"><td class="cb"></td><td class="icon">📁</td><td class="name"><a href="{parent}">..</a></td><td class="size dim">&mdash;</td><td class="modified dim">&mdash;</td></tr>"#,
+            r#"<tr class="entry" data-name=".." data-href="{parent}"><td class="cb"></td><td class="icon">📁</td><td class="name"><a href="{parent}">..</a></td><td class="size dim">&mdash;</td><td class="modified dim">&mdash;</td></tr>"#,
         ));
     }
 
-    for (name, is_dir, size, mod_ago) in &entries {
+    for (name, is_dir, size, mod_ago, badge) in &entries {
         let href = if uri_path.ends_with('/') { format!("{uri_path}{}", percent_encode(name)) }
                    else { format!("{uri_path}/{}", percent_encode(name)) };
         let href_s = if *is_dir { format!("{href}/") } else { href.clone() };
@@ -456,7 +958,7 @@ async fn render_directory(dir_path: &Path, uri_path: &str, root: &Path) -> Strin
         let esc = html_escape(name);
         let suf = if *is_dir { "/" } else { "" };
         rows.push_str(&format!(
-            r#"<tr class="entry" data-name="{}" data-href="{href_s}" onclick="rowClick(event,this)"><td class="cb"><input type="checkbox" class="sel-cb" data-path="{href_s}" onclick="event.stopPropagation();updateSelection()"></td><td class="icon">{icon}</td><td class="{nc}"><a href="{href_s}">{esc}{suf}</a></td><td class="size">{sz}</td><td class="modified">{mt}</td></tr>"#,
+            r#"<tr class="entry" data-name="{}" data-href="{href_s}"><td class="cb"><input type="checkbox" class="sel-cb" data-path="{href_s}"></td><td class="icon">{icon}</td><td class="{nc}"><a href="{href_s}">{esc}{suf}</a>{badge}</td><td class="size">{sz}</td><td class="modified">{mt}</td></tr>"#,
             html_escape(&name.to_lowercase()),
         ));
     }
@@ -469,12 +971,21 @@ async fn render_directory(dir_path: &Path, uri_path: &str, root: &Path) -> Strin
                         else { format!("{uri_path}/__upload") };
     let download_target = if uri_path.ends_with('/') { format!("{uri_path}__download") }
                           else { format!("{uri_path}/__download") };
+    let search_target = if uri_path.ends_with('/') { format!("{uri_path}__search") }
+                        else { format!("{uri_path}/__search") };
+    let search_hint = if content_search { "Search files and contents..." } else { "Search files..." };
+    let upload_off = if mount.upload { "" } else { " off" };
+    let csrf = session.map(|s| s.csrf.as_str()).unwrap_or("");
+    let logout = match session {
+        Some(s) => format!(r#"<form method="post" action="/__logout"><input type="hidden" name="csrf" value="{}"><button class="theme-btn" type="submit" title="Log out">Log out</button></form>"#, s.csrf),
+        None => String::new(),
+    };
 
     format!(
         r##"<!DOCTYPE html><html lang="en"><head>
 <meta charset="utf-8"><meta name="viewport" content="width=device-width,initial-scale=1">
 <title>leak {display_path}</title>
-<style>{PAGE_CSS}</style>
+<style nonce="{nonce}">{PAGE_CSS}</style>
 </head><body>
 <div class="header"><div class="header-inner">
   <div class="header-left">
@@ -483,10 +994,11 @@ async fn render_directory(dir_path: &Path, uri_path: &str, root: &Path) -> Strin
   </div>
   <div class="header-right">
     <button class="theme-btn" id="themeToggle" title="Toggle theme">◑</button>
+    {logout}
   </div>
 </div></div>
 <div class="container">
-  <div class="upload-zone" id="dropzone">
+  <div class="upload-zone{upload_off}" id="dropzone">
     <input type="file" class="upload-input" id="fileInput" multiple>
     <div class="upload-icon">↑</div>
     <div class="upload-text">Drop files here or <strong>click to browse</strong></div>
@@ -495,15 +1007,16 @@ async fn render_directory(dir_path: &Path, uri_path: &str, root: &Path) -> Strin
       <div class="upload-status" id="uploadStatus"></div>
     </div>
   </div>
-  <input type="text" class="search-bar" id="searchBar" placeholder="Search files..." autocomplete="off">
+  <input type="text" class="search-bar" id="searchBar" placeholder="{search_hint}" autocomplete="off">
   <div class="stats">
     <span>{dc} folder{}</span>
     <span>{fc} file{}</span>
     <span>{}</span>
   </div>
-  <table><thead><tr><th class="cb"><input type="checkbox" id="selectAll" title="Select all"></th><th></th><th>Name</th><th style="text-align:right">Size</th><th style="text-align:right">Modified</th></tr></thead>
+  <table><thead><tr><th class="cb"><input type="checkbox" id="selectAll" title="Select all"></th><th></th><th>Name</th><th class="right">Size</th><th class="right">Modified</th></tr></thead>
   <tbody id="fileList">{rows}</tbody></table>
   <div class="no-results" id="noResults">No files match your search</div>
+  <div class="hits" id="hits"></div>
   {}
 </div>
 <div class="sel-bar" id="selBar">
@@ -511,7 +1024,8 @@ async fn render_directory(dir_path: &Path, uri_path: &str, root: &Path) -> Strin
   <button class="sel-btn" id="selDownload">Download</button>
   <button class="sel-btn sel-clear" id="selClear">Clear</button>
 </div>
-<script>
+<script nonce="{nonce}">
+const CSRF = '{csrf}';
 // Theme toggle
 const html = document.documentElement;
 const saved = localStorage.getItem('leak-theme');
@@ -541,6 +1055,41 @@ searchBar.addEventListener('input', () => {{
   }});
   noResults.style.display = (visible === 0 && q) ? 'block' : 'none';
 }});
+// Content search (only when the server runs with --index)
+const hits = document.getElementById('hits');
+let searchTimer = null;
+searchBar.addEventListener('input', () => {{
+  if (!{content_search}) return;
+  clearTimeout(searchTimer);
+  const q = searchBar.value.trim();
+  if (q.length < 2) {{ hits.replaceChildren(); return; }}
+  searchTimer = setTimeout(async () => {{
+    try {{
+      const r = await fetch('{search_target}?q=' + encodeURIComponent(q));
+      if (!r.ok || searchBar.value.trim() !== q) return;
+      const data = await r.json();
+      hits.replaceChildren();
+      if (!data.results.length) return;
+      const title = document.createElement('div');
+      title.className = 'hits-title';
+      title.textContent = `${{data.total}} document${{data.total!==1?'s':''}} mention this` + (data.indexing ? ' (still indexing)' : '');
+      hits.appendChild(title);
+      for (const hit of data.results) {{
+        const a = document.createElement('a');
+        a.className = 'hit';
+        a.href = hit.href;
+        const path = document.createElement('div');
+        path.className = 'hit-path';
+        path.textContent = hit.path;
+        const snip = document.createElement('div');
+        snip.className = 'hit-snippet';
+        snip.innerHTML = hit.snippet; // escaped server-side, only <mark> is markup
+        a.append(path, snip);
+        hits.appendChild(a);
+      }}
+    }} catch(e) {{}}
+  }}, 200);
+}});
 // Focus search on / key
 document.addEventListener('keydown', (e) => {{
   if (e.key === '/' && document.activeElement !== searchBar) {{
@@ -572,11 +1121,15 @@ function updateSelection() {{
   selectAll.indeterminate = checked.length > 0 && checked.length < cbs.length;
 }}
 
-function rowClick(e, row) {{
-  if (e.target.tagName === 'A' || e.target.tagName === 'INPUT') return;
+// Clicking a row toggles its checkbox; the parent row has none and navigates
+fileList.addEventListener('click', (e) => {{
+  const row = e.target.closest('.entry');
+  if (!row || e.target.tagName === 'A' || e.target.tagName === 'INPUT') return;
   const cb = row.querySelector('.sel-cb');
   if (cb) {{ cb.checked = !cb.checked; updateSelection(); }}
-}}
+  else window.location = row.dataset.href;
+}});
+fileList.addEventListener('change', (e) => {{ if (e.target.classList.contains('sel-cb')) updateSelection(); }});
 
 selectAll.addEventListener('change', () => {{
   const state = selectAll.checked;
@@ -598,7 +1151,7 @@ selDownload.addEventListener('click', async () => {{
   try {{
     const r = await fetch('{download_target}', {{
       method: 'POST',
-      headers: {{'Content-Type': 'application/json'}},
+      headers: {{'Content-Type': 'application/json', 'X-CSRF-Token': CSRF}},
       body: JSON.stringify({{files:paths}})
     }});
     if (!r.ok) {{ alert('Download failed: ' + await r.text()); return; }}
@@ -636,7 +1189,7 @@ async function uploadFiles(files) {{
     const fd = new FormData();
     fd.append('file', file);
     try {{
-      const r = await fetch('{upload_target}', {{ method:'POST', body:fd }});
+      const r = await fetch('{upload_target}', {{ method:'POST', body:fd, headers:{{'X-CSRF-Token': CSRF}} }});
       if (!r.ok) {{ status.textContent = `Failed: ${{await r.text()}}`; status.className='upload-status error'; return; }}
       done++;
       bar.style.width = `${{(done/total)*100}}%`;
@@ -674,6 +1227,8 @@ fn add_path_to_zip(
     fs_path: &Path,
     archive_name: &str,
     root: &Path,
+    hidden: &HiddenPolicy,
+    symlinks: SymlinkPolicy,
     opts: SimpleFileOptions,
 ) -> std::io::Result<()> {
     if fs_path.is_file() {
@@ -682,13 +1237,15 @@ fn add_path_to_zip(
         std::io::Write::write_all(zip, &data)?;
     } else if fs_path.is_dir() {
         let mut stack: Vec<(PathBuf, String)> = vec![(fs_path.to_path_buf(), archive_name.to_string())];
+        let mut visited: HashSet<PathBuf> = HashSet::new();
         while let Some((dir, prefix)) = stack.pop() {
+            // Followed symlinks can form loops
+            if !dir.canonicalize().map(|c| visited.insert(c)).unwrap_or(false) { continue; }
             if let Ok(rd) = std::fs::read_dir(&dir) {
                 for entry in rd.flatten() {
                     let path = entry.path();
-                    if !path.starts_with(root) { continue; }
+                    if !walk_allowed(root, &path, symlinks) || hidden.hides_path(root, &path) { continue; }
                     let name = entry.file_name().to_string_lossy().to_string();
-                    if name.starts_with('.') { continue; }
                     let arc_name = if prefix.is_empty() { name.clone() } else { format!("{prefix}/{name}") };
                     if path.is_file() {
                         zip.start_file(&arc_name, opts)?;
@@ -704,421 +1261,4813 @@ fn add_path_to_zip(
     Ok(())
 }
 
-// ── Multipart parsing ─────────────────────────────────────────────────
+// ── Content index ─────────────────────────────────────────────────────
 
-struct UploadedFile { filename: String, data: Vec<u8> }
+const INDEX_MAGIC: &[u8] = b"LEAKIDX1\n";
+const INDEX_MAX_FILE: u64 = 32 * 1024 * 1024;
+const INDEX_MAX_TEXT: usize = 2 * 1024 * 1024;
 
-fn parse_multipart(body: &[u8], boundary: &str) -> Vec<UploadedFile> {
-    let mut files = Vec::new();
-    let delim = format!("--{boundary}").into_bytes();
-    let mut starts: Vec<usize> = Vec::new();
-    let mut i = 0;
-    while i + delim.len() <= body.len() {
-        if &body[i..i + delim.len()] == delim.as_slice() {
-            starts.push(i + delim.len()); i += delim.len();
-        } else { i += 1; }
-    }
-    for (idx, &start) in starts.iter().enumerate() {
-        let end = if idx + 1 < starts.len() { starts[idx + 1] - delim.len() } else { body.len() };
-        if start >= end { continue; }
-        let part = &body[start..end];
-        let part = if part.starts_with(b"\r\n") { &part[2..] } else { part };
-        if part.starts_with(b"--") { continue; }
-        let sep = match part.windows(4).position(|w| w == b"\r\n\r\n") { Some(p) => p, None => continue };
-        let headers = String::from_utf8_lossy(&part[..sep]);
-        let data = &part[sep + 4..];
-        let data = if data.ends_with(b"\r\n") { &data[..data.len() - 2] } else { data };
-        if let Some(filename) = extract_filename(&headers) {
-            if !filename.is_empty() { files.push(UploadedFile { filename, data: data.to_vec() }); }
+const TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "md", "markdown", "rst", "adoc", "org", "tex", "log", "csv", "tsv",
+    "json", "toml", "yaml", "yml", "ini", "cfg", "conf", "env", "xml", "html", "htm",
+    "css", "scss", "js", "mjs", "cjs", "ts", "tsx", "jsx", "vue", "svelte",
+    "rs", "py", "go", "rb", "php", "pl", "lua", "sh", "bash", "zsh", "fish", "ps1",
+    "c", "h", "cc", "cpp", "hpp", "cs", "java", "kt", "kts", "scala", "swift", "m",
+    "ex", "exs", "erl", "hs", "ml", "clj", "dart", "r", "jl", "zig", "nim", "sql",
+    "proto", "graphql", "gradle", "cmake", "mk", "dockerfile",
+];
+
+struct IndexedDoc {
+    path: String, // relative to root, '/'-separated
+    mtime: u64,
+    size: u64,
+    terms: u32,
+    text: String,
+}
+
+#[derive(Default)]
+struct ContentIndex {
+    docs: Vec<Option<IndexedDoc>>,
+    free: Vec<usize>,
+    by_path: HashMap<String, usize>,
+    postings: HashMap<String, HashMap<usize, u32>>,
+    total_terms: u64,
+}
+
+struct SearchHit { path: String, score: f64, snippet: String }
+
+/// Byte ranges of the searchable words in `text`.
+fn token_spans(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        if c.is_alphanumeric() {
+            if start.is_none() { start = Some(i); }
+        } else if let Some(s) = start.take() {
+            spans.push((s, i));
         }
     }
-    files
+    if let Some(s) = start { spans.push((s, text.len())); }
+    spans.retain(|&(s, e)| (2..=64).contains(&(e - s)));
+    spans
 }
 
-fn extract_filename(headers: &str) -> Option<String> {
-    for line in headers.lines() {
-        if line.to_lowercase().contains("content-disposition") {
-            if let Some(pos) = line.find("filename=\"") {
-                let start = pos + 10;
-                if let Some(end) = line[start..].find('"') {
-                    let name = &line[start..start + end];
-                    return Some(name.rsplit(['/', '\\']).next().unwrap_or(name).to_string());
+impl ContentIndex {
+    fn len(&self) -> usize { self.by_path.len() }
+
+    fn is_fresh(&self, path: &str, mtime: u64, size: u64) -> bool {
+        self.by_path.get(path)
+            .and_then(|&id| self.docs[id].as_ref())
+            .map(|d| d.mtime == mtime && d.size == size)
+            .unwrap_or(false)
+    }
+
+    fn insert(&mut self, mut doc: IndexedDoc) {
+        self.remove(&doc.path);
+        let mut tf: HashMap<String, u32> = HashMap::new();
+        let spans = token_spans(&doc.text);
+        for &(s, e) in &spans {
+            *tf.entry(doc.text[s..e].to_lowercase()).or_insert(0) += 1;
+        }
+        doc.terms = spans.len() as u32;
+        let id = match self.free.pop() { Some(id) => id, None => { self.docs.push(None); self.docs.len() - 1 } };
+        for (term, n) in tf { self.postings.entry(term).or_default().insert(id, n); }
+        self.total_terms += doc.terms as u64;
+        self.by_path.insert(doc.path.clone(), id);
+        self.docs[id] = Some(doc);
+    }
+
+    fn remove(&mut self, path: &str) {
+        let id = match self.by_path.remove(path) { Some(id) => id, None => return };
+        if let Some(doc) = self.docs[id].take() {
+            let terms: HashSet<String> = token_spans(&doc.text).into_iter()
+                .map(|(s, e)| doc.text[s..e].to_lowercase()).collect();
+            for term in terms {
+                if let Some(p) = self.postings.get_mut(&term) {
+                    p.remove(&id);
+                    if p.is_empty() { self.postings.remove(&term); }
                 }
             }
+            self.total_terms -= doc.terms as u64;
+            self.free.push(id);
         }
     }
-    None
-}
 
-fn get_boundary(req: &Request<Incoming>) -> Option<String> {
-    let ct = req.headers().get("content-type")?.to_str().ok()?;
-    if !ct.contains("multipart/form-data") { return None; }
-    Some(ct.split("boundary=").nth(1)?.trim().trim_matches('"').to_string())
-}
+    /// Remove `path` and, if it was a directory, everything below it.
+    fn remove_tree(&mut self, path: &str) {
+        let prefix = format!("{path}/");
+        let gone: Vec<String> = self.by_path.keys()
+            .filter(|p| p.as_str() == path || p.starts_with(&prefix)).cloned().collect();
+        for p in gone { self.remove(&p); }
+    }
 
-// ── Auth ──────────────────────────────────────────────────────────────
+    /// BM25-ranked search over documents below `scope` (a root-relative directory).
+    fn search(&self, query: &str, scope: &str, limit: usize, visible: impl Fn(&str) -> bool) -> (Vec<SearchHit>, usize) {
+        const K1: f64 = 1.2;
+        const BM25_B: f64 = 0.75;
+        let mut terms: Vec<String> = token_spans(query).into_iter()
+            .map(|(s, e)| query[s..e].to_lowercase()).collect();
+        terms.sort();
+        terms.dedup();
+        if terms.is_empty() || self.by_path.is_empty() { return (vec![], 0); }
 
-fn check_auth(req: &Request<Incoming>, expected: &str) -> bool {
-    if let Some(auth) = req.headers().get("authorization") {
-        if let Ok(val) = auth.to_str() {
-            if val.starts_with("Basic ") {
-                return &val[6..] == expected;
+        let n = self.by_path.len() as f64;
+        let avgdl = (self.total_terms as f64 / n).max(1.0);
+        let prefix = if scope.is_empty() { String::new() } else { format!("{scope}/") };
+        let mut scores: HashMap<usize, f64> = HashMap::new();
+        for term in &terms {
+            let post = match self.postings.get(term) { Some(p) => p, None => continue };
+            let df = post.len() as f64;
+            let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+            for (&id, &tf) in post {
+                let doc = match &self.docs[id] { Some(d) => d, None => continue };
+                if !doc.path.starts_with(&prefix) || !visible(&doc.path) { continue; }
+                let tf = tf as f64;
+                let norm = K1 * (1.0 - BM25_B + BM25_B * doc.terms as f64 / avgdl);
+                let mut s = idf * tf * (K1 + 1.0) / (tf + norm);
+                // Matches in the file name count for more than matches in the body
+                if doc.path.rsplit('/').next().unwrap_or("").to_lowercase().contains(term.as_str()) { s += idf; }
+                *scores.entry(id).or_insert(0.0) += s;
             }
         }
+
+        let total = scores.len();
+        let mut ranked: Vec<(usize, f64)> = scores.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let hits = ranked.into_iter().take(limit).filter_map(|(id, score)| {
+            let doc = self.docs[id].as_ref()?;
+            Some(SearchHit { path: doc.path.clone(), score, snippet: highlight_snippet(&doc.text, &terms) })
+        }).collect();
+        (hits, total)
     }
-    false
 }
 
-fn auth_required_response() -> Response<Full<Bytes>> {
-    Response::builder()
-        .status(StatusCode::UNAUTHORIZED)
-        .header("WWW-Authenticate", "Basic realm=\"leak\"")
-        .header("Content-Type", "text/plain")
-        .body(Full::new(Bytes::from("Authentication required")))
-        .unwrap()
-}
+/// A short HTML excerpt around the first query match, with matches wrapped in `<mark>`.
+fn highlight_snippet(text: &str, terms: &[String]) -> String {
+    let spans = token_spans(text);
+    let is_hit = |&(s, e): &(usize, usize)| terms.iter().any(|t| text[s..e].to_lowercase() == *t);
+    let center = spans.iter().find(|sp| is_hit(sp)).map(|sp| sp.0).unwrap_or(0);
+    let mut start = center.saturating_sub(60);
+    while !text.is_char_boundary(start) { start -= 1; }
+    let mut end = (center + 180).min(text.len());
+    while !text.is_char_boundary(end) { end += 1; }
 
-// ── HTTP core ─────────────────────────────────────────────────────────
+    let squash = |s: &str| {
+        let mut r = String::with_capacity(s.len());
+        for c in s.chars() {
+            if !c.is_whitespace() { r.push(c); } else if !r.ends_with(' ') { r.push(' '); }
+        }
+        html_escape(&r)
+    };
+    let mut html = String::new();
+    if start > 0 { html.push('…'); }
+    let mut cur = start;
+    for sp in spans.iter().filter(|&&(s, e)| s >= start && e <= end) {
+        if !is_hit(sp) { continue; }
+        html.push_str(&squash(&text[cur..sp.0]));
+        html.push_str("<mark>");
+        html.push_str(&html_escape(&text[sp.0..sp.1]));
+        html.push_str("</mark>");
+        cur = sp.1;
+    }
+    html.push_str(&squash(&text[cur..end]));
+    if end < text.len() { html.push('…'); }
+    html.trim().to_string()
+}
 
-fn http_response(status: StatusCode, body: impl Into<Bytes>, ctype: &str) -> Response<Full<Bytes>> {
-    Response::builder()
-        .status(status)
-        .header("Content-Type", ctype)
-        .header("Access-Control-Allow-Origin", "*")
-        .body(Full::new(body.into()))
-        .unwrap()
+fn indexable(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => { let ext = ext.to_ascii_lowercase(); ext == "pdf" || TEXT_EXTENSIONS.contains(&ext.as_str()) }
+        None => true, // README, Makefile, LICENSE, ...
+    }
 }
 
-async fn serve(cfg: Arc<ServerConfig>, req: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
-    // Auth check
-    if let Some(ref expected) = cfg.auth {
-        if !check_auth(&req, expected) {
-            return Ok(auth_required_response());
+fn extract_text(path: &Path) -> Option<String> {
+    let data = std::fs::read(path).ok()?;
+    let ext = path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+    let mut text = match ext.as_deref() {
+        Some("pdf") => extract_pdf_text(&data),
+        _ => {
+            if data[..data.len().min(8192)].contains(&0) { return None; }
+            let text = String::from_utf8_lossy(&data).into_owned();
+            if matches!(ext.as_deref(), Some("html" | "htm")) { strip_tags(&text) } else { text }
         }
+    };
+    if text.len() > INDEX_MAX_TEXT {
+        let mut cut = INDEX_MAX_TEXT;
+        while !text.is_char_boundary(cut) { cut -= 1; }
+        text.truncate(cut);
     }
+    Some(text)
+}
 
-    let uri_path = req.uri().path().to_string();
-    let method = req.method().clone();
-    let root = &cfg.root;
-
-    // Upload handler
-    if method == Method::POST && uri_path.ends_with("/__upload") {
-        let dir_uri = uri_path.trim_end_matches("/__upload");
-        let dir_uri = if dir_uri.is_empty() { "/" } else { dir_uri };
-        let clean = dir_uri.trim_start_matches('/');
-        let decoded = percent_decode(clean);
-        let dir_path = root.join(&decoded);
-
-        let canonical = match dir_path.canonicalize() {
-            Ok(c) if c.starts_with(root) => c,
-            _ if decoded.is_empty() => root.clone(),
-            _ => return Ok(http_response(StatusCode::BAD_REQUEST, "Invalid path", "text/plain")),
-        };
-        if !canonical.is_dir() {
-            return Ok(http_response(StatusCode::BAD_REQUEST, "Not a directory", "text/plain"));
+fn strip_tags(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => { in_tag = false; out.push(' '); }
+            _ if !in_tag => out.push(c),
+            _ => {}
         }
+    }
+    out
+}
 
-        let boundary = match get_boundary(&req) {
-            Some(b) => b,
-            None => return Ok(http_response(StatusCode::BAD_REQUEST, "Missing boundary", "text/plain")),
-        };
-
-        let upload_start = Instant::now();
-        let body_bytes = match req.collect().await {
-            Ok(c) => c.to_bytes(),
-            Err(_) => return Ok(http_response(StatusCode::BAD_REQUEST, "Read failed", "text/plain")),
+/// Best-effort text extraction: inflates content streams and collects the
+/// strings shown by the `Tj`/`TJ`/`'`/`"` operators. Fonts with custom
+/// encodings come out as noise, which simply never matches a query.
+fn extract_pdf_text(data: &[u8]) -> String {
+    let find = |hay: &[u8], needle: &[u8], from: usize| -> Option<usize> {
+        hay.get(from..)?.windows(needle.len()).position(|w| w == needle).map(|p| p + from)
+    };
+    let mut out = String::new();
+    let mut pos = 0;
+    while let Some(kw) = find(data, b"stream", pos) {
+        pos = kw + 6;
+        if kw >= 3 && &data[kw - 3..kw] == b"end" { continue; }
+        let dict = &data[kw.saturating_sub(512)..kw];
+        let dict = match dict.windows(3).rposition(|w| w == b"obj") { Some(p) => &dict[p..], None => dict };
+        let mut start = pos;
+        if data.get(start) == Some(&b'\r') { start += 1; }
+        if data.get(start) == Some(&b'\n') { start += 1; }
+        let end = match find(data, b"endstream", start) { Some(e) => e, None => break };
+        pos = end + 9;
+        let raw = &data[start..end];
+        let has = |k: &[u8]| dict.windows(k.len()).any(|w| w == k);
+        let content = if has(b"/FlateDecode") {
+            let mut buf = Vec::new();
+            let mut dec = flate2::read::ZlibDecoder::new(raw);
+            if std::io::Read::read_to_end(&mut dec, &mut buf).is_err() && buf.is_empty() { continue; }
+            buf
+        } else if has(b"/Filter") {
+            continue;
+        } else {
+            raw.to_vec()
         };
-        if body_bytes.len() > 500 * 1024 * 1024 {
-            return Ok(http_response(StatusCode::PAYLOAD_TOO_LARGE, "500MB max", "text/plain"));
-        }
-
-        let files = parse_multipart(&body_bytes, &boundary);
-        if files.is_empty() {
-            return Ok(http_response(StatusCode::BAD_REQUEST, "No file in upload", "text/plain"));
-        }
-
-        let elapsed_ms = upload_start.elapsed().as_millis() as u64;
+        pdf_content_text(&content, &mut out);
+        if out.len() > INDEX_MAX_TEXT { break; }
+    }
+    out
+}
+
+fn pdf_content_text(content: &[u8], out: &mut String) {
+    let mut pending: Vec<Vec<u8>> = Vec::new();
+    let mut i = 0;
+    while i < content.len() {
+        match content[i] {
+            b'(' => {
+                let mut s = Vec::new();
+                let mut depth = 1;
+                i += 1;
+                while i < content.len() {
+                    let c = content[i];
+                    match c {
+                        b'\\' if i + 1 < content.len() => {
+                            i += 1;
+                            match content[i] {
+                                b'n' => s.push(b'\n'),
+                                b'r' | b't' => s.push(b' '),
+                                d @ b'0'..=b'7' => {
+                                    let mut v = (d - b'0') as u32;
+                                    for _ in 0..2 {
+                                        match content.get(i + 1) {
+                                            Some(&d @ b'0'..=b'7') => { v = v * 8 + (d - b'0') as u32; i += 1; }
+                                            _ => break,
+                                        }
+                                    }
+                                    s.push(v as u8);
+                                }
+                                b'\r' | b'\n' => {}
+                                other => s.push(other),
+                            }
+                        }
+                        b'(' => { depth += 1; s.push(c); }
+                        b')' => { depth -= 1; if depth == 0 { break; } s.push(c); }
+                        _ => s.push(c),
+                    }
+                    i += 1;
+                }
+                pending.push(s);
+            }
+            b'<' if content.get(i + 1) != Some(&b'<') => {
+                let end = content[i..].iter().position(|&c| c == b'>').map(|p| p + i).unwrap_or(content.len());
+                let hex: Vec<u8> = content[i + 1..end].iter().copied().filter(u8::is_ascii_hexdigit).collect();
+                pending.push(hex.chunks(2).filter_map(|h| u8::from_str_radix(std::str::from_utf8(h).ok()?, 16).ok()).collect());
+                i = end;
+            }
+            c if c.is_ascii_alphabetic() || c == b'\'' || c == b'"' || c == b'*' => {
+                let start = i;
+                while i + 1 < content.len() && (content[i + 1].is_ascii_alphabetic() || content[i + 1] == b'*') { i += 1; }
+                match &content[start..=i] {
+                    b"Tj" | b"TJ" | b"'" | b"\"" => {
+                        for s in pending.drain(..) { out.push_str(&pdf_string(&s)); }
+                    }
+                    b"T*" | b"Td" | b"TD" | b"ET" => { out.push('\n'); pending.clear(); }
+                    _ => pending.clear(),
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+}
+
+fn pdf_string(bytes: &[u8]) -> String {
+    if bytes.starts_with(&[0xFE, 0xFF]) {
+        let units: Vec<u16> = bytes[2..].chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+        return String::from_utf16_lossy(&units);
+    }
+    // PDFDocEncoding is close enough to Latin-1 for search purposes
+    bytes.iter().map(|&b| if b.is_ascii_control() && b != b'\n' { ' ' } else { b as char }).collect()
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |h, &b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+fn default_cache_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CACHE_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))
+        .map(|d| d.join("leak"))
+}
+
+fn save_index(idx: &ContentIndex, file: &Path) -> std::io::Result<()> {
+    use std::io::Write;
+    if let Some(dir) = file.parent() { std::fs::create_dir_all(dir)?; }
+    let tmp = file.with_extension("tmp");
+    // The cache holds the text of every indexed file
+    let mut opts = std::fs::OpenOptions::new();
+    opts.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut opts, 0o600);
+    let mut w = std::io::BufWriter::new(opts.open(&tmp)?);
+    w.write_all(INDEX_MAGIC)?;
+    for doc in idx.docs.iter().flatten() {
+        writeln!(w, "{} {} {} {}", doc.path.len(), doc.mtime, doc.size, doc.text.len())?;
+        w.write_all(doc.path.as_bytes())?;
+        w.write_all(doc.text.as_bytes())?;
+    }
+    w.flush()?;
+    drop(w);
+    std::fs::rename(&tmp, file)
+}
+
+/// `None` for a missing, foreign or damaged cache, which is then rebuilt.
+fn load_index(file: &Path) -> Option<ContentIndex> {
+    let data = std::fs::read(file).ok()?;
+    let mut rest = data.strip_prefix(INDEX_MAGIC)?;
+    let mut idx = ContentIndex::default();
+    while !rest.is_empty() {
+        let nl = rest.iter().position(|&c| c == b'\n')?;
+        let head = std::str::from_utf8(&rest[..nl]).ok()?;
+        let nums: Vec<u64> = head.split(' ').map(|n| n.parse().ok()).collect::<Option<_>>()?;
+        if nums.len() != 4 { return None; }
+        let (plen, tlen) = (nums[0] as usize, nums[3] as usize);
+        let end = plen.checked_add(tlen).and_then(|n| n.checked_add(nl + 1)).filter(|&end| end <= rest.len())?;
+        let body = &rest[nl + 1..end];
+        let path = String::from_utf8(body[..plen].to_vec()).ok()?;
+        let text = String::from_utf8(body[plen..].to_vec()).ok()?;
+        idx.insert(IndexedDoc { path, mtime: nums[1], size: nums[2], terms: 0, text });
+        rest = &rest[end..];
+    }
+    Some(idx)
+}
+
+/// The shared, live-updated index behind `--index`.
+struct ContentSearch {
+    root: PathBuf,
+    symlinks: SymlinkPolicy,
+    hidden: Arc<HiddenPolicy>,
+    index: RwLock<ContentIndex>,
+    cache_file: Option<PathBuf>,
+    ready: AtomicBool,
+    dirty: AtomicBool,
+}
+
+impl ContentSearch {
+    /// Bring the entry for one file up to date. Returns true if anything changed.
+    fn refresh_file(&self, path: &Path, rel: String) -> bool {
+        let meta = match std::fs::metadata(path) { Ok(m) if m.is_file() => m, _ => return false };
+        let mtime = meta.modified().ok()
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs()).unwrap_or(0);
+        if self.index.read().unwrap().is_fresh(&rel, mtime, meta.len()) { return false; }
+        // Unreadable and binary files are kept with empty text so they are not re-read on every sync
+        let text = if meta.len() <= INDEX_MAX_FILE { extract_text(path).unwrap_or_default() } else { String::new() };
+        self.index.write().unwrap().insert(IndexedDoc { path: rel, mtime, size: meta.len(), terms: 0, text });
+        true
+    }
+
+    /// Index every candidate file under `dir`, returning the relative paths seen.
+    fn sync_dir(&self, dir: &Path) -> (HashSet<String>, usize) {
+        let mut seen = HashSet::new();
+        let mut changed = 0;
+        let mut visited: HashSet<PathBuf> = HashSet::new();
+        let mut stack = vec![dir.to_path_buf()];
+        while let Some(dir) = stack.pop() {
+            let real = match dir.canonicalize() { Ok(c) => c, _ => continue };
+            if !visited.insert(real) { continue; }
+            let rd = match std::fs::read_dir(&dir) { Ok(rd) => rd, Err(_) => continue };
+            for entry in rd.flatten() {
+                let path = entry.path();
+                if self.hidden.hides_path(&self.root, &path) || !walk_allowed(&self.root, &path, self.symlinks) { continue; }
+                if path.is_dir() { stack.push(path); continue; }
+                if !indexable(&path) { continue; }
+                let rel = match index_rel_path(&self.root, &path) { Some(r) => r, None => continue };
+                seen.insert(rel.clone());
+                if self.refresh_file(&path, rel) { changed += 1; }
+            }
+        }
+        (seen, changed)
+    }
+
+    fn sync_all(&self) -> usize {
+        let (seen, mut changed) = self.sync_dir(&self.root);
+        let gone: Vec<String> = self.index.read().unwrap().by_path.keys()
+            .filter(|p| !seen.contains(*p)).cloned().collect();
+        changed += gone.len();
+        let mut idx = self.index.write().unwrap();
+        for p in gone { idx.remove(&p); }
+        changed
+    }
+
+    /// Apply a batch of paths reported by the file watcher.
+    fn apply_changes(&self, paths: HashSet<PathBuf>) -> usize {
+        let mut changed = 0;
+        for path in paths {
+            let rel = match index_rel_path(&self.root, &path) { Some(r) => r, None => continue };
+            if self.hidden.is_hidden(&rel, path.is_dir()) { continue; }
+            if path.is_dir() {
+                changed += self.sync_dir(&path).1;
+            } else if path.is_file() {
+                if indexable(&path) && self.refresh_file(&path, rel) { changed += 1; }
+            } else {
+                let mut idx = self.index.write().unwrap();
+                let before = idx.len();
+                idx.remove_tree(&rel);
+                changed += before - idx.len();
+            }
+        }
+        changed
+    }
+
+    fn save(&self) {
+        if let Some(ref file) = self.cache_file {
+            if self.dirty.swap(false, Ordering::Relaxed) {
+                if let Err(e) = save_index(&self.index.read().unwrap(), file) {
+                    eprintln!("  {RD}index save failed:{RST} {e}");
+                }
+            }
+        }
+    }
+}
+
+fn index_rel_path(root: &Path, path: &Path) -> Option<String> {
+    let rel = path.strip_prefix(root).ok()?;
+    let parts: Vec<String> = rel.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+    if parts.is_empty() { None } else { Some(parts.join("/")) }
+}
+
+/// Load the cached index, then reconcile it with the disk and keep it current.
+fn start_content_index(
+    root: PathBuf,
+    symlinks: SymlinkPolicy,
+    hidden: Arc<HiddenPolicy>,
+    cache_dir: Option<PathBuf>,
+) -> Arc<ContentSearch> {
+    use notify::Watcher;
+
+    let cache_file = cache_dir.map(|d| d.join(format!("index-{:016x}.bin", fnv1a(root.to_string_lossy().as_bytes()))));
+    let index = cache_file.as_deref().and_then(load_index).unwrap_or_default();
+    let search = Arc::new(ContentSearch {
+        root: root.clone(),
+        symlinks,
+        hidden,
+        index: RwLock::new(index),
+        cache_file,
+        ready: AtomicBool::new(false),
+        dirty: AtomicBool::new(false),
+    });
+
+    let s = search.clone();
+    tokio::spawn(async move {
+        let start = Instant::now();
+        let s2 = s.clone();
+        let changed = tokio::task::spawn_blocking(move || s2.sync_all()).await.unwrap_or(0);
+        s.ready.store(true, Ordering::Relaxed);
+        if changed > 0 { s.dirty.store(true, Ordering::Relaxed); }
+        let s2 = s.clone();
+        let _ = tokio::task::spawn_blocking(move || s2.save()).await;
+        let docs = s.index.read().unwrap().len();
+        eprintln!(
+            "  {} {MG}{B}INDEX{RST} {CY}{docs} files{RST} {D}({changed} updated in {} ms){RST}",
+            ts(), start.elapsed().as_millis(),
+        );
+    });
+
+    // File watcher: batch events for a moment, then re-index what they touched
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<PathBuf>();
+    let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(ev) = res { for p in ev.paths { let _ = tx.send(p); } }
+    });
+    match watcher {
+        Ok(mut w) => {
+            if let Err(e) = w.watch(&root, notify::RecursiveMode::Recursive) {
+                eprintln!("  {YL}index watcher unavailable:{RST} {e}");
+            }
+            let s = search.clone();
+            tokio::spawn(async move {
+                let _watcher = w;
+                while let Some(first) = rx.recv().await {
+                    let mut batch = HashSet::from([first]);
+                    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+                    while let Ok(p) = rx.try_recv() { batch.insert(p); }
+                    let s2 = s.clone();
+                    let changed = tokio::task::spawn_blocking(move || s2.apply_changes(batch)).await.unwrap_or(0);
+                    if changed > 0 { s.dirty.store(true, Ordering::Relaxed); }
+                }
+            });
+        }
+        Err(e) => eprintln!("  {YL}index watcher unavailable:{RST} {e}"),
+    }
+
+    // Persist changes periodically so a crash loses little work
+    let s = search.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+            let s2 = s.clone();
+            let _ = tokio::task::spawn_blocking(move || s2.save()).await;
+        }
+    });
+
+    search
+}
+
+fn json_escape(input: &str) -> String {
+    let mut r = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            '\n' => r.push_str("\\n"),
+            '\r' => r.push_str("\\r"),
+            '\t' => r.push_str("\\t"),
+            c if (c as u32) < 0x20 => r.push_str(&format!("\\u{:04x}", c as u32)),
+            c => r.push(c),
+        }
+    }
+    r
+}
+
+fn query_param(query: &str, key: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+        if k == key { Some(percent_decode(&v.replace('+', " "))) } else { None }
+    })
+}
+
+// ── Multipart parsing ─────────────────────────────────────────────────
+
+struct UploadedFile { filename: String, data: Vec<u8> }
+
+fn parse_multipart(body: &[u8], boundary: &str) -> Vec<UploadedFile> {
+    let mut files = Vec::new();
+    let delim = format!("--{boundary}").into_bytes();
+    let mut starts: Vec<usize> = Vec::new();
+    let mut i = 0;
+    while i + delim.len() <= body.len() {
+        if &body[i..i + delim.len()] == delim.as_slice() {
+            starts.push(i + delim.len()); i += delim.len();
+        } else { i += 1; }
+    }
+    for (idx, &start) in starts.iter().enumerate() {
+        let end = if idx + 1 < starts.len() { starts[idx + 1] - delim.len() } else { body.len() };
+        if start >= end { continue; }
+        let part = &body[start..end];
+        let part = if part.starts_with(b"\r\n") { &part[2..] } else { part };
+        if part.starts_with(b"--") { continue; }
+        let sep = match part.windows(4).position(|w| w == b"\r\n\r\n") { Some(p) => p, None => continue };
+        let headers = String::from_utf8_lossy(&part[..sep]);
+        let data = &part[sep + 4..];
+        let data = if data.ends_with(b"\r\n") { &data[..data.len() - 2] } else { data };
+        if let Some(filename) = extract_filename(&headers) {
+            if !filename.is_empty() { files.push(UploadedFile { filename, data: data.to_vec() }); }
+        }
+    }
+    files
+}
+
+fn extract_filename(headers: &str) -> Option<String> {
+    for line in headers.lines() {
+        if line.to_lowercase().contains("content-disposition") {
+            if let Some(pos) = line.find("filename=\"") {
+                let start = pos + 10;
+                if let Some(end) = line[start..].find('"') {
+                    let name = &line[start..start + end];
+                    return Some(name.rsplit(['/', '\\']).next().unwrap_or(name).to_string());
+                }
+            }
+        }
+    }
+    None
+}
+
+fn get_boundary<B>(req: &Request<B>) -> Option<String> {
+    let ct = req.headers().get("content-type")?.to_str().ok()?;
+    if !ct.contains("multipart/form-data") { return None; }
+    Some(ct.split("boundary=").nth(1)?.trim().trim_matches('"').to_string())
+}
+
+// ── Auth ──────────────────────────────────────────────────────────────
+
+fn check_auth<B>(req: &Request<B>, expected: &str) -> bool {
+    if let Some(auth) = req.headers().get("authorization") {
+        if let Ok(val) = auth.to_str() {
+            if let Some(given) = val.strip_prefix("Basic ") {
+                return ct_eq(given.as_bytes(), expected.as_bytes());
+            }
+        }
+    }
+    false
+}
+
+/// The credentials to serve with, and whether they were made up here.
+/// Going public always takes a password unless `--no-auth` says otherwise,
+/// and going public without one needs saying so twice.
+fn public_auth(public: bool, auth: Option<(String, String)>, no_auth: bool, i_know: bool) -> Result<(Option<(String, String)>, bool), String> {
+    if public && no_auth && !i_know {
+        return Err("--public --no-auth puts this directory on the internet for anyone with the URL; add --i-know to confirm".to_string());
+    }
+    Ok(match auth {
+        _ if no_auth => (None, false),
+        None if public => (Some(("leak".to_string(), generate_passphrase())), true),
+        auth => (auth, false),
+    })
+}
+
+/// Letters and digits that can't be misread for each other.
+const PASSPHRASE_CHARS: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Four dash-separated groups of four, about 79 bits.
+fn generate_passphrase() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes).expect("no system randomness");
+    let chars: Vec<char> = bytes.iter().map(|b| PASSPHRASE_CHARS[*b as usize % PASSPHRASE_CHARS.len()] as char).collect();
+    chars.chunks(4).map(|c| c.iter().collect::<String>()).collect::<Vec<_>>().join("-")
+}
+
+/// `http://host/` → `http://host/__login?key=…`, so scanning a QR code logs
+/// in. Browsers only send `user:pass@` URLs in answer to a Basic challenge,
+/// which the login page doesn't make.
+fn login_link(url: &str, key: &str) -> String {
+    format!("{}/__login?key={key}", url.trim_end_matches('/'))
+}
+
+/// Comparison that takes the same time wherever the first difference is.
+fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn auth_required_response() -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header("WWW-Authenticate", "Basic realm=\"leak\"")
+        .header("Content-Type", "text/plain")
+        .body(Full::new(Bytes::from("Authentication required")))
+        .unwrap()
+}
+
+// ── Sessions ──────────────────────────────────────────────────────────
+
+const SESSION_COOKIE: &str = "leak_session";
+
+struct Session {
+    creds: HashSet<String>, // base64 "user:pass" this browser has logged in with
+    csrf: String,
+    created: Instant,
+    seen: Instant,
+}
+
+/// What a request learns about its session.
+struct SessionInfo {
+    id: String,
+    creds: HashSet<String>,
+    csrf: String,
+}
+
+/// Browser logins. The cookie holds a random id and its HMAC; everything
+/// else stays here, so logging out (or restarting) really ends a session.
+struct Sessions {
+    key: [u8; 32],
+    idle: std::time::Duration,
+    max: std::time::Duration,
+    map: std::sync::Mutex<HashMap<String, Session>>,
+    pending: std::sync::Mutex<HashMap<String, Challenge>>,
+}
+
+/// A password that was right, waiting for its second factor.
+struct Challenge {
+    cred: String,
+    user: String,
+    created: Instant,
+    attempts: u32,
+}
+
+const CHALLENGE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+const CHALLENGE_ATTEMPTS: u32 = 5;
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 18];
+    getrandom::fill(&mut bytes).expect("no system randomness");
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+impl Sessions {
+    fn new(idle: std::time::Duration, max: std::time::Duration) -> Sessions {
+        let mut key = [0u8; 32];
+        getrandom::fill(&mut key).expect("no system randomness");
+        Sessions {
+            key,
+            idle,
+            max,
+            map: std::sync::Mutex::new(HashMap::new()),
+            pending: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn sign(&self, id: &str) -> String {
+        use hmac::Mac;
+        let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(&self.key).expect("any key length");
+        mac.update(id.as_bytes());
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    }
+
+    /// The `/__login?key=` value standing in for `cred`, good until restart.
+    fn link_key(&self, cred: &str) -> String {
+        self.sign(&format!("link:{cred}"))
+    }
+
+    /// The session a `leak_session` cookie value names, if it is genuine and live.
+    fn lookup(&self, cookie: &str) -> Option<SessionInfo> {
+        let (id, sig) = cookie.split_once('.')?;
+        if !ct_eq(sig.as_bytes(), self.sign(id).as_bytes()) { return None; }
+        let mut map = self.map.lock().unwrap();
+        let s = map.get_mut(id)?;
+        if s.seen.elapsed() > self.idle || s.created.elapsed() > self.max {
+            map.remove(id);
+            return None;
+        }
+        s.seen = Instant::now();
+        Some(SessionInfo { id: id.to_string(), creds: s.creds.clone(), csrf: s.csrf.clone() })
+    }
+
+    /// Add a login to the current session, or start one. Returns the cookie value.
+    fn login(&self, current: Option<&SessionInfo>, cred: &str) -> String {
+        let mut map = self.map.lock().unwrap();
+        map.retain(|_, s| s.seen.elapsed() <= self.idle && s.created.elapsed() <= self.max);
+        // Always a fresh id: a session planted in the browser beforehand must
+        // not gain what this login grants. Earlier logins carry over.
+        let mut creds = current.and_then(|cur| map.remove(&cur.id)).map(|s| s.creds).unwrap_or_default();
+        creds.insert(cred.to_string());
+        let id = random_token();
+        let now = Instant::now();
+        map.insert(id.clone(), Session { creds, csrf: random_token(), created: now, seen: now });
+        format!("{id}.{}", self.sign(&id))
+    }
+
+    fn logout(&self, id: &str) {
+        self.map.lock().unwrap().remove(id);
+    }
+
+    /// Hold on to a checked password until its code arrives. Returns the challenge id.
+    fn challenge(&self, cred: &str, user: &str) -> String {
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, c| c.created.elapsed() <= CHALLENGE_TIMEOUT);
+        let id = random_token();
+        pending.insert(id.clone(), Challenge { cred: cred.to_string(), user: user.to_string(), created: Instant::now(), attempts: 0 });
+        id
+    }
+
+    /// The user a live challenge for these credentials belongs to.
+    fn pending(&self, id: &str, cred: &str) -> Option<String> {
+        let pending = self.pending.lock().unwrap();
+        let c = pending.get(id).filter(|c| c.created.elapsed() <= CHALLENGE_TIMEOUT && c.cred == cred)?;
+        Some(c.user.clone())
+    }
+
+    /// Count a wrong code. Returns false once the challenge is used up.
+    fn retry_challenge(&self, id: &str) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        let c = match pending.get_mut(id) { Some(c) => c, None => return false };
+        c.attempts += 1;
+        if c.attempts < CHALLENGE_ATTEMPTS { return true; }
+        pending.remove(id);
+        false
+    }
+
+    fn finish_challenge(&self, id: &str) {
+        self.pending.lock().unwrap().remove(id);
+    }
+
+    /// `Set-Cookie` value; `None` clears the cookie.
+    fn cookie(&self, value: Option<&str>, secure: bool) -> String {
+        let (value, age) = match value {
+            Some(v) => (v, self.max.as_secs()),
+            None => ("", 0),
+        };
+        let secure = if secure { "; Secure" } else { "" };
+        format!("{SESSION_COOKIE}={value}; Path=/; Max-Age={age}; HttpOnly; SameSite=Lax{secure}")
+    }
+}
+
+/// The value of a cookie sent with the request.
+fn request_cookie<B>(req: &Request<B>, name: &str) -> Option<String> {
+    req.headers().get_all("cookie").iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|c| c.trim().strip_prefix(name)?.strip_prefix('=').map(str::to_string))
+}
+
+/// Browsers get the login page; curl and scripts get a Basic challenge.
+fn wants_login_page<B>(req: &Request<B>) -> bool {
+    req.method() == Method::GET
+        && req.headers().get("accept").and_then(|v| v.to_str().ok()).is_some_and(|a| a.contains("text/html"))
+}
+
+/// Only same-site paths, so the login form can't bounce someone elsewhere.
+fn safe_next(next: &str) -> &str {
+    if next.starts_with('/') && !next.starts_with("//") && !next.contains('\\') { next } else { "/" }
+}
+
+/// HTTP/1 sends a Host header; HTTP/2 puts it in the URI as :authority.
+fn request_host<B>(req: &Request<B>) -> Option<&str> {
+    req.headers().get("host").and_then(|v| v.to_str().ok())
+        .or_else(|| req.uri().authority().map(|a| a.as_str()))
+}
+
+/// A POST from another site's page carries its own Origin.
+fn same_origin<B>(req: &Request<B>) -> bool {
+    let origin = match req.headers().get("origin").and_then(|v| v.to_str().ok()) {
+        Some(o) => o,
+        None => return true,
+    };
+    let host = request_host(req).unwrap_or("");
+    origin.split_once("://").is_some_and(|(_, h)| h == host)
+}
+
+/// The password form, or with a `challenge` the second-factor form that follows it.
+fn render_login(next: &str, error: Option<&str>, challenge: Option<&str>, nonce: &str) -> String {
+    let error = error.map(|e| format!(r#"<div class="login-error">{}</div>"#, html_escape(e))).unwrap_or_default();
+    let fields = match challenge {
+        Some(id) => format!(
+            r#"<div class="login-hint">Enter the 6-digit code from your authenticator app, or a recovery code.</div>
+    <input type="hidden" name="challenge" value="{}">
+    <input class="search-bar" name="code" placeholder="123456" inputmode="numeric" autocomplete="one-time-code" autocapitalize="off" required autofocus>"#,
+            html_escape(id),
+        ),
+        None => r#"<input class="search-bar" name="user" placeholder="Username" autocomplete="username" autocapitalize="off" required autofocus>
+    <input class="search-bar" name="pass" type="password" placeholder="Password" autocomplete="current-password" required>"#.to_string(),
+    };
+    format!(
+        r##"<!DOCTYPE html><html lang="en"><head>
+<meta charset="utf-8"><meta name="viewport" content="width=device-width,initial-scale=1">
+<title>leak · log in</title>
+<style nonce="{nonce}">{PAGE_CSS}</style>
+</head><body>
+<div class="header"><div class="header-inner">
+  <div class="header-left">
+    <div class="logo"><div class="logo-dot"></div><div class="logo-text">leak</div></div>
+  </div>
+</div></div>
+<div class="container">
+  <form class="login-card" method="post" action="/__login">
+    <div class="login-title">Log in</div>
+    {error}
+    <input type="hidden" name="next" value="{}">
+    {fields}
+    <button class="sel-btn login-btn" type="submit">Log in</button>
+  </form>
+</div>
+<script nonce="{nonce}">
+const saved = localStorage.getItem('leak-theme');
+if (saved) document.documentElement.setAttribute('data-theme', saved);
+</script>
+</body></html>"##,
+        html_escape(next),
+    )
+}
+
+fn login_response(status: StatusCode, next: &str, error: Option<&str>, challenge: Option<&str>) -> Response<Full<Bytes>> {
+    let nonce = random_token();
+    let mut resp = page_response(status, render_login(next, error, challenge, &nonce), &nonce);
+    resp.headers_mut().insert("Cache-Control", hyper::header::HeaderValue::from_static("no-store"));
+    resp
+}
+
+/// 303 to `location`, setting or clearing the session cookie.
+fn session_redirect(location: &str, cookie: Option<String>) -> Response<Full<Bytes>> {
+    let mut resp = Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header("Location", location)
+        .header("Cache-Control", "no-store");
+    if let Some(c) = cookie { resp = resp.header("Set-Cookie", c); }
+    resp.body(Full::new(Bytes::new())).unwrap()
+}
+
+// ── API tokens ────────────────────────────────────────────────────────
+
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Perm { Read, Upload, Rw }
+
+impl Perm {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "read" => Some(Self::Read),
+            "upload" => Some(Self::Upload),
+            "rw" | "read-write" => Some(Self::Rw),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self { Self::Read => "read", Self::Upload => "upload", Self::Rw => "rw" }
+    }
+
+    /// Upload-only tokens can't read anything back.
+    fn allows(&self, class: RouteClass) -> bool {
+        match self {
+            Self::Rw => true,
+            Self::Read => class != RouteClass::Upload,
+            Self::Upload => class == RouteClass::Upload,
+        }
+    }
+}
+
+/// One line of the token store. Only a hash of the secret is kept.
+#[derive(Clone, Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct TokenRecord {
+    id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    hash: String, // sha256 of the secret, hex
+    scope: String, // clean relative path; empty for everything
+    perm: Perm,
+    created: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_used: Option<u64>,
+}
+
+#[derive(Default, Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+struct TokenFile {
+    #[serde(default)]
+    token: Vec<TokenRecord>,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+fn default_config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+        .map(|d| d.join("leak"))
+}
+
+fn token_hash(secret: &str) -> String {
+    use sha2::Digest;
+    sha2::Sha256::digest(secret.as_bytes()).iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// A missing store is an empty one.
+fn read_tokens(path: &Path) -> Result<Vec<TokenRecord>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => toml::from_str::<TokenFile>(&text)
+            .map(|f| f.token)
+            .map_err(|e| format!("{}: {}", path.display(), e.message())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+fn write_tokens(path: &Path, tokens: &[TokenRecord]) -> Result<(), String> {
+    let text = toml::to_string(&TokenFile { token: tokens.to_vec() }).map_err(|e| e.to_string())?;
+    write_private(path, &text)
+}
+
+/// Replace a file in one step, readable only by its owner.
+fn write_private(path: &Path, text: &str) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut opts = std::fs::OpenOptions::new();
+    opts.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut opts, 0o600);
+    let mut f = opts.open(&tmp).map_err(|e| format!("{}: {e}", tmp.display()))?;
+    std::io::Write::write_all(&mut f, text.as_bytes()).map_err(|e| format!("{}: {e}", tmp.display()))?;
+    std::fs::rename(&tmp, path).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// What a valid bearer token lets a request do.
+struct Grant {
+    scope: String,
+    perm: Perm,
+}
+
+impl Grant {
+    /// `rel` is a clean relative path, as from `decode_request_path`.
+    fn covers(&self, rel: &str) -> bool {
+        let rel = rel.trim_matches('/');
+        self.scope.is_empty() || rel == self.scope || rel.strip_prefix(&self.scope).is_some_and(|r| r.starts_with('/'))
+    }
+}
+
+struct TokenSet {
+    stamp: Option<SystemTime>,
+    checked: Instant,
+    tokens: Vec<TokenRecord>,
+}
+
+/// The tokens a running server accepts, re-read whenever `leak token`
+/// changes the store so revocations apply at once.
+struct TokenStore {
+    path: PathBuf,
+    loaded: RwLock<TokenSet>,
+    writing: Arc<std::sync::Mutex<()>>, // one `last_used` write-back at a time
+}
+
+impl TokenStore {
+    fn open(path: PathBuf) -> Result<TokenStore, String> {
+        let tokens = read_tokens(&path)?;
+        let stamp = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+        Ok(TokenStore {
+            path,
+            loaded: RwLock::new(TokenSet { stamp, checked: Instant::now(), tokens }),
+            writing: Arc::default(),
+        })
+    }
+
+    fn refresh(&self) {
+        if self.loaded.read().unwrap().checked.elapsed() < std::time::Duration::from_secs(1) { return; }
+        let stamp = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        let mut set = self.loaded.write().unwrap();
+        set.checked = Instant::now();
+        if stamp == set.stamp { return; }
+        set.stamp = stamp;
+        match read_tokens(&self.path) {
+            Ok(tokens) => set.tokens = tokens,
+            Err(e) => eprintln!("  {} {RD}tokens not reloaded:{RST} {e}", ts()),
+        }
+    }
+
+    fn active(&self) -> usize {
+        let now = unix_now();
+        self.loaded.read().unwrap().tokens.iter().filter(|t| t.expires.is_none_or(|e| e > now)).count()
+    }
+
+    /// Check a `leak_<id>_<secret>` token, noting when it was last used.
+    fn verify(&self, token: &str) -> Option<Grant> {
+        let (id, secret) = token.strip_prefix("leak_")?.split_once('_')?;
+        self.refresh();
+        let now = unix_now();
+        let (grant, stale) = {
+            let set = self.loaded.read().unwrap();
+            let t = set.tokens.iter().find(|t| t.id == id)?;
+            if !ct_eq(token_hash(secret).as_bytes(), t.hash.as_bytes()) { return None; }
+            if t.expires.is_some_and(|e| e <= now) { return None; }
+            let stale = t.last_used.is_none_or(|u| now.saturating_sub(u) >= 60);
+            (Grant { scope: t.scope.clone(), perm: t.perm }, stale)
+        };
+        // Written back at most once a minute per token
+        if stale { self.touch(id, now); }
+        Some(grant)
+    }
+
+    fn touch(&self, id: &str, now: u64) {
+        if let Some(t) = self.loaded.write().unwrap().tokens.iter_mut().find(|t| t.id == id) { t.last_used = Some(now); }
+        // The file is written off the async workers; `refresh` picks it up
+        let (path, id, writing) = (self.path.clone(), id.to_string(), self.writing.clone());
+        tokio::task::spawn_blocking(move || {
+            let _writing = writing.lock().unwrap();
+            // Re-read first so a revoke that landed meanwhile isn't undone
+            let mut tokens = match read_tokens(&path) { Ok(t) => t, Err(_) => return };
+            match tokens.iter_mut().find(|t| t.id == id) {
+                Some(t) => t.last_used = Some(now),
+                None => return,
+            }
+            let _ = write_tokens(&path, &tokens);
+        });
+    }
+}
+
+fn bearer_token<B>(req: &Request<B>) -> Option<&str> {
+    req.headers().get("authorization")?.to_str().ok()?.strip_prefix("Bearer ").map(str::trim)
+}
+
+fn token_store_path(raw: &[String]) -> Option<PathBuf> {
+    flag_value(raw, "--token-store").map(PathBuf::from)
+        .or_else(|| env::var_os("LEAK_TOKEN_STORE").map(PathBuf::from))
+        .map(|p| expand_home(&p))
+        .or_else(|| default_config_dir().map(|d| d.join("tokens.toml")))
+}
+
+/// `leak token create|list|revoke`
+fn token_command(raw: &[String]) {
+    let die = |msg: String| -> ! { eprintln!("{RD}{B}Error:{RST} {msg}"); std::process::exit(1); };
+    let store = token_store_path(raw).unwrap_or_else(|| die("no home directory; pass --token-store".to_string()));
+    let mut tokens = read_tokens(&store).unwrap_or_else(|e| die(e));
+    let now = unix_now();
+    match raw.first().map(String::as_str) {
+        Some("create") => {
+            let scope = flag_value(raw, "--scope").map(String::as_str).unwrap_or("/");
+            let scope = decode_request_path(scope).unwrap_or_else(|_| die(format!("--scope {scope}: not a clean path")));
+            let perm = match flag_value(raw, "--perm") {
+                Some(p) => Perm::parse(p).unwrap_or_else(|| die(format!("--perm {p}: expected read, upload or rw"))),
+                None => Perm::Read,
+            };
+            let expires = flag_value(raw, "--ttl").map(|t| {
+                let d = parse_duration(t).filter(|d| !d.is_zero())
+                    .unwrap_or_else(|| die(format!("--ttl {t}: expected a duration like 12h or 30d")));
+                now + d.as_secs()
+            });
+            let mut id_bytes = [0u8; 4];
+            getrandom::fill(&mut id_bytes).expect("no system randomness");
+            let id: String = id_bytes.iter().map(|b| format!("{b:02x}")).collect();
+            let secret = random_token() + &random_token();
+            tokens.push(TokenRecord {
+                id: id.clone(),
+                name: flag_value(raw, "--name").cloned(),
+                hash: token_hash(&secret),
+                scope: scope.clone(),
+                perm,
+                created: now,
+                expires,
+                last_used: None,
+            });
+            write_tokens(&store, &tokens).unwrap_or_else(|e| die(e));
+            eprintln!("\n  {GR}●{RST} {B}Token {id}{RST} {D}· {} on /{scope}{}{RST}", perm.name(),
+                expires.map(|e| format!(" · expires in {}", format_time(e - now).trim_end_matches(" ago"))).unwrap_or_default());
+            eprintln!("  {D}  Shown once; only a hash is kept in {}{RST}\n", store.display());
+            println!("leak_{id}_{secret}");
+        }
+        Some("list") => {
+            let json = raw.iter().any(|a| a == "--json");
+            if json {
+                for t in &tokens {
+                    let opt = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_else(|| "null".into());
+                    println!(
+                        r#"{{"id":"{}","name":{},"scope":"/{}","perm":"{}","created":{},"expires":{},"last_used":{}}}"#,
+                        t.id, t.name.as_ref().map(|n| format!("\"{}\"", json_escape(n))).unwrap_or_else(|| "null".into()),
+                        json_escape(&t.scope), t.perm.name(), t.created, opt(t.expires), opt(t.last_used),
+                    );
+                }
+                return;
+            }
+            if tokens.is_empty() {
+                eprintln!("\n  {D}No tokens in {}{RST}\n", store.display());
+                return;
+            }
+            eprintln!();
+            for t in &tokens {
+                let expiry = match t.expires {
+                    Some(e) if e <= now => format!("{RD}expired{RST}"),
+                    Some(e) => format!("expires in {}", format_time(e - now).trim_end_matches(" ago")),
+                    None => "no expiry".to_string(),
+                };
+                let used = t.last_used.map(|u| format!("used {}", format_time(now.saturating_sub(u)))).unwrap_or_else(|| "never used".into());
+                eprintln!(
+                    "  {B}{}{RST} {CY}{:<6}{RST} /{} {D}{}· created {} · {expiry} · {used}{RST}",
+                    t.id, t.perm.name(), t.scope,
+                    t.name.as_ref().map(|n| format!("{n} ")).unwrap_or_default(),
+                    format_time(now.saturating_sub(t.created)),
+                );
+            }
+            eprintln!();
+        }
+        Some("revoke") => {
+            let mut ids = Vec::new();
+            let mut rest = raw[1..].iter();
+            while let Some(a) = rest.next() {
+                if a == "--token-store" { rest.next(); } else { ids.push(a); }
+            }
+            if ids.is_empty() { die("usage: leak token revoke <id>...".to_string()); }
+            for id in ids {
+                let before = tokens.len();
+                tokens.retain(|t| &t.id != id && t.name.as_ref() != Some(id));
+                if tokens.len() == before { die(format!("no token {id}")); }
+                eprintln!("  {YL}●{RST} revoked {B}{id}{RST}");
+            }
+            write_tokens(&store, &tokens).unwrap_or_else(|e| die(e));
+        }
+        _ => {
+            eprintln!("{B}Usage:{RST}");
+            eprintln!("  leak token create [--scope /path] [--perm read|upload|rw] [--ttl 30d] [--name label]");
+            eprintln!("  leak token list [--json]");
+            eprintln!("  leak token revoke <id|name>...");
+            eprintln!("  {D}All take --token-store file (default ~/.config/leak/tokens.toml){RST}");
+            std::process::exit(2);
+        }
+    }
+}
+
+// ── Two-factor logins ─────────────────────────────────────────────────
+
+/// A user enrolled in TOTP. Recovery codes are kept as hashes and used up.
+#[derive(Clone, Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct TotpRecord {
+    user: String,
+    secret: String, // base32, as authenticator apps take it
+    recovery: Vec<String>, // sha256 of each unused code, hex
+    created: u64,
+}
+
+#[derive(Default, Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+struct TotpFile {
+    #[serde(default)]
+    user: Vec<TotpRecord>,
+}
+
+fn read_totp(path: &Path) -> Result<Vec<TotpRecord>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => toml::from_str::<TotpFile>(&text)
+            .map(|f| f.user)
+            .map_err(|e| format!("{}: {}", path.display(), e.message())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+fn write_totp(path: &Path, users: &[TotpRecord]) -> Result<(), String> {
+    let text = toml::to_string(&TotpFile { user: users.to_vec() }).map_err(|e| e.to_string())?;
+    write_private(path, &text)
+}
+
+const BASE32: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in bytes.chunks(5) {
+        let mut buf = [0u8; 5];
+        buf[..chunk.len()].copy_from_slice(chunk);
+        let bits = buf.iter().fold(0u64, |acc, b| acc << 8 | *b as u64);
+        for i in 0..(chunk.len() * 8).div_ceil(5) {
+            out.push(BASE32[(bits >> (35 - i * 5)) as usize & 31] as char);
+        }
+    }
+    out
+}
+
+/// Lenient about case, spaces and padding, as people copy secrets by hand.
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let (mut out, mut acc, mut bits) = (Vec::new(), 0u32, 0);
+    for c in s.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let v = BASE32.iter().position(|b| *b as char == c.to_ascii_uppercase())? as u32;
+        acc = acc << 5 | v;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Some(out)
+}
+
+/// RFC 4226 HOTP, six digits.
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    use hmac::Mac;
+    let mut mac = hmac::Hmac::<sha1::Sha1>::new_from_slice(key).expect("any key length");
+    mac.update(&counter.to_be_bytes());
+    let h = mac.finalize().into_bytes();
+    let off = (h[19] & 0x0f) as usize;
+    let bin = u32::from_be_bytes([h[off] & 0x7f, h[off + 1], h[off + 2], h[off + 3]]);
+    bin % 1_000_000
+}
+
+const TOTP_PERIOD: u64 = 30;
+
+/// The RFC 6238 time step `code` is valid for, allowing one step of clock drift.
+fn totp_step(secret: &str, code: &str, now: u64) -> Option<u64> {
+    let key = base32_decode(secret)?;
+    let code: u32 = code.trim().parse().ok().filter(|_| code.trim().len() == 6)?;
+    let step = now / TOTP_PERIOD;
+    [step, step.saturating_sub(1), step + 1].into_iter().find(|s| hotp(&key, *s) == code)
+}
+
+fn normalize_recovery(code: &str) -> String {
+    code.trim().to_lowercase().replace([' ', '-'], "")
+}
+
+struct TotpSet {
+    stamp: Option<SystemTime>,
+    checked: Instant,
+    users: Vec<TotpRecord>,
+}
+
+/// Users with a second factor, re-read when `leak totp` changes the store.
+struct TotpStore {
+    path: PathBuf,
+    loaded: RwLock<TotpSet>,
+    used_steps: std::sync::Mutex<HashMap<String, u64>>, // a code works once
+}
+
+impl TotpStore {
+    fn open(path: PathBuf) -> Result<TotpStore, String> {
+        let users = read_totp(&path)?;
+        let stamp = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+        Ok(TotpStore {
+            path,
+            loaded: RwLock::new(TotpSet { stamp, checked: Instant::now(), users }),
+            used_steps: std::sync::Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn refresh(&self) {
+        if self.loaded.read().unwrap().checked.elapsed() < std::time::Duration::from_secs(1) { return; }
+        let stamp = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        let mut set = self.loaded.write().unwrap();
+        set.checked = Instant::now();
+        if stamp == set.stamp { return; }
+        set.stamp = stamp;
+        match read_totp(&self.path) {
+            Ok(users) => set.users = users,
+            Err(e) => eprintln!("  {} {RD}2FA users not reloaded:{RST} {e}", ts()),
+        }
+    }
+
+    fn enrolled(&self, user: &str) -> bool {
+        self.refresh();
+        self.loaded.read().unwrap().users.iter().any(|u| u.user == user)
+    }
+
+    fn count(&self) -> usize {
+        self.loaded.read().unwrap().users.len()
+    }
+
+    /// Check a code from the app, or use up a recovery code.
+    fn verify(&self, user: &str, code: &str) -> bool {
+        self.refresh();
+        let secret = match self.loaded.read().unwrap().users.iter().find(|u| u.user == user) {
+            Some(u) => u.secret.clone(),
+            None => return false,
+        };
+        if let Some(step) = totp_step(&secret, code, unix_now()) {
+            let mut used = self.used_steps.lock().unwrap();
+            if used.get(user).is_some_and(|s| *s >= step) { return false; }
+            used.insert(user.to_string(), step);
+            return true;
+        }
+        let hash = token_hash(&normalize_recovery(code));
+        let mut set = self.loaded.write().unwrap();
+        // Re-read so codes used or reissued meanwhile aren't resurrected
+        let mut users = match read_totp(&self.path) { Ok(u) => u, Err(_) => return false };
+        let rec = match users.iter_mut().find(|u| u.user == user) { Some(r) => r, None => return false };
+        let before = rec.recovery.len();
+        rec.recovery.retain(|h| !ct_eq(h.as_bytes(), hash.as_bytes()));
+        if rec.recovery.len() == before { return false; }
+        let left = rec.recovery.len();
+        if write_totp(&self.path, &users).is_err() { return false; }
+        eprintln!("  {} {YL}{B}RECOVERY{RST} {CY}{user}{RST} {D}used a recovery code, {left} left{RST}", ts());
+        set.stamp = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        set.users = users;
+        true
+    }
+}
+
+fn totp_store_path(raw: &[String]) -> Option<PathBuf> {
+    flag_value(raw, "--totp-store").map(PathBuf::from)
+        .or_else(|| env::var_os("LEAK_TOTP_STORE").map(PathBuf::from))
+        .map(|p| expand_home(&p))
+        .or_else(|| default_config_dir().map(|d| d.join("totp.toml")))
+}
+
+/// Ten codes of two groups, shown once.
+fn recovery_codes() -> (Vec<String>, Vec<String>) {
+    let codes: Vec<String> = (0..10).map(|_| generate_passphrase()[..9].to_string()).collect();
+    let hashes = codes.iter().map(|c| token_hash(&normalize_recovery(c))).collect();
+    (codes, hashes)
+}
+
+fn print_recovery_codes(codes: &[String]) {
+    eprintln!("  {B}Recovery codes{RST} {D}(each works once; keep them somewhere safe){RST}");
+    for pair in codes.chunks(2) {
+        eprintln!("    {}", pair.join("    "));
+    }
+    eprintln!();
+}
+
+/// `leak totp enroll|list|remove|recovery`
+fn totp_command(raw: &[String]) {
+    let die = |msg: String| -> ! { eprintln!("{RD}{B}Error:{RST} {msg}"); std::process::exit(1); };
+    let store = totp_store_path(raw).unwrap_or_else(|| die("no home directory; pass --totp-store".to_string()));
+    let mut users = read_totp(&store).unwrap_or_else(|e| die(e));
+    let mut positional = Vec::new();
+    let mut rest = raw.iter();
+    while let Some(a) = rest.next() {
+        if ["--totp-store", "--issuer"].contains(&a.as_str()) { rest.next(); } else { positional.push(a.as_str()); }
+    }
+    match positional[..] {
+        ["enroll", user] => {
+            if users.iter().any(|u| u.user == user) {
+                die(format!("{user} is already enrolled; `leak totp remove {user}` first"));
+            }
+            let mut key = [0u8; 20];
+            getrandom::fill(&mut key).expect("no system randomness");
+            let secret = base32_encode(&key);
+            let issuer = flag_value(raw, "--issuer").map(String::as_str).unwrap_or("leak");
+            let uri = format!(
+                "otpauth://totp/{}:{}?secret={secret}&issuer={}&algorithm=SHA1&digits=6&period={TOTP_PERIOD}",
+                percent_encode(issuer), percent_encode(user), percent_encode(issuer),
+            );
+            eprintln!("\n  {B}Scan with an authenticator app{RST} {D}for {user}{RST}");
+            render_qr_terminal(&uri);
+            eprintln!("  {D}  Or enter the key by hand: {secret}{RST}\n");
+            // Nothing is saved until the app has shown it can produce codes
+            let mut tries = 0;
+            loop {
+                eprint!("  Code from the app: ");
+                let mut line = String::new();
+                if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0 { die("not enrolled".to_string()); }
+                if totp_step(&secret, &line, unix_now()).is_some() { break; }
+                tries += 1;
+                if tries == 3 { die("codes didn't match; check the phone's clock and try again".to_string()); }
+                eprintln!("  {RD}That code doesn't match.{RST}");
+            }
+            let (codes, hashes) = recovery_codes();
+            users.push(TotpRecord { user: user.to_string(), secret, recovery: hashes, created: unix_now() });
+            write_totp(&store, &users).unwrap_or_else(|e| die(e));
+            eprintln!("\n  {GR}●{RST} {B}{user}{RST} now needs a code to log in {D}({}){RST}\n", store.display());
+            print_recovery_codes(&codes);
+        }
+        ["recovery", user] => {
+            let rec = users.iter_mut().find(|u| u.user == user).unwrap_or_else(|| die(format!("{user} is not enrolled")));
+            let (codes, hashes) = recovery_codes();
+            rec.recovery = hashes;
+            write_totp(&store, &users).unwrap_or_else(|e| die(e));
+            eprintln!("\n  {GR}●{RST} new recovery codes for {B}{user}{RST}; the old ones no longer work\n");
+            print_recovery_codes(&codes);
+        }
+        ["remove", user] => {
+            let before = users.len();
+            users.retain(|u| u.user != user);
+            if users.len() == before { die(format!("{user} is not enrolled")); }
+            write_totp(&store, &users).unwrap_or_else(|e| die(e));
+            eprintln!("  {YL}●{RST} removed the second factor for {B}{user}{RST}");
+        }
+        ["list"] => {
+            if users.is_empty() {
+                eprintln!("\n  {D}No users enrolled in {}{RST}\n", store.display());
+                return;
+            }
+            eprintln!();
+            let now = unix_now();
+            for u in &users {
+                eprintln!(
+                    "  {B}{}{RST} {D}enrolled {} · {} recovery codes left{RST}",
+                    u.user, format_time(now.saturating_sub(u.created)), u.recovery.len(),
+                );
+            }
+            eprintln!();
+        }
+        _ => {
+            eprintln!("{B}Usage:{RST}");
+            eprintln!("  leak totp enroll <user> [--issuer name]");
+            eprintln!("  leak totp list");
+            eprintln!("  leak totp recovery <user>    {D}issue new recovery codes{RST}");
+            eprintln!("  leak totp remove <user>");
+            eprintln!("  {D}All take --totp-store file (default ~/.config/leak/totp.toml){RST}");
+            std::process::exit(2);
+        }
+    }
+}
+
+// ── Rate limiting ─────────────────────────────────────────────────────
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum RouteClass { Get, Upload, Archive }
+
+impl RouteClass {
+    const ALL: [RouteClass; 3] = [Self::Get, Self::Upload, Self::Archive];
+
+    fn of(method: &Method, path: &str) -> Self {
+        match (method, path) {
+            (&Method::POST, p) if p.ends_with("/__upload") => Self::Upload,
+            (&Method::POST, p) if p.ends_with("/__download") => Self::Archive,
+            _ => Self::Get,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self { Self::Get => "get", Self::Upload => "upload", Self::Archive => "archive" }
+    }
+}
+
+/// A token bucket: `per_sec` refill, up to `burst` saved up.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Rate {
+    per_sec: f64,
+    burst: f64,
+    label: (u32, char), // as written, e.g. (60, 'm'), for the banner
+}
+
+fn default_rates() -> HashMap<RouteClass, Rate> {
+    HashMap::from([
+        (RouteClass::Get, Rate { per_sec: 50.0, burst: 200.0, label: (50, 's') }),
+        (RouteClass::Upload, Rate { per_sec: 1.0, burst: 60.0, label: (60, 'm') }),
+        (RouteClass::Archive, Rate { per_sec: 10.0 / 60.0, burst: 10.0, label: (10, 'm') }),
+    ])
+}
+
+/// `get=20/s`, `upload=30/m:10` (burst after the colon), `archive=off`.
+fn parse_rate(spec: &str) -> Option<(RouteClass, Option<Rate>)> {
+    let (class, rate) = spec.split_once('=')?;
+    let class = RouteClass::ALL.into_iter().find(|c| c.name() == class)?;
+    if rate == "off" { return Some((class, None)); }
+    let (rate, burst) = match rate.split_once(':') {
+        Some((r, b)) => (r, Some(b.parse::<u32>().ok()?)),
+        None => (rate, None),
+    };
+    let (n, unit) = rate.split_once('/')?;
+    let n: u32 = n.parse().ok().filter(|n| *n > 0)?;
+    let secs = match unit { "s" => 1.0, "m" => 60.0, "h" => 3600.0, _ => return None };
+    let unit = unit.chars().next()?;
+    Some((class, Some(Rate { per_sec: n as f64 / secs, burst: burst.unwrap_or(n).max(1) as f64, label: (n, unit) })))
+}
+
+/// `30s`, `5m`, `1h`, or plain seconds.
+fn parse_duration(s: &str) -> Option<std::time::Duration> {
+    let (n, mult) = match s.char_indices().last()? {
+        (i, 's') => (&s[..i], 1),
+        (i, 'm') => (&s[..i], 60),
+        (i, 'h') => (&s[..i], 3600),
+        (i, 'd') => (&s[..i], 86400),
+        _ => (s, 1),
+    };
+    Some(std::time::Duration::from_secs(n.parse::<u64>().ok()? * mult))
+}
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    warned: bool,
+}
+
+struct Failures {
+    count: u32,
+    first: Instant,
+    strikes: u32,
+    locked_until: Option<Instant>,
+}
+
+/// Failed logins only count within this window.
+const FAILURE_WINDOW: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+const MAX_LOCKOUT: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Who a budget or lockout belongs to. An IPv6 client usually owns a whole
+/// /64 and can pick a fresh address from it for every request.
+fn guard_key(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V6(v6) => IpAddr::V6((u128::from(v6) & !(u64::MAX as u128)).into()),
+        v4 => v4,
+    }
+}
+
+/// Per-IP request budgets and login lockouts (per /64 for IPv6). Unix socket
+/// clients have no address and are never limited.
+struct Guard {
+    rates: HashMap<RouteClass, Rate>,
+    max_failures: u32, // 0 turns lockouts off
+    lockout: std::time::Duration,
+    buckets: std::sync::Mutex<HashMap<(IpAddr, RouteClass), Bucket>>,
+    failures: std::sync::Mutex<HashMap<IpAddr, Failures>>,
+}
+
+impl Guard {
+    fn new(rates: HashMap<RouteClass, Rate>, max_failures: u32, lockout: std::time::Duration) -> Guard {
+        Guard {
+            rates,
+            max_failures,
+            lockout,
+            buckets: std::sync::Mutex::new(HashMap::new()),
+            failures: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Spend a token, or say how long until one is available.
+    fn take(&self, ip: IpAddr, class: RouteClass) -> Result<(), std::time::Duration> {
+        let Some(rate) = self.rates.get(&class) else { return Ok(()) };
+        let ip = guard_key(ip);
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > 10_000 {
+            // Anyone back to a full bucket is indistinguishable from a newcomer
+            buckets.retain(|(_, c), b| {
+                let r = self.rates[c];
+                b.tokens + now.duration_since(b.last).as_secs_f64() * r.per_sec < r.burst
+            });
+        }
+        let b = buckets.entry((ip, class)).or_insert(Bucket { tokens: rate.burst, last: now, warned: false });
+        b.tokens = (b.tokens + now.duration_since(b.last).as_secs_f64() * rate.per_sec).min(rate.burst);
+        b.last = now;
+        if b.tokens >= 1.0 {
+            b.tokens -= 1.0;
+            b.warned = false;
+            return Ok(());
+        }
+        if !b.warned {
+            b.warned = true;
+            eprintln!("  {} {YL}{B}LIMIT{RST} {CY}{ip}{RST} {D}{} requests over {}/{}{RST}", ts(), class.name(), rate.label.0, rate.label.1);
+        }
+        Err(std::time::Duration::from_secs_f64((1.0 - b.tokens) / rate.per_sec))
+    }
+
+    /// Time left on a lockout, if the address is serving one.
+    fn locked(&self, ip: IpAddr) -> Option<std::time::Duration> {
+        let f = self.failures.lock().unwrap();
+        f.get(&guard_key(ip))?.locked_until?.checked_duration_since(Instant::now())
+    }
+
+    /// Each lockout doubles the last one, up to an hour.
+    fn login_failed(&self, ip: IpAddr) {
+        if self.max_failures == 0 { return; }
+        let ip = guard_key(ip);
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+        // Strikes are remembered for a while after the last lockout ends, not forever
+        failures.retain(|_, f| now.duration_since(f.first) < FAILURE_WINDOW || f.locked_until.is_some_and(|t| now < t + MAX_LOCKOUT));
+        let f = failures.entry(ip).or_insert(Failures { count: 0, first: now, strikes: 0, locked_until: None });
+        if now.duration_since(f.first) >= FAILURE_WINDOW { f.count = 0; f.first = now; }
+        f.count += 1;
+        if f.count >= self.max_failures {
+            let ban = self.lockout.saturating_mul(1 << f.strikes.min(16)).min(MAX_LOCKOUT);
+            f.locked_until = Some(now + ban);
+            f.strikes += 1;
+            f.count = 0;
+            f.first = now;
+            eprintln!(
+                "  {} {RD}{B}BANNED{RST} {CY}{ip}{}{RST} {D}for {}s after {} failed logins{RST}",
+                ts(), if ip.is_ipv6() { "/64" } else { "" }, ban.as_secs(), self.max_failures,
+            );
+        }
+    }
+
+    /// A successful login wipes the slate, strikes included.
+    fn login_ok(&self, ip: IpAddr) {
+        if self.max_failures == 0 { return; }
+        self.failures.lock().unwrap().remove(&guard_key(ip));
+    }
+
+    fn summary(&self, auth: bool) -> String {
+        let mut parts: Vec<String> = RouteClass::ALL.iter()
+            .filter_map(|c| self.rates.get(c).map(|r| format!("{} {}/{}", c.name(), r.label.0, r.label.1)))
+            .collect();
+        if parts.is_empty() { parts.push("no rate limits".into()); }
+        let mut s = parts.join(", ");
+        if auth && self.max_failures > 0 {
+            s += &format!(" · lockout after {} failed logins", self.max_failures);
+        }
+        s
+    }
+}
+
+fn too_many_requests(wait: std::time::Duration) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header("Retry-After", wait.as_secs_f64().ceil().max(1.0).to_string())
+        .header("Content-Type", "text/plain")
+        .body(Full::new(Bytes::from("Too many requests")))
+        .unwrap()
+}
+
+// ── Redirect rules ────────────────────────────────────────────────────
+
+/// One line of a Netlify-style `_redirects` file:
+/// `/old/:slug  /new/:slug  301`, `/docs/*  /v2/docs/:splat  302`, or
+/// `/app/*  /index.html  200` to rewrite without telling the client.
+#[derive(Debug, PartialEq)]
+struct RedirectRule {
+    from: String,
+    to: String,
+    status: u16,
+}
+
+fn parse_redirects(contents: &str) -> (Vec<RedirectRule>, Vec<String>) {
+    let mut rules = Vec::new();
+    let mut errors = Vec::new();
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (from, to, status) = match fields[..] {
+            [from, to] => (from, to, "301"),
+            [from, to, status] => (from, to, status),
+            _ => { errors.push(format!("line {}: expected `from to [status]`", n + 1)); continue; }
+        };
+        // Every rule already runs before the filesystem, so Netlify's `!`
+        // would change nothing; say so rather than accept it silently
+        if status.ends_with('!') {
+            errors.push(format!("line {}: drop the `!` from {status}: rules always apply, even over existing files", n + 1));
+            continue;
+        }
+        let status = match status.parse::<u16>() {
+            Ok(c @ (200 | 301 | 302 | 307 | 308)) => c,
+            _ => { errors.push(format!("line {}: status must be 200, 301, 302, 307 or 308", n + 1)); continue; }
+        };
+        if !from.starts_with('/') {
+            errors.push(format!("line {}: `{from}` should start with /", n + 1));
+            continue;
+        }
+        if status == 200 && !to.starts_with('/') {
+            errors.push(format!("line {}: a 200 rewrite needs a local path, not `{to}`", n + 1));
+            continue;
+        }
+        rules.push(RedirectRule { from: from.to_string(), to: to.to_string(), status });
+    }
+    (rules, errors)
+}
+
+impl RedirectRule {
+    /// The placeholders and splat captured from `path`, if the rule applies.
+    /// Other segments are globs, so `/blog/*.php` works too.
+    fn matches(&self, path: &str) -> Option<Vec<(String, String)>> {
+        let pat: Vec<&str> = self.from.split('/').filter(|p| !p.is_empty()).collect();
+        let segs: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+        let mut caps = Vec::new();
+        for (i, p) in pat.iter().enumerate() {
+            if *p == "*" && i == pat.len() - 1 {
+                caps.push(("splat".to_string(), segs.get(i..).unwrap_or_default().join("/")));
+                return Some(caps);
+            }
+            let seg = segs.get(i)?;
+            match p.strip_prefix(':') {
+                Some(name) => caps.push((name.to_string(), seg.to_string())),
+                None => if !glob_match(p.as_bytes(), seg.as_bytes()) { return None },
+            }
+        }
+        (pat.len() == segs.len()).then_some(caps)
+    }
+
+    /// `to` with `:name` replaced by what was captured.
+    fn target(&self, caps: &[(String, String)]) -> String {
+        let mut out = String::new();
+        let mut rest = self.to.as_str();
+        while let Some(i) = rest.find(':') {
+            out.push_str(&rest[..i]);
+            let after = &rest[i + 1..];
+            let len = after.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(after.len());
+            let name = &after[..len];
+            match caps.iter().find(|(k, _)| k == name) {
+                Some((_, v)) => out.push_str(v),
+                None => { out.push(':'); out.push_str(name); }
+            }
+            rest = &after[len..];
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+struct RedirectSet {
+    rules: Vec<RedirectRule>,
+    stamp: Option<SystemTime>,
+    checked: Instant,
+}
+
+/// The `_redirects` file of the main root (or `--redirects`), re-read when it
+/// changes. Rules are tried in order before the filesystem; the first wins.
+struct Redirects {
+    path: PathBuf,
+    set: RwLock<RedirectSet>,
+}
+
+impl Redirects {
+    fn open(path: PathBuf) -> Redirects {
+        let r = Redirects { path, set: RwLock::new(RedirectSet { rules: vec![], stamp: None, checked: Instant::now() }) };
+        r.reload(false);
+        r
+    }
+
+    fn reload(&self, announce: bool) {
+        let stamp = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        let (rules, errors) = parse_redirects(&std::fs::read_to_string(&self.path).unwrap_or_default());
+        for e in &errors {
+            eprintln!("  {} {YL}{}:{RST} {e}", ts(), self.path.display());
+        }
+        if announce {
+            eprintln!("  {} {D}redirects reloaded ({} rule{}){RST}", ts(), rules.len(), if rules.len() != 1 { "s" } else { "" });
+        }
+        *self.set.write().unwrap() = RedirectSet { rules, stamp, checked: Instant::now() };
+    }
+
+    /// Pick up edits, checking the disk at most once a second.
+    fn refresh(&self) {
+        let stale = self.set.read().unwrap().checked.elapsed() >= std::time::Duration::from_secs(1);
+        if !stale { return; }
+        let stamp = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if stamp != self.set.read().unwrap().stamp { self.reload(true); }
+        else { self.set.write().unwrap().checked = Instant::now(); }
+    }
+
+    fn count(&self) -> usize { self.set.read().unwrap().rules.len() }
+
+    /// The status and target of the first rule matching `path`.
+    fn lookup(&self, path: &str) -> Option<(u16, String)> {
+        self.refresh();
+        let set = self.set.read().unwrap();
+        set.rules.iter().find_map(|r| r.matches(path).map(|caps| (r.status, r.target(&caps))))
+    }
+}
+
+// ── Response headers ──────────────────────────────────────────────────
+
+/// Which other sites' pages may call this server from the browser. Off
+/// unless `--cors` names some origins.
+#[derive(Clone)]
+struct Cors {
+    origins: Vec<String>, // lowercase, no trailing slash; "*" for any
+    methods: String,
+    credentials: bool,
+}
+
+impl Cors {
+    /// The Access-Control-Allow-Origin value for a request from `origin`.
+    fn allow(&self, origin: &str) -> Option<hyper::header::HeaderValue> {
+        let o = origin.trim_end_matches('/').to_ascii_lowercase();
+        let any = self.origins.iter().any(|a| a == "*");
+        if !any && !self.origins.contains(&o) { return None; }
+        // Credentialed responses must name the origin, never `*`
+        if any && !self.credentials { return Some(hyper::header::HeaderValue::from_static("*")); }
+        origin.parse().ok()
+    }
+
+    fn decorate(&self, h: &mut hyper::header::HeaderMap, allow: hyper::header::HeaderValue) {
+        if allow != "*" { h.append("Vary", hyper::header::HeaderValue::from_static("Origin")); }
+        h.insert("Access-Control-Allow-Origin", allow);
+        if self.credentials {
+            h.insert("Access-Control-Allow-Credentials", hyper::header::HeaderValue::from_static("true"));
+        }
+    }
+
+    /// The answer to an OPTIONS preflight, which carries no credentials and
+    /// so is handled before auth.
+    fn preflight<B>(&self, req: &Request<B>) -> Option<Response<Full<Bytes>>> {
+        if req.method() != Method::OPTIONS || !req.headers().contains_key("access-control-request-method") { return None; }
+        let allow = self.allow(req.headers().get("origin")?.to_str().ok()?)?;
+        let mut resp = Response::builder().status(StatusCode::NO_CONTENT).body(Full::new(Bytes::new())).unwrap();
+        let h = resp.headers_mut();
+        self.decorate(h, allow);
+        h.insert("Access-Control-Allow-Methods", self.methods.parse().ok()?);
+        if let Some(asked) = req.headers().get("access-control-request-headers") {
+            h.insert("Access-Control-Allow-Headers", asked.clone());
+        }
+        h.insert("Access-Control-Max-Age", hyper::header::HeaderValue::from_static("600"));
+        Some(resp)
+    }
+}
+
+/// A Netlify-style `_headers` file: a path glob, then indented `Name: value`
+/// lines for responses under it. A trailing `/*` covers everything below.
+struct HeaderBlock {
+    pattern: String,
+    headers: Vec<(hyper::header::HeaderName, hyper::header::HeaderValue)>,
+}
+
+fn parse_headers_file(contents: &str) -> (Vec<HeaderBlock>, Vec<String>) {
+    let mut blocks: Vec<HeaderBlock> = Vec::new();
+    let mut errors = Vec::new();
+    for (n, line) in contents.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') { continue; }
+        if !line.starts_with([' ', '\t']) {
+            if !trimmed.starts_with('/') {
+                errors.push(format!("line {}: `{trimmed}` should be a path starting with /", n + 1));
+                continue;
+            }
+            blocks.push(HeaderBlock { pattern: trimmed.to_string(), headers: Vec::new() });
+            continue;
+        }
+        let Some(block) = blocks.last_mut() else {
+            errors.push(format!("line {}: header before any path", n + 1));
+            continue;
+        };
+        let parsed = trimmed.split_once(':').and_then(|(k, v)| Some((k.trim().parse().ok()?, v.trim().parse().ok()?)));
+        match parsed {
+            Some(h) => block.headers.push(h),
+            None => errors.push(format!("line {}: expected `Name: value`", n + 1)),
+        }
+    }
+    (blocks, errors)
+}
+
+fn header_path_matches(pattern: &str, path: &str) -> bool {
+    match pattern.strip_suffix('*').filter(|p| p.ends_with('/')) {
+        Some(prefix) => path.starts_with(prefix) || path == prefix.trim_end_matches('/'),
+        None => glob_match(pattern.as_bytes(), path.as_bytes()),
+    }
+}
+
+struct HeaderSet {
+    blocks: Vec<HeaderBlock>,
+    stamp: Option<SystemTime>,
+    checked: Instant,
+}
+
+/// The `_headers` file of the main root (or `--headers`), re-read when it
+/// changes. Every matching block applies, later ones winning.
+struct HeaderRules {
+    path: PathBuf,
+    set: RwLock<HeaderSet>,
+}
+
+impl HeaderRules {
+    fn open(path: PathBuf) -> HeaderRules {
+        let r = HeaderRules { path, set: RwLock::new(HeaderSet { blocks: vec![], stamp: None, checked: Instant::now() }) };
+        r.reload(false);
+        r
+    }
+
+    fn reload(&self, announce: bool) {
+        let stamp = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        let (blocks, errors) = parse_headers_file(&std::fs::read_to_string(&self.path).unwrap_or_default());
+        for e in &errors {
+            eprintln!("  {} {YL}{}:{RST} {e}", ts(), self.path.display());
+        }
+        if announce {
+            eprintln!("  {} {D}headers reloaded ({} path{}){RST}", ts(), blocks.len(), if blocks.len() != 1 { "s" } else { "" });
+        }
+        *self.set.write().unwrap() = HeaderSet { blocks, stamp, checked: Instant::now() };
+    }
+
+    /// Pick up edits, checking the disk at most once a second.
+    fn refresh(&self) {
+        let stale = self.set.read().unwrap().checked.elapsed() >= std::time::Duration::from_secs(1);
+        if !stale { return; }
+        let stamp = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if stamp != self.set.read().unwrap().stamp { self.reload(true); }
+        else { self.set.write().unwrap().checked = Instant::now(); }
+    }
+
+    fn count(&self) -> usize { self.set.read().unwrap().blocks.len() }
+
+    fn apply(&self, path: &str, h: &mut hyper::header::HeaderMap) {
+        self.refresh();
+        for b in self.set.read().unwrap().blocks.iter().filter(|b| header_path_matches(&b.pattern, path)) {
+            for (k, v) in &b.headers { h.insert(k.clone(), v.clone()); }
+        }
+    }
+}
+
+// ── Compression ───────────────────────────────────────────────────────
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Encoding { Br, Zstd, Gzip }
+impl Encoding {
+    fn token(self) -> &'static str {
+        match self { Self::Br => "br", Self::Zstd => "zstd", Self::Gzip => "gzip" }
+    }
+    /// Suffix of a precompressed sibling, e.g. app.js.br
+    fn ext(self) -> &'static str {
+        match self { Self::Br => "br", Self::Zstd => "zst", Self::Gzip => "gz" }
+    }
+}
+
+/// Bodies smaller than this go out as they are.
+const COMPRESS_MIN: usize = 1024;
+/// On-the-fly compression of anything bigger would stall the response.
+const COMPRESS_MAX: usize = 32 * 1024 * 1024;
+
+/// The encodings a client accepts, best first: by q-value, then br, zstd, gzip.
+fn accepted_encodings(header: &str) -> Vec<Encoding> {
+    let mut q = [None::<f32>; 3];
+    let mut star = None;
+    for item in header.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let weight = parts.filter_map(|p| p.trim().strip_prefix("q=")).next()
+            .map(|v| v.trim().parse().unwrap_or(0.0)).unwrap_or(1.0);
+        match name.as_str() {
+            "br" => q[0] = Some(weight),
+            "zstd" => q[1] = Some(weight),
+            "gzip" | "x-gzip" => q[2] = Some(weight),
+            "*" => star = Some(weight),
+            _ => {}
+        }
+    }
+    let mut out: Vec<(Encoding, f32)> = [Encoding::Br, Encoding::Zstd, Encoding::Gzip].into_iter().zip(q)
+        .filter_map(|(e, w)| Some((e, w.or(star)?)))
+        .filter(|(_, w)| *w > 0.0)
+        .collect();
+    out.sort_by(|a, b| b.1.total_cmp(&a.1)); // stable, so ties keep our order
+    out.into_iter().map(|(e, _)| e).collect()
+}
+
+/// Text-like types that shrink; images, video, archives and fonts other
+/// than TTF are already compressed.
+fn compressible(ctype: &str) -> bool {
+    let mime = ctype.split(';').next().unwrap_or("").trim();
+    mime.starts_with("text/")
+        || matches!(mime, "application/javascript" | "application/json" | "application/xml"
+            | "image/svg+xml" | "application/wasm" | "font/ttf" | "image/x-icon")
+}
+
+fn compress(enc: Encoding, data: &[u8]) -> Option<Vec<u8>> {
+    use std::io::Write;
+    match enc {
+        Encoding::Br => {
+            let mut out = Vec::new();
+            // Quality 5 is the usual trade-off for compressing per request
+            let params = brotli::enc::BrotliEncoderParams { quality: 5, ..Default::default() };
+            brotli::BrotliCompress(&mut &data[..], &mut out, &params).ok()?;
+            Some(out)
+        }
+        Encoding::Zstd => zstd::encode_all(data, 3).ok(),
+        Encoding::Gzip => {
+            let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            enc.write_all(data).ok()?;
+            enc.finish().ok()
+        }
+    }
+}
+
+/// Marks a response that must go out as it is: leak's own pages carry the
+/// session's CSRF token next to text from the URL, which compressed is a
+/// BREACH oracle.
+#[derive(Clone, Copy)]
+struct Uncompressed;
+
+/// The encodings `dispatch` settled on for this request, empty when
+/// compression is off or a range was asked for.
+#[derive(Clone)]
+struct AcceptEncodings(Vec<Encoding>);
+
+/// Compress a finished response for a client that accepts `encodings`.
+/// Anything already encoded, partial or not worth it is left alone.
+async fn compress_response(resp: Response<Full<Bytes>>, encodings: &[Encoding]) -> Response<Full<Bytes>> {
+    let ctype = resp.headers().get("content-type").and_then(|v| v.to_str().ok()).unwrap_or("");
+    if resp.status() != StatusCode::OK || !compressible(ctype) || resp.headers().contains_key("content-encoding")
+        || resp.extensions().get::<Uncompressed>().is_some() {
+        return resp;
+    }
+    let Some(&enc) = encodings.first() else { return resp };
+    let len = hyper::body::Body::size_hint(resp.body()).exact().unwrap_or(0);
+    if !(COMPRESS_MIN as u64..=COMPRESS_MAX as u64).contains(&len) { return resp; }
+    let (mut parts, body) = resp.into_parts();
+    parts.headers.insert("Vary", hyper::header::HeaderValue::from_static("Accept-Encoding"));
+    let Ok(data) = body.collect().await.map(|c| c.to_bytes());
+    let packed = {
+        let data = data.clone();
+        tokio::task::spawn_blocking(move || compress(enc, &data)).await.ok().flatten()
+    };
+    match packed {
+        Some(packed) if packed.len() < data.len() => {
+            parts.headers.insert("Content-Encoding", hyper::header::HeaderValue::from_static(enc.token()));
+            // Ranges would address the identity bytes, which this isn't
+            parts.headers.remove("accept-ranges");
+            Response::from_parts(parts, Full::new(Bytes::from(packed)))
+        }
+        _ => Response::from_parts(parts, Full::new(data)),
+    }
+}
+
+/// A `.br`/`.zst`/`.gz` file built next to the one at `uri_path`, in the
+/// client's order of preference. Siblings go through the same path policy.
+async fn precompressed(cfg: &ServerConfig, uri_path: &str, encodings: &[Encoding]) -> Option<(Encoding, Vec<u8>)> {
+    for &enc in encodings {
+        let Ok((_, file)) = cfg.resolve(&format!("{uri_path}.{}", enc.ext())) else { continue };
+        if file.path.is_file() {
+            if let Ok(data) = fs::read(&file.path).await {
+                return Some((enc, data));
+            }
+        }
+    }
+    None
+}
+
+// ── HTTP core ─────────────────────────────────────────────────────────
+
+fn http_response(status: StatusCode, body: impl Into<Bytes>, ctype: &str) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", ctype)
+        .header("X-Content-Type-Options", "nosniff")
+        .body(Full::new(body.into()))
+        .unwrap()
+}
+
+/// One of leak's own pages. Only its nonce'd script and style may run, so
+/// an HTML file name can't smuggle script into the listing.
+fn page_response(status: StatusCode, html: String, nonce: &str) -> Response<Full<Bytes>> {
+    let mut resp = http_response(status, html, "text/html; charset=utf-8");
+    let csp = format!(
+        "default-src 'none'; script-src 'nonce-{nonce}'; style-src 'nonce-{nonce}'; img-src 'self' data:; \
+         connect-src 'self'; form-action 'self'; base-uri 'none'; frame-ancestors 'none'"
+    );
+    resp.headers_mut().insert("Content-Security-Policy", csp.parse().unwrap());
+    resp.headers_mut().insert("Referrer-Policy", hyper::header::HeaderValue::from_static("same-origin"));
+    resp.extensions_mut().insert(Uncompressed);
+    resp
+}
+
+async fn serve<B: hyper::body::Body>(cfg: Arc<ServerConfig>, req: Request<B>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let uri_path = req.uri().path().to_string();
+    let method = req.method().clone();
+    let ip = req.extensions().get::<ClientIp>().and_then(|c| c.0);
+    let cert_user = req.extensions().get::<CertUser>().and_then(|c| c.0.clone());
+    let client = match cert_user {
+        Some(ref u) => format!("{u}@{}", peer_label(ip)),
+        None => peer_label(ip),
+    };
+
+    if let Some(ip) = ip {
+        if let Err(wait) = cfg.guard.take(ip, RouteClass::of(&method, &uri_path)) {
+            return Ok(too_many_requests(wait));
+        }
+    }
+
+    let session = request_cookie(&req, SESSION_COOKIE).and_then(|c| cfg.sessions.lookup(&c));
+    let https = req.extensions().get::<Https>().is_some_and(|h| h.0);
+
+    // Login form
+    if uri_path == "/__login" {
+        if method == Method::GET {
+            let query = req.uri().query().unwrap_or("");
+            let next = safe_next(&query_param(query, "next").unwrap_or_default()).to_string();
+            // The QR code's login link
+            if let (Some(key), Some(expected)) = (query_param(query, "key"), cfg.auth.as_deref()) {
+                if let Some(wait) = ip.and_then(|ip| cfg.guard.locked(ip)) {
+                    let msg = format!("Too many failed logins, try again in {}s", wait.as_secs().max(1));
+                    return Ok(login_response(StatusCode::TOO_MANY_REQUESTS, &next, Some(&msg), None));
+                }
+                if !ct_eq(key.as_bytes(), cfg.sessions.link_key(expected).as_bytes()) {
+                    if let Some(ip) = ip { cfg.guard.login_failed(ip); }
+                    return Ok(login_response(StatusCode::UNAUTHORIZED, &next, Some("That login link is no longer valid"), None));
+                }
+                // The link stands in for the password only; enrolled users still owe a code
+                let user = auth_user(expected).unwrap_or_default();
+                if cfg.totp.as_ref().is_some_and(|t| t.enrolled(&user)) {
+                    let id = cfg.sessions.challenge(expected, &user);
+                    return Ok(login_response(StatusCode::OK, &next, None, Some(&id)));
+                }
+                if let Some(ip) = ip { cfg.guard.login_ok(ip); }
+                eprintln!("  {} {GR}{B}LOGIN{RST} {CY}{}{RST} {D}{} (link){RST}", ts(), user, client);
+                let value = cfg.sessions.login(session.as_ref(), expected);
+                return Ok(session_redirect(&next, Some(cfg.sessions.cookie(Some(&value), https))));
+            }
+            return Ok(login_response(StatusCode::OK, &next, None, None));
+        }
+        if method != Method::POST {
+            return Ok(http_response(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed", "text/plain"));
+        }
+        if !same_origin(&req) {
+            return Ok(http_response(StatusCode::FORBIDDEN, "Cross-site login refused", "text/plain"));
+        }
+        let form = match req.collect().await {
+            Ok(c) => String::from_utf8_lossy(&c.to_bytes()).into_owned(),
+            Err(_) => return Ok(http_response(StatusCode::BAD_REQUEST, "Read failed", "text/plain")),
+        };
+        let field = |k| query_param(&form, k).unwrap_or_default();
+        let next = safe_next(&field("next")).to_string();
+        let next_path = next.split(['?', '#']).next().unwrap_or("/");
+        let (mount, _) = cfg.mount_for(&decode_request_path(next_path).unwrap_or_default());
+        let expected = match cfg.required_auth(mount) {
+            Some(e) => e,
+            None => return Ok(session_redirect(&next, None)),
+        };
+        if let Some(wait) = ip.and_then(|ip| cfg.guard.locked(ip)) {
+            let msg = format!("Too many failed logins, try again in {}s", wait.as_secs().max(1));
+            return Ok(login_response(StatusCode::TOO_MANY_REQUESTS, &next, Some(&msg), None));
+        }
+        let challenge = field("challenge");
+        let user = if challenge.is_empty() {
+            let user = field("user");
+            let given = base64::engine::general_purpose::STANDARD.encode(format!("{user}:{}", field("pass")));
+            if !ct_eq(given.as_bytes(), expected.as_bytes()) {
+                if let Some(ip) = ip { cfg.guard.login_failed(ip); }
+                return Ok(login_response(StatusCode::UNAUTHORIZED, &next, Some("Wrong username or password"), None));
+            }
+            // The password was right; enrolled users still owe a code
+            if cfg.totp.as_ref().is_some_and(|t| t.enrolled(&user)) {
+                let id = cfg.sessions.challenge(expected, &user);
+                return Ok(login_response(StatusCode::OK, &next, None, Some(&id)));
+            }
+            user
+        } else {
+            let user = match cfg.sessions.pending(&challenge, expected) {
+                Some(u) => u,
+                None => return Ok(login_response(StatusCode::UNAUTHORIZED, &next, Some("That took too long, please log in again"), None)),
+            };
+            if !cfg.totp.as_ref().is_some_and(|t| t.verify(&user, &field("code"))) {
+                if let Some(ip) = ip { cfg.guard.login_failed(ip); }
+                return Ok(match cfg.sessions.retry_challenge(&challenge) {
+                    true => login_response(StatusCode::UNAUTHORIZED, &next, Some("Wrong code"), Some(&challenge)),
+                    false => login_response(StatusCode::UNAUTHORIZED, &next, Some("Too many wrong codes, please log in again"), None),
+                });
+            }
+            cfg.sessions.finish_challenge(&challenge);
+            user
+        };
+        if let Some(ip) = ip { cfg.guard.login_ok(ip); }
+        eprintln!("  {} {GR}{B}LOGIN{RST} {CY}{}{RST} {D}{}{RST}", ts(), user, client);
+        let value = cfg.sessions.login(session.as_ref(), expected);
+        return Ok(session_redirect(&next, Some(cfg.sessions.cookie(Some(&value), https))));
+    }
+
+    // Logout button
+    if method == Method::POST && uri_path == "/__logout" {
+        let form = match req.collect().await {
+            Ok(c) => String::from_utf8_lossy(&c.to_bytes()).into_owned(),
+            Err(_) => return Ok(http_response(StatusCode::BAD_REQUEST, "Read failed", "text/plain")),
+        };
+        if let Some(s) = session {
+            let token = query_param(&form, "csrf").unwrap_or_default();
+            if !ct_eq(token.as_bytes(), s.csrf.as_bytes()) {
+                return Ok(http_response(StatusCode::FORBIDDEN, "Missing or stale CSRF token", "text/plain"));
+            }
+            cfg.sessions.logout(&s.id);
+        }
+        return Ok(session_redirect("/", Some(cfg.sessions.cookie(None, https))));
+    }
+
+    // API tokens stand in for any password, but only within their scope
+    let grant = match bearer_token(&req).filter(|_| cfg.tokens.is_some()) {
+        Some(token) => {
+            if let Some(wait) = ip.and_then(|ip| cfg.guard.locked(ip)) {
+                return Ok(too_many_requests(wait));
+            }
+            match cfg.tokens.as_ref().and_then(|t| t.verify(token)) {
+                Some(g) => Some(g),
+                None => {
+                    if let Some(ip) = ip { cfg.guard.login_failed(ip); }
+                    return Ok(http_response(StatusCode::UNAUTHORIZED, "Invalid or expired token", "text/plain"));
+                }
+            }
+        }
+        None => None,
+    };
+    if let Some(ref g) = grant {
+        let rel = decode_request_path(&uri_path).unwrap_or_default();
+        if !g.perm.allows(RouteClass::of(&method, &uri_path)) || !g.covers(&rel) {
+            return Ok(http_response(StatusCode::FORBIDDEN, "Token not valid here", "text/plain"));
+        }
+    }
+    let in_scope = |rel: &str| grant.as_ref().is_none_or(|g| g.covers(rel));
+
+    // Users enrolled in 2FA can't get by on a password alone
+    let needs_code = |expected: &str| {
+        cfg.totp.as_ref().is_some_and(|t| auth_user(expected).is_some_and(|u| t.enrolled(&u)))
+    };
+    // A session covers every mount whose password it was opened with, a client
+    // certificate every mount whose user it names; Basic still works for curl
+    let authorized = |expected: &str| {
+        grant.is_some()
+            || session.as_ref().is_some_and(|s| s.creds.contains(expected))
+            || cert_user.is_some() && cert_user == auth_user(expected)
+            || check_auth(&req, expected) && !needs_code(expected)
+    };
+
+    // Auth check, against the mount the request is for
+    let (mount, _) = cfg.mount_for(&decode_request_path(&uri_path).unwrap_or_default());
+    if let Some(expected) = cfg.required_auth(mount) {
+        // A locked-out address is refused even with the right password
+        if let Some(wait) = ip.and_then(|ip| cfg.guard.locked(ip)) {
+            return Ok(too_many_requests(wait));
+        }
+        if !authorized(expected) {
+            let password_ok = check_auth(&req, expected);
+            // Browsers ask without credentials first; only wrong ones count
+            if let (Some(ip), true, false) = (ip, req.headers().contains_key("authorization"), password_ok) {
+                cfg.guard.login_failed(ip);
+            }
+            if wants_login_page(&req) {
+                let here = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+                return Ok(login_response(StatusCode::UNAUTHORIZED, here, None, None));
+            }
+            if password_ok {
+                return Ok(http_response(StatusCode::UNAUTHORIZED, "This user logs in with a second factor; use the login page or an API token", "text/plain"));
+            }
+            return Ok(auth_required_response());
+        }
+        // Only a password checked just now clears the record; sessions,
+        // tokens and certificates didn't prove anything on this request
+        let by_password = grant.is_none()
+            && !session.as_ref().is_some_and(|s| s.creds.contains(expected))
+            && !(cert_user.is_some() && cert_user == auth_user(expected));
+        if let (Some(ip), true) = (ip, by_password) { cfg.guard.login_ok(ip); }
+    }
+    // Mounts this request may see, for handlers that span several of them
+    let allowed: HashSet<String> = cfg.mounts.iter()
+        .filter(|m| cfg.required_auth(m).map(authorized).unwrap_or(true))
+        .map(|m| m.prefix.clone())
+        .collect();
+
+    // The session cookie rides along on cross-site requests; the page's token doesn't
+    if let (Some(s), &Method::POST) = (&session, &method) {
+        let token = req.headers().get("x-csrf-token").and_then(|v| v.to_str().ok()).unwrap_or("");
+        if !req.headers().contains_key("authorization") && !ct_eq(token.as_bytes(), s.csrf.as_bytes()) {
+            return Ok(http_response(StatusCode::FORBIDDEN, "Missing or stale CSRF token", "text/plain"));
+        }
+    }
+
+    // Upload handler
+    if method == Method::POST && uri_path.ends_with("/__upload") {
+        let dir_uri = uri_path.trim_end_matches("/__upload");
+        let dir_uri = if dir_uri.is_empty() { "/" } else { dir_uri };
+        let (mount, dir) = match cfg.resolve(dir_uri) {
+            Ok(r) => r,
+            Err(e) => return Ok(http_response(e.status(), e.message(), "text/plain")),
+        };
+        if !dir.real.is_dir() {
+            return Ok(http_response(StatusCode::BAD_REQUEST, "Not a directory", "text/plain"));
+        }
+        if !mount.upload {
+            return Ok(http_response(StatusCode::FORBIDDEN, "Uploads are disabled here", "text/plain"));
+        }
+
+        let boundary = match get_boundary(&req) {
+            Some(b) => b,
+            None => return Ok(http_response(StatusCode::BAD_REQUEST, "Missing boundary", "text/plain")),
+        };
+
+        let upload_start = Instant::now();
+        let body_bytes = match req.collect().await {
+            Ok(c) => c.to_bytes(),
+            Err(_) => return Ok(http_response(StatusCode::BAD_REQUEST, "Read failed", "text/plain")),
+        };
+        if body_bytes.len() > 500 * 1024 * 1024 {
+            return Ok(http_response(StatusCode::PAYLOAD_TOO_LARGE, "500MB max", "text/plain"));
+        }
+
+        let files = parse_multipart(&body_bytes, &boundary);
+        if files.is_empty() {
+            return Ok(http_response(StatusCode::BAD_REQUEST, "No file in upload", "text/plain"));
+        }
+
+        let elapsed_ms = upload_start.elapsed().as_millis() as u64;
 
         for file in &files {
             let safe: String = file.filename.chars()
                 .map(|c| if c.is_alphanumeric() || c == '.' || c == '-' || c == '_' || c == ' ' { c } else { '_' })
                 .collect();
             if safe.is_empty() || safe == "." || safe == ".." { continue; }
-            let dest = canonical.join(&safe);
-            if let Ok(parent) = dest.parent().unwrap_or(&canonical).canonicalize() {
-                if !parent.starts_with(root) { continue; }
+            let rel = if dir.rel.is_empty() { safe.clone() } else { format!("{}/{safe}", dir.rel) };
+            if mount.hidden.is_hidden(&rel, false) { continue; }
+            // Never write through an existing symlink the policy would refuse to serve
+            let dest = dir.path.join(&safe);
+            if dest.symlink_metadata().is_ok() {
+                match resolve_path(&mount.root, &rel, cfg.symlinks) {
+                    Ok(r) if !mount.hidden.hides_path(&mount.root, &r.real) => {}
+                    _ => continue,
+                }
+            }
+            if let Ok(mut f) = tokio::fs::File::create(&dest).await {
+                let _ = f.write_all(&file.data).await;
+                let speed = format_speed(file.data.len() as u64, elapsed_ms);
+                eprintln!(
+                    "  {} {BL}{B}UPLOAD{RST} {CY}{}{RST} {D}({} at {}) {}{RST}",
+                    ts(), safe, format_size(file.data.len() as u64), speed, client,
+                );
+            }
+        }
+        return Ok(http_response(StatusCode::OK, "OK", "text/plain"));
+    }
+
+    // Download handler (multi-file ZIP)
+    if method == Method::POST && uri_path.ends_with("/__download") {
+        let body_bytes = match req.collect().await {
+            Ok(c) => c.to_bytes(),
+            Err(_) => return Ok(http_response(StatusCode::BAD_REQUEST, "Read failed", "text/plain")),
+        };
+        let body_str = String::from_utf8_lossy(&body_bytes);
+        let mut paths = extract_json_string_array(&body_str, "files");
+        paths.retain(|p| in_scope(&decode_request_path(p).unwrap_or_default()));
+        if paths.is_empty() {
+            return Ok(http_response(StatusCode::BAD_REQUEST, "No files specified", "text/plain"));
+        }
+
+        let cfg_zip = cfg.clone();
+        let zip_result = tokio::task::spawn_blocking(move || {
+            let buf = std::io::Cursor::new(Vec::new());
+            let mut zip = zip::ZipWriter::new(buf);
+            let opts = SimpleFileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated);
+
+            for path_str in &paths {
+                let (mount, r) = match cfg_zip.resolve(path_str) {
+                    Ok((m, r)) if allowed.contains(&m.prefix) => (m, r),
+                    _ => continue,
+                };
+                // Use the last path component as the archive entry name
+                let arc_name = match r.path.file_name() {
+                    Some(n) if !r.rel.is_empty() => n.to_string_lossy().to_string(),
+                    _ if !mount.prefix.is_empty() => mount.prefix.clone(),
+                    _ => continue,
+                };
+                let _ = add_path_to_zip(&mut zip, &r.path, &arc_name, &mount.root, &mount.hidden, cfg_zip.symlinks, opts);
+            }
+
+            zip.finish().map(|c| c.into_inner())
+        }).await;
+
+        match zip_result {
+            Ok(Ok(data)) => {
+                let size = data.len();
+                eprintln!(
+                    "  {} {GR}{B}DOWNLOAD{RST} {CY}ZIP{RST} {D}({}) {}{RST}",
+                    ts(), format_size(size as u64), client,
+                );
+                return Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", "application/zip")
+                    .header("Content-Disposition", "attachment; filename=\"leak-download.zip\"")
+                    .body(Full::new(Bytes::from(data)))
+                    .unwrap());
+            }
+            _ => return Ok(http_response(StatusCode::INTERNAL_SERVER_ERROR, "ZIP creation failed", "text/plain")),
+        }
+    }
+
+    // Content search (JSON)
+    if method == Method::GET && uri_path.ends_with("/__search") {
+        let scope = match decode_request_path(uri_path.trim_end_matches("/__search")) {
+            Ok(s) => s,
+            Err(e) => return Ok(http_response(e.status(), e.message(), "text/plain")),
+        };
+        let q = req.uri().query().and_then(|q| query_param(q, "q")).unwrap_or_default();
+        // The top level searches every mount; below it, only the mount the scope is in
+        let targets: Vec<(&Mount, String)> = if scope.is_empty() {
+            cfg.mounts.iter().map(|m| (m, String::new())).collect()
+        } else {
+            vec![cfg.mount_for(&scope)]
+        };
+        let mut hits: Vec<(&str, SearchHit)> = Vec::new();
+        let (mut total, mut indexing, mut enabled) = (0, false, false);
+        for (m, sub) in targets {
+            let search = match m.search { Some(ref s) => s, None => continue };
+            enabled = true;
+            if !allowed.contains(&m.prefix) { continue; }
+            let visible = |p: &str| {
+                !m.hidden.is_hidden(p, false) && in_scope(&if m.prefix.is_empty() { p.to_string() } else { format!("{}/{p}", m.prefix) })
+            };
+            let (h, t) = search.index.read().unwrap().search(&q, &sub, 50, visible);
+            total += t;
+            indexing |= !search.ready.load(Ordering::Relaxed);
+            hits.extend(h.into_iter().map(|h| (m.prefix.as_str(), h)));
+        }
+        if !enabled {
+            return Ok(http_response(StatusCode::NOT_FOUND, "Content index disabled (start with --index)", "text/plain"));
+        }
+        hits.sort_by(|a, b| b.1.score.total_cmp(&a.1.score));
+        hits.truncate(50);
+        let results: Vec<String> = hits.iter().map(|(prefix, h)| {
+            let path = if prefix.is_empty() { h.path.clone() } else { format!("{prefix}/{}", h.path) };
+            let href: Vec<String> = path.split('/').map(percent_encode).collect();
+            format!(
+                r#"{{"path":"/{}","href":"/{}","score":{:.3},"snippet":"{}"}}"#,
+                json_escape(&path), href.join("/"), h.score, json_escape(&h.snippet),
+            )
+        }).collect();
+        let body = format!(
+            r#"{{"query":"{}","total":{total},"indexing":{indexing},"results":[{}]}}"#,
+            json_escape(&q), results.join(","),
+        );
+        return Ok(http_response(StatusCode::OK, body, "application/json; charset=utf-8"));
+    }
+
+    // Redirect and rewrite rules come before the filesystem
+    let uri_path = match cfg.redirects.lookup(&uri_path) {
+        Some((200, target)) => {
+            let target = target.split(['?', '#']).next().unwrap_or("/").to_string();
+            // A rewrite can't reach what the request couldn't
+            let rel = decode_request_path(&target).unwrap_or_default();
+            if !allowed.contains(&cfg.mount_for(&rel).0.prefix) || !in_scope(&rel) {
+                return Ok(http_response(StatusCode::FORBIDDEN, "403 Forbidden", "text/plain; charset=utf-8"));
+            }
+            target
+        }
+        Some((status, mut target)) => {
+            if let (Some(q), false) = (req.uri().query(), target.contains('?')) {
+                target = format!("{target}?{q}");
+            }
+            return Ok(Response::builder()
+                .status(status)
+                .header("Location", target)
+                .body(Full::new(Bytes::new()))
+                .unwrap());
+        }
+        None => uri_path,
+    };
+
+    // GET handler
+    let (uri_path, (mount, file)) = match cfg.resolve(&uri_path) {
+        Ok(r) => (uri_path, r),
+        Err(ResolveError::NotFound) => match static_fallback(&cfg, &uri_path) {
+            Some(r) => r,
+            None => return Ok(not_found(&cfg, &uri_path).await),
+        },
+        Err(e) => return Ok(http_response(e.status(), e.message(), "text/plain; charset=utf-8")),
+    };
+    let canonical = file.path;
+
+    if canonical.is_dir() {
+        // Relative links in an index.html only work from inside the directory.
+        // A leading `//` would make both the Location and the listing's links
+        // point off-site, so those are redirected to the clean path too.
+        if !uri_path.ends_with('/') || uri_path.starts_with("//") {
+            let query = req.uri().query().map(|q| format!("?{q}")).unwrap_or_default();
+            let rel = decode_request_path(&uri_path).unwrap_or_default();
+            let path: Vec<String> = rel.split('/').filter(|s| !s.is_empty()).map(percent_encode).collect();
+            let location = if path.is_empty() { "/".to_string() } else { format!("/{}/", path.join("/")) };
+            return Ok(Response::builder()
+                .status(StatusCode::MOVED_PERMANENTLY)
+                .header("Location", format!("{location}{query}"))
+                .body(Full::new(Bytes::new()))
+                .unwrap());
+        }
+        let index = canonical.join("index.html");
+        if index.exists() {
+            if let Ok(contents) = fs::read(&index).await {
+                return Ok(http_response(StatusCode::OK, contents, "text/html; charset=utf-8"));
+            }
+        }
+        let nonce = random_token();
+        let html = render_directory(&cfg, mount, &canonical, &uri_path, session.as_ref(), &nonce, &allowed).await;
+        return Ok(page_response(StatusCode::OK, html, &nonce));
+    }
+
+    let len = match fs::metadata(&canonical).await {
+        Ok(m) => m.len(),
+        Err(_) => return Ok(http_response(StatusCode::NOT_FOUND, format!("404 Not Found: {uri_path}"), "text/plain; charset=utf-8")),
+    };
+    let range = req.headers().get(hyper::header::RANGE).and_then(|v| v.to_str().ok()).and_then(|v| parse_range(v, len));
+    match range {
+        Some(Ok((start, end))) => match read_slice(&canonical, start, end - start + 1).await {
+            Ok(contents) => {
+                let mut resp = http_response(StatusCode::PARTIAL_CONTENT, contents, content_type(&canonical));
+                resp.headers_mut().insert("Content-Range", format!("bytes {start}-{end}/{len}").parse().unwrap());
+                resp.headers_mut().insert("Accept-Ranges", hyper::header::HeaderValue::from_static("bytes"));
+                Ok(resp)
+            }
+            Err(_) => Ok(http_response(StatusCode::NOT_FOUND, format!("404 Not Found: {uri_path}"), "text/plain; charset=utf-8")),
+        },
+        Some(Err(())) => {
+            let mut resp = http_response(StatusCode::RANGE_NOT_SATISFIABLE, "Range not satisfiable", "text/plain");
+            resp.headers_mut().insert("Content-Range", format!("bytes */{len}").parse().unwrap());
+            Ok(resp)
+        }
+        None => {
+            let encodings = req.extensions().get::<AcceptEncodings>().map(|e| e.0.clone()).unwrap_or_default();
+            if let Some((enc, contents)) = precompressed(&cfg, &uri_path, &encodings).await {
+                let mut resp = http_response(StatusCode::OK, contents, content_type(&canonical));
+                resp.headers_mut().insert("Content-Encoding", hyper::header::HeaderValue::from_static(enc.token()));
+                resp.headers_mut().insert("Vary", hyper::header::HeaderValue::from_static("Accept-Encoding"));
+                return Ok(resp);
+            }
+            match fs::read(&canonical).await {
+                Ok(contents) => {
+                    let mut resp = http_response(StatusCode::OK, contents, content_type(&canonical));
+                    resp.headers_mut().insert("Accept-Ranges", hyper::header::HeaderValue::from_static("bytes"));
+                    Ok(resp)
+                }
+                Err(_) => Ok(http_response(StatusCode::NOT_FOUND, format!("404 Not Found: {uri_path}"), "text/plain; charset=utf-8")),
+            }
+        }
+    }
+}
+
+/// What a static site host serves for a path that doesn't exist: `/about`
+/// from about.html, then under `--spa` the mount's index.html for anything
+/// that doesn't look like a file. Returns the path actually served.
+fn static_fallback<'a>(cfg: &'a ServerConfig, uri_path: &str) -> Option<(String, (&'a Mount, Resolved))> {
+    let last = uri_path.rsplit('/').next().unwrap_or("");
+    if last.is_empty() || last.contains('.') { return None; }
+    let mut candidates = vec![format!("{uri_path}.html")];
+    if cfg.spa {
+        let (mount, _) = cfg.mount_for(&decode_request_path(uri_path).ok()?);
+        candidates.push(if mount.prefix.is_empty() { "/index.html".to_string() } else { format!("/{}/index.html", mount.prefix) });
+    }
+    candidates.into_iter().find_map(|p| match cfg.resolve(&p) {
+        Ok(r) if r.1.path.is_file() => Some((p, r)),
+        _ => None,
+    })
+}
+
+/// The mount's own 404.html if it has one, else a plain message.
+async fn not_found(cfg: &ServerConfig, uri_path: &str) -> Response<Full<Bytes>> {
+    let page = decode_request_path(uri_path).ok().and_then(|rel| {
+        let (mount, _) = cfg.mount_for(&rel);
+        let p = if mount.prefix.is_empty() { "/404.html".to_string() } else { format!("/{}/404.html", mount.prefix) };
+        cfg.resolve(&p).ok().map(|(_, f)| f.path)
+    });
+    if let Some(page) = page {
+        if let Ok(contents) = fs::read(&page).await {
+            return http_response(StatusCode::NOT_FOUND, contents, "text/html; charset=utf-8");
+        }
+    }
+    http_response(StatusCode::NOT_FOUND, format!("404 Not Found: {uri_path}"), "text/plain; charset=utf-8")
+}
+
+/// Parses a single `bytes=` range against a file of `len` bytes into an
+/// inclusive (start, end). None means serve the whole file (no header, or a
+/// multi-range we don't support); Err means 416.
+fn parse_range(header: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = header.trim().strip_prefix("bytes=")?.trim();
+    if spec.contains(',') { return None; }
+    let (a, b) = spec.split_once('-')?;
+    let (a, b) = (a.trim(), b.trim());
+    let range = if a.is_empty() {
+        // Suffix range: the last N bytes
+        let n: u64 = b.parse().ok()?;
+        if n == 0 || len == 0 { return Some(Err(())); }
+        (len.saturating_sub(n), len - 1)
+    } else {
+        let start: u64 = a.parse().ok()?;
+        let end = if b.is_empty() { len.saturating_sub(1) } else { b.parse::<u64>().ok()?.min(len.saturating_sub(1)) };
+        if start >= len || end < start { return Some(Err(())); }
+        (start, end)
+    };
+    Some(Ok(range))
+}
+
+async fn read_slice(path: &Path, start: u64, count: u64) -> std::io::Result<Vec<u8>> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+    let mut f = fs::File::open(path).await?;
+    f.seek(std::io::SeekFrom::Start(start)).await?;
+    let mut buf = Vec::with_capacity(count as usize);
+    f.take(count).read_to_end(&mut buf).await?;
+    Ok(buf)
+}
+
+// ── Tunnel ────────────────────────────────────────────────────────────
+
+#[derive(Clone, Copy)]
+enum TunnelProvider { Localtunnel, Cloudflared, Serveo }
+impl TunnelProvider {
+    fn name(&self) -> &'static str {
+        match self { Self::Localtunnel=>"localtunnel", Self::Cloudflared=>"cloudflared", Self::Serveo=>"serveo" }
+    }
+    fn parse(s: &str) -> Option<Self> {
+        match s { "localtunnel" | "lt" => Some(Self::Localtunnel), "cloudflared" => Some(Self::Cloudflared), "serveo" => Some(Self::Serveo), _ => None }
+    }
+    fn command(&self) -> &'static str {
+        match self { Self::Localtunnel=>"lt", Self::Cloudflared=>"cloudflared", Self::Serveo=>"ssh" }
+    }
+}
+
+async fn which(cmd: &str) -> bool {
+    Command::new("which").arg(cmd).stdout(Stdio::null()).stderr(Stdio::null())
+        .status().await.map(|s| s.success()).unwrap_or(false)
+}
+
+async fn detect_tunnel(preferred: Option<TunnelProvider>) -> Option<TunnelProvider> {
+    if let Some(p) = preferred { return which(p.command()).await.then_some(p); }
+    if which("lt").await { Some(TunnelProvider::Localtunnel) }
+    else if which("cloudflared").await { Some(TunnelProvider::Cloudflared) }
+    else if which("ssh").await { Some(TunnelProvider::Serveo) }
+    else { None }
+}
+
+async fn start_tunnel(prov: &TunnelProvider, port: u16) -> Option<(tokio::process::Child, tokio::sync::oneshot::Receiver<String>)> {
+    let (tx, rx) = tokio::sync::oneshot::channel::<String>();
+    let tx = std::sync::Mutex::new(Some(tx));
+
+    macro_rules! drain {
+        ($stream:expr) => {
+            if let Some(s) = $stream {
+                tokio::spawn(async move { let mut l = BufReader::new(s).lines(); while let Ok(Some(_)) = l.next_line().await {} });
+            }
+        }
+    }
+    macro_rules! scan_url {
+        ($stream:expr, $pattern:expr) => {{
+            let s = $stream?;
+            let tx = tx;
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(s).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if let Some(url) = line.split_whitespace().find(|w| w.contains($pattern)) {
+                        if let Some(sender) = tx.lock().unwrap().take() { let _ = sender.send(url.to_string()); }
+                    }
+                }
+            });
+        }}
+    }
+
+    match prov {
+        TunnelProvider::Localtunnel => {
+            let mut c = Command::new("lt").arg("--port").arg(port.to_string())
+                .stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().ok()?;
+            let stdout = c.stdout.take(); let stderr = c.stderr.take();
+            scan_url!(stdout, "https://");
+            drain!(stderr);
+            Some((c, rx))
+        }
+        TunnelProvider::Cloudflared => {
+            let mut c = Command::new("cloudflared").arg("tunnel").arg("--url").arg(format!("http://127.0.0.1:{port}"))
+                .stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().ok()?;
+            let stderr = c.stderr.take(); let stdout = c.stdout.take();
+            scan_url!(stderr, ".trycloudflare.com");
+            drain!(stdout);
+            Some((c, rx))
+        }
+        TunnelProvider::Serveo => {
+            let mut c = Command::new("ssh").arg("-o").arg("StrictHostKeyChecking=no")
+                .arg("-o").arg("ServerAliveInterval=60")
+                .arg("-R").arg(format!("80:localhost:{port}")).arg("serveo.net")
+                .stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().ok()?;
+            let stdout = c.stdout.take(); let stderr = c.stderr.take();
+            scan_url!(stdout, "https://");
+            drain!(stderr);
+            Some((c, rx))
+        }
+    }
+}
+
+// ── TLS ───────────────────────────────────────────────────────────────
+
+/// One self-signed certificate for every TLS listener, TCP and QUIC alike;
+/// callers set the ALPN list for their transport.
+fn generate_self_signed_tls(client_ca: Option<&Path>) -> Result<rustls::ServerConfig, Box<dyn std::error::Error>> {
+    let cert_params = rcgen::CertificateParams::new(vec!["localhost".to_string()])?;
+    let key_pair = rcgen::KeyPair::generate()?;
+    let cert = cert_params.self_signed(&key_pair)?;
+
+    let cert_der = cert.der().clone();
+    let key_der = key_pair.serialize_der();
+
+    let certs = vec![rustls::pki_types::CertificateDer::from(cert_der.to_vec())];
+    let key = rustls::pki_types::PrivateKeyDer::try_from(key_der)
+        .map_err(|e| format!("key error: {e}"))?;
+
+    let builder = rustls::ServerConfig::builder();
+    let config = match client_ca {
+        Some(path) => builder.with_client_cert_verifier(client_verifier(path)?),
+        None => builder.with_no_client_auth(),
+    }.with_single_cert(certs, key)?;
+    Ok(config)
+}
+
+/// Clients must present a certificate issued by one of the CAs in `path`.
+fn client_verifier(path: &Path) -> Result<Arc<dyn rustls::server::danger::ClientCertVerifier>, Box<dyn std::error::Error>> {
+    let pem = std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut &pem[..]) {
+        roots.add(cert?)?;
+    }
+    if roots.is_empty() {
+        return Err(format!("{}: no certificates found", path.display()).into());
+    }
+    Ok(rustls::server::WebPkiClientVerifier::builder(Arc::new(roots)).build()?)
+}
+
+/// One DER element: (tag, contents, what follows).
+fn der_next(buf: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = buf.split_first()?;
+    let (&first, mut rest) = rest.split_first()?;
+    let len = if first < 0x80 {
+        first as usize
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || rest.len() < n { return None; }
+        let len = rest[..n].iter().fold(0usize, |acc, b| acc << 8 | *b as usize);
+        rest = &rest[n..];
+        len
+    };
+    if rest.len() < len { return None; }
+    Some((tag, &rest[..len], &rest[len..]))
+}
+
+fn der_items(mut buf: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut items = Vec::new();
+    while let Some((tag, body, rest)) = der_next(buf) {
+        items.push((tag, body));
+        buf = rest;
+    }
+    items
+}
+
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+/// Who a client certificate names: the subject CN, else its first email,
+/// DNS or URI subject alternative name.
+fn cert_username(der: &[u8]) -> Option<String> {
+    let (_, cert, _) = der_next(der)?;
+    let (_, tbs, _) = der_next(cert)?;
+    let fields = der_items(tbs);
+    // version [0] is optional; then serial, signature, issuer, validity, subject
+    let skip = usize::from(fields.first()?.0 == 0xa0);
+    let subject = fields.get(skip + 4)?.1;
+    for (_, rdn) in der_items(subject) {
+        for (_, attr) in der_items(rdn) {
+            if let [(0x06, oid), (_, value)] = der_items(attr)[..] {
+                if oid == OID_COMMON_NAME {
+                    return String::from_utf8(value.to_vec()).ok().filter(|s| !s.is_empty());
+                }
             }
-            if let Ok(mut f) = tokio::fs::File::create(&dest).await {
-                let _ = f.write_all(&file.data).await;
-                let speed = format_speed(file.data.len() as u64, elapsed_ms);
-                eprintln!(
-                    "  {} {BL}{B}UPLOAD{RST} {CY}{}{RST} {D}({} at {}){RST}",
-                    ts(), safe, format_size(file.data.len() as u64), speed,
-                );
+        }
+    }
+    let (_, exts) = fields.iter().find(|f| f.0 == 0xa3)?;
+    let (_, exts, _) = der_next(exts)?;
+    for (_, ext) in der_items(exts) {
+        let parts = der_items(ext);
+        if parts.first().map(|p| p.1) != Some(OID_SUBJECT_ALT_NAME) { continue; }
+        // skip the optional `critical` flag
+        let (_, value) = parts.iter().find(|p| p.0 == 0x04)?;
+        let (_, names, _) = der_next(value)?;
+        return der_items(names).into_iter()
+            .find(|n| matches!(n.0, 0x81 | 0x82 | 0x86))
+            .and_then(|(_, name)| String::from_utf8(name.to_vec()).ok());
+    }
+    None
+}
+
+/// The verified client certificate's name, for ACLs and logs.
+#[derive(Clone)]
+struct CertUser(Option<String>);
+
+/// The user name in base64 "user:pass" credentials.
+fn auth_user(b64: &str) -> Option<String> {
+    let plain = base64::engine::general_purpose::STANDARD.decode(b64).ok()?;
+    let plain = String::from_utf8(plain).ok()?;
+    plain.split_once(':').map(|(u, _)| u.to_string())
+}
+
+// ── Local CA ──────────────────────────────────────────────────────────
+
+const CA_NAME: &str = "leak local CA";
+
+/// The CA's identity. Only the key is secret; rebuilding these params and
+/// self-signing again gives an issuer that chains to the saved `ca.pem`.
+fn ca_params() -> rcgen::CertificateParams {
+    let mut params = rcgen::CertificateParams::default();
+    params.distinguished_name.push(rcgen::DnType::CommonName, CA_NAME);
+    params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    params.key_usages = vec![rcgen::KeyUsagePurpose::KeyCertSign, rcgen::KeyUsagePurpose::CrlSign];
+    params
+}
+
+/// Load the local CA from `dir`, creating it on first use.
+fn load_or_create_ca(dir: &Path) -> Result<(rcgen::Certificate, rcgen::KeyPair), String> {
+    let key_file = dir.join("ca.key");
+    let err = |e: &dyn std::fmt::Display| e.to_string();
+    if let Ok(pem) = std::fs::read_to_string(&key_file) {
+        let key = rcgen::KeyPair::from_pem(&pem).map_err(|e| format!("{}: {e}", key_file.display()))?;
+        let cert = ca_params().self_signed(&key).map_err(|e| err(&e))?;
+        return Ok((cert, key));
+    }
+    let key = rcgen::KeyPair::generate().map_err(|e| err(&e))?;
+    let cert = ca_params().self_signed(&key).map_err(|e| err(&e))?;
+    write_private(&key_file, &key.serialize_pem())?;
+    std::fs::write(dir.join("ca.pem"), cert.pem()).map_err(|e| format!("{}: {e}", dir.display()))?;
+    eprintln!("  {GR}●{RST} created {B}{CA_NAME}{RST} {D}in {}{RST}", dir.display());
+    Ok((cert, key))
+}
+
+/// `leak ca issue-client <name>`
+fn ca_command(raw: &[String]) {
+    let die = |msg: String| -> ! { eprintln!("{RD}{B}Error:{RST} {msg}"); std::process::exit(1); };
+    let dir = flag_value(raw, "--ca-dir").map(|d| expand_home(Path::new(d)))
+        .or_else(|| default_config_dir().map(|d| d.join("ca")))
+        .unwrap_or_else(|| die("no home directory; pass --ca-dir".to_string()));
+    let mut positional = Vec::new();
+    let mut rest = raw.iter();
+    while let Some(a) = rest.next() {
+        if ["--ca-dir", "--email", "--days", "--out"].contains(&a.as_str()) { rest.next(); } else { positional.push(a.as_str()); }
+    }
+    let name = match positional[..] {
+        ["issue-client", name] => name,
+        _ => {
+            eprintln!("{B}Usage:{RST}");
+            eprintln!("  leak ca issue-client <name> [--email addr] [--days 365] [--out dir] [--ca-dir dir]");
+            eprintln!("  {D}The CA lives in ~/.config/leak/ca; serve with --client-ca ~/.config/leak/ca/ca.pem{RST}");
+            std::process::exit(2);
+        }
+    };
+    let days: i64 = match flag_value(raw, "--days") {
+        Some(d) => d.parse().ok().filter(|d| *d > 0).unwrap_or_else(|| die(format!("--days expects a number, got {d}"))),
+        None => 365,
+    };
+    let out = flag_value(raw, "--out").map(|d| expand_home(Path::new(d))).unwrap_or_else(|| PathBuf::from("."));
+
+    let (ca, ca_key) = load_or_create_ca(&dir).unwrap_or_else(|e| die(e));
+    let mut params = rcgen::CertificateParams::default();
+    params.distinguished_name.push(rcgen::DnType::CommonName, name);
+    if let Some(email) = flag_value(raw, "--email") {
+        let email = email.as_str().try_into().unwrap_or_else(|_| die(format!("--email {email}: not a valid address")));
+        params.subject_alt_names.push(rcgen::SanType::Rfc822Name(email));
+    }
+    params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ClientAuth];
+    params.key_usages = vec![rcgen::KeyUsagePurpose::DigitalSignature];
+    let now = time::OffsetDateTime::now_utc();
+    params.not_before = now - time::Duration::hours(1);
+    params.not_after = now + time::Duration::days(days);
+    let key = rcgen::KeyPair::generate().unwrap_or_else(|e| die(e.to_string()));
+    let cert = params.signed_by(&key, &ca, &ca_key).unwrap_or_else(|e| die(e.to_string()));
+
+    let file = name.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' }).collect::<String>();
+    let (cert_file, key_file) = (out.join(format!("{file}.pem")), out.join(format!("{file}.key")));
+    std::fs::create_dir_all(&out).unwrap_or_else(|e| die(format!("{}: {e}", out.display())));
+    std::fs::write(&cert_file, cert.pem()).unwrap_or_else(|e| die(format!("{}: {e}", cert_file.display())));
+    write_private(&key_file, &key.serialize_pem()).unwrap_or_else(|e| die(e));
+
+    eprintln!("\n  {GR}●{RST} {B}{name}{RST} {D}valid {days} days{RST}");
+    eprintln!("  {D}  Cert:    {}{RST}", cert_file.display());
+    eprintln!("  {D}  Key:     {}{RST}", key_file.display());
+    eprintln!("  {D}  Server:  leak --tls --client-ca {}{RST}", dir.join("ca.pem").display());
+    eprintln!("  {D}  curl:    curl -k --cert {} --key {} https://...{RST}", cert_file.display(), key_file.display());
+    eprintln!("  {D}  Browser: openssl pkcs12 -export -in {} -inkey {} -out {file}.p12{RST}\n", cert_file.display(), key_file.display());
+}
+
+
+// ── Client address ────────────────────────────────────────────────────
+
+/// An address block such as `10.0.0.0/8` or `2001:db8::/32`. A bare address
+/// is a block of one.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Cidr {
+    addr: IpAddr,
+    len: u8,
+}
+
+impl Cidr {
+    fn parse(s: &str) -> Option<Self> {
+        let (ip, len) = match s.split_once('/') {
+            Some((ip, len)) => (ip, Some(len.parse::<u8>().ok()?)),
+            None => (s, None),
+        };
+        let addr: IpAddr = ip.trim_start_matches('[').trim_end_matches(']').parse().ok()?;
+        let addr = addr.to_canonical();
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let len = len.unwrap_or(max);
+        (len <= max).then_some(Self { addr, len })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.len as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.len as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
             }
+            _ => false,
         }
-        return Ok(http_response(StatusCode::OK, "OK", "text/plain"));
     }
+}
 
-    // Download handler (multi-file ZIP)
-    if method == Method::POST && uri_path.ends_with("/__download") {
-        let body_bytes = match req.collect().await {
-            Ok(c) => c.to_bytes(),
-            Err(_) => return Ok(http_response(StatusCode::BAD_REQUEST, "Read failed", "text/plain")),
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let max = if self.addr.is_ipv4() { 32 } else { 128 };
+        if self.len == max { write!(f, "{}", self.addr) } else { write!(f, "{}/{}", self.addr, self.len) }
+    }
+}
+
+/// `--trust-proxy`, `--allow` and `--deny` values: CIDRs, or `loopback` /
+/// `private` / `cloudflare` for the usual blocks.
+fn parse_cidrs(spec: &str) -> Option<Vec<Cidr>> {
+    let blocks: &[&str] = match spec {
+        "loopback" => &["127.0.0.0/8", "::1"],
+        "private" => &["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "fc00::/7"],
+        // https://www.cloudflare.com/ips/
+        "cloudflare" => &[
+            "173.245.48.0/20", "103.21.244.0/22", "103.22.200.0/22", "103.31.4.0/22", "141.101.64.0/18",
+            "108.162.192.0/18", "190.93.240.0/20", "188.114.96.0/20", "197.234.240.0/22", "198.41.128.0/17",
+            "162.158.0.0/15", "104.16.0.0/13", "104.24.0.0/14", "172.64.0.0/13", "131.0.72.0/22",
+            "2400:cb00::/32", "2606:4700::/32", "2803:f800::/32", "2405:b500::/32", "2405:8100::/32",
+            "2a06:98c0::/29", "2c0f:f248::/32",
+        ],
+        _ => return Cidr::parse(spec).map(|c| vec![c]),
+    };
+    Some(blocks.iter().filter_map(|b| Cidr::parse(b)).collect())
+}
+
+/// The address a request really came from, after trusted proxies. `None`
+/// for Unix socket clients that no header vouched for.
+#[derive(Clone, Copy)]
+struct ClientIp(Option<IpAddr>);
+
+/// Whether the browser reached us over HTTPS, directly or through a trusted proxy.
+#[derive(Clone, Copy)]
+struct Https(bool);
+
+/// Take the client from forwarding headers, but only while the hop that set
+/// them is trusted: walking `Forwarded`/`X-Forwarded-For` from the right, the
+/// first untrusted address is the client. A Unix socket peer is always local.
+/// `CF-Connecting-IP` wins over the chain, but only when `cf_header` says
+/// Cloudflare is in front; anyone else could set it to dodge a lockout.
+fn client_ip(headers: &hyper::HeaderMap, peer: Option<IpAddr>, trusted: &[Cidr], cf_header: bool) -> Option<IpAddr> {
+    let is_trusted = |ip: Option<IpAddr>| ip.is_none_or(|ip| trusted.iter().any(|c| c.contains(ip)));
+    if !is_trusted(peer) { return peer; }
+    let header = |name: &str| -> Vec<&str> {
+        headers.get_all(name).iter().filter_map(|v| v.to_str().ok()).flat_map(|v| v.split(',')).map(str::trim).collect()
+    };
+
+    // Cloudflare's own header is a single address, set at its edge
+    if let Some(ip) = header("cf-connecting-ip").first().and_then(|v| v.parse::<IpAddr>().ok()).filter(|_| cf_header) {
+        return Some(ip.to_canonical());
+    }
+    let forwarded: Vec<IpAddr> = header("forwarded").iter()
+        .filter_map(|elem| elem.split(';').find_map(|pair| {
+            let (k, v) = pair.trim().split_once('=')?;
+            k.eq_ignore_ascii_case("for").then(|| forwarded_node(v)).flatten()
+        }))
+        .collect();
+    let chain = if !forwarded.is_empty() {
+        forwarded
+    } else {
+        header("x-forwarded-for").iter().filter_map(|v| forwarded_node(v)).collect()
+    };
+    let mut client = peer;
+    for ip in chain.into_iter().rev() {
+        client = Some(ip);
+        if !is_trusted(client) { break; }
+    }
+    client
+}
+
+/// A `for=` node or `X-Forwarded-For` entry: `1.2.3.4`, `1.2.3.4:80`,
+/// `"[2001:db8::1]:80"`. Obfuscated and `unknown` nodes give `None`.
+fn forwarded_node(v: &str) -> Option<IpAddr> {
+    let v = v.trim().trim_matches('"');
+    let host = match v.strip_prefix('[') {
+        Some(rest) => rest.split_once(']')?.0,
+        None if v.matches(':').count() == 1 => v.split_once(':')?.0,
+        None => v,
+    };
+    host.parse::<IpAddr>().ok().map(|ip| ip.to_canonical())
+}
+
+const PROXY_V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Read a HAProxy PROXY protocol header (v1 text or v2 binary) off the front
+/// of a connection. `Ok(None)` means the proxy sent `LOCAL`/`UNKNOWN`, e.g. a
+/// health check, and the peer address stands.
+async fn read_proxy_header<S: tokio::io::AsyncRead + Unpin>(stream: &mut S) -> std::io::Result<Option<SocketAddr>> {
+    use tokio::io::AsyncReadExt;
+    let bad = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("PROXY header: {msg}"));
+    let mut head = [0u8; 12];
+    stream.read_exact(&mut head[..6]).await?;
+    if &head[..6] == b"PROXY " {
+        // v1: at most 107 bytes including CRLF
+        let mut line = head[..6].to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= 107 { return Err(bad("v1 line too long")); }
+            line.push(stream.read_u8().await?);
+        }
+        return parse_proxy_v1(&line).ok_or_else(|| bad("malformed v1 line"));
+    }
+    stream.read_exact(&mut head[6..]).await?;
+    if &head != PROXY_V2_SIGNATURE { return Err(bad("missing")); }
+    let mut fixed = [0u8; 4];
+    stream.read_exact(&mut fixed).await?;
+    let mut body = vec![0u8; u16::from_be_bytes([fixed[2], fixed[3]]) as usize];
+    stream.read_exact(&mut body).await?;
+    parse_proxy_v2(fixed[0], fixed[1], &body).ok_or_else(|| bad("malformed v2 header"))
+}
+
+/// `PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n`
+fn parse_proxy_v1(line: &[u8]) -> Option<Option<SocketAddr>> {
+    let line = std::str::from_utf8(line).ok()?.strip_suffix("\r\n")?;
+    let parts: Vec<&str> = line.split(' ').collect();
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Some(None),
+        ["PROXY", "TCP4" | "TCP6", src, _dst, sport, _dport] => {
+            Some(Some(SocketAddr::new(src.parse().ok()?, sport.parse().ok()?)))
+        }
+        _ => None,
+    }
+}
+
+/// v2: version/command byte, family/transport byte, then the address block.
+fn parse_proxy_v2(ver_cmd: u8, family: u8, body: &[u8]) -> Option<Option<SocketAddr>> {
+    if ver_cmd >> 4 != 2 { return None; }
+    match ver_cmd & 0x0f {
+        0 => return Some(None), // LOCAL
+        1 => {}                 // PROXY
+        _ => return None,
+    }
+    match family >> 4 {
+        1 if body.len() >= 12 => {
+            let ip = std::net::Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            Some(Some(SocketAddr::new(ip.into(), u16::from_be_bytes([body[8], body[9]]))))
+        }
+        2 if body.len() >= 36 => {
+            let ip = std::net::Ipv6Addr::from(<[u8; 16]>::try_from(&body[..16]).ok()?);
+            Some(Some(SocketAddr::new(IpAddr::V6(ip).to_canonical(), u16::from_be_bytes([body[32], body[33]]))))
+        }
+        0 | 3 => Some(None), // AF_UNSPEC, AF_UNIX: nothing useful to report
+        _ => None,
+    }
+}
+
+// ── IP rules ──────────────────────────────────────────────────────────
+
+struct RuleSet {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+    stamp: Option<SystemTime>,
+    checked: Instant,
+}
+
+/// `--allow`/`--deny` plus an optional rules file of `allow <cidr>` and
+/// `deny <cidr>` lines, re-read when it changes or on SIGHUP. Deny wins; once
+/// anything is allowed, everything else is denied.
+struct IpRules {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+    file: Option<PathBuf>,
+    loaded: RwLock<RuleSet>,
+}
+
+fn parse_rules_file(text: &str) -> Result<(Vec<Cidr>, Vec<Cidr>), String> {
+    let (mut allow, mut deny) = (Vec::new(), Vec::new());
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() { continue; }
+        let (verb, spec) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let list = match verb {
+            "allow" => &mut allow,
+            "deny" => &mut deny,
+            _ => return Err(format!("line {}: expected allow or deny", n + 1)),
         };
-        let body_str = String::from_utf8_lossy(&body_bytes);
-        let paths = extract_json_string_array(&body_str, "files");
-        if paths.is_empty() {
-            return Ok(http_response(StatusCode::BAD_REQUEST, "No files specified", "text/plain"));
+        list.extend(parse_cidrs(spec.trim()).ok_or_else(|| format!("line {}: bad address {}", n + 1, spec.trim()))?);
+    }
+    Ok((allow, deny))
+}
+
+impl IpRules {
+    fn new(allow: Vec<Cidr>, deny: Vec<Cidr>, file: Option<PathBuf>) -> Result<IpRules, String> {
+        let rules = IpRules {
+            allow,
+            deny,
+            file,
+            loaded: RwLock::new(RuleSet { allow: Vec::new(), deny: Vec::new(), stamp: None, checked: Instant::now() }),
+        };
+        rules.reload()?;
+        Ok(rules)
+    }
+
+    fn stamp(&self) -> Option<SystemTime> {
+        self.file.as_ref().and_then(|f| std::fs::metadata(f).and_then(|m| m.modified()).ok())
+    }
+
+    /// A broken edit keeps the previous rules in force.
+    fn reload(&self) -> Result<(), String> {
+        let Some(ref path) = self.file else { return Ok(()) };
+        let stamp = self.stamp();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let (allow, deny) = parse_rules_file(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+        *self.loaded.write().unwrap() = RuleSet { allow, deny, stamp, checked: Instant::now() };
+        Ok(())
+    }
+
+    fn refresh(&self) {
+        if self.file.is_none() { return; }
+        let stale = self.loaded.read().unwrap().checked.elapsed() >= std::time::Duration::from_secs(1);
+        if !stale { return; }
+        let stamp = self.stamp();
+        if stamp == self.loaded.read().unwrap().stamp {
+            self.loaded.write().unwrap().checked = Instant::now();
+            return;
+        }
+        match self.reload() {
+            Ok(()) => eprintln!("  {} {D}ip rules reloaded ({}){RST}", ts(), self.summary()),
+            Err(e) => {
+                eprintln!("  {} {RD}ip rules not reloaded:{RST} {e}", ts());
+                let mut set = self.loaded.write().unwrap();
+                set.stamp = stamp;
+                set.checked = Instant::now();
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty() && self.file.is_none()
+    }
+
+    /// Unix socket clients (`None`) are local and always let through.
+    fn permits(&self, ip: Option<IpAddr>) -> bool {
+        let Some(ip) = ip else { return true };
+        self.refresh();
+        let set = self.loaded.read().unwrap();
+        let hit = |list: &[Cidr]| list.iter().any(|c| c.contains(ip));
+        if hit(&self.deny) || hit(&set.deny) { return false; }
+        let open = self.allow.is_empty() && set.allow.is_empty();
+        open || hit(&self.allow) || hit(&set.allow)
+    }
+
+    fn summary(&self) -> String {
+        let set = self.loaded.read().unwrap();
+        let list = |a: &[Cidr], b: &[Cidr]| a.iter().chain(b).map(|c| c.to_string()).collect::<Vec<_>>().join(", ");
+        let mut parts = Vec::new();
+        if !self.allow.is_empty() || !set.allow.is_empty() { parts.push(format!("allow {}", list(&self.allow, &set.allow))); }
+        if !self.deny.is_empty() || !set.deny.is_empty() { parts.push(format!("deny {}", list(&self.deny, &set.deny))); }
+        if parts.is_empty() { "no rules".to_string() } else { parts.join("; ") }
+    }
+}
+
+// ── Listeners ─────────────────────────────────────────────────────────
+
+enum ListenAddr {
+    Any(u16), // every interface: dual-stack [::], or 0.0.0.0 without IPv6
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+/// One `--bind`: `ip`, `ip:port`, `[v6]:port` or `unix:/path`, optionally
+/// prefixed with `http://` or `https://` to override `--tls` for that listener.
+struct ListenSpec {
+    addr: ListenAddr,
+    tls: bool,
+    fixed_port: bool, // false when the port comes from the main port setting
+}
+
+impl ListenSpec {
+    fn parse(spec: &str, port: u16, tls: bool) -> Result<Self, String> {
+        let (tls, rest) = match (spec.strip_prefix("https://"), spec.strip_prefix("http://")) {
+            (Some(r), _) => (true, r),
+            (_, Some(r)) => (false, r),
+            _ => (tls, spec),
+        };
+        if let Some(path) = rest.strip_prefix("unix:") {
+            if path.is_empty() { return Err("missing socket path".into()); }
+            return Ok(Self { addr: ListenAddr::Unix(expand_home(Path::new(path))), tls, fixed_port: true });
+        }
+        let rest = rest.trim_end_matches('/');
+        let (host, explicit) = if let Some(v6) = rest.strip_prefix('[') {
+            let (host, tail) = v6.split_once(']').ok_or("unclosed [")?;
+            match tail.strip_prefix(':') {
+                Some(p) => (host, Some(p)),
+                None if tail.is_empty() => (host, None),
+                None => return Err("expected [address]:port".into()),
+            }
+        } else if rest.matches(':').count() == 1 {
+            let (host, p) = rest.split_once(':').unwrap();
+            (host, Some(p))
+        } else {
+            (rest, None) // a bare IPv4 or unbracketed IPv6 address
+        };
+        let (port, fixed_port) = match explicit {
+            Some(p) => (parse_port(p).ok_or_else(|| format!("invalid port {p}"))?, true),
+            None => (port, false),
+        };
+        let ip: IpAddr = match host {
+            "localhost" => IpAddr::from([127, 0, 0, 1]),
+            "*" => return Ok(Self { addr: ListenAddr::Any(port), tls, fixed_port }),
+            _ => host.parse().map_err(|_| format!("not an IP address: {host}"))?,
+        };
+        Ok(Self { addr: ListenAddr::Tcp(SocketAddr::new(ip, port)), tls, fixed_port })
+    }
+}
+
+impl ListenAddr {
+    fn with_port(&self, port: u16) -> Self {
+        match self {
+            Self::Any(_) => Self::Any(port),
+            Self::Tcp(addr) => Self::Tcp(SocketAddr::new(addr.ip(), port)),
+            Self::Unix(path) => Self::Unix(path.clone()),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Any(port) => format!("port {port}"),
+            Self::Tcp(addr) => addr.to_string(),
+            Self::Unix(path) => path.display().to_string(),
+        }
+    }
+}
+
+/// A port number, or `auto` (same as 0) to let the OS pick a free one.
+fn parse_port(s: &str) -> Option<u16> {
+    if s == "auto" { Some(0) } else { s.parse().ok() }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, PathBuf),
+}
+
+fn bind_tcp(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    // [::] also accepts IPv4 (as mapped addresses) unless the OS forbids it
+    if addr.is_ipv6() && addr.ip().is_unspecified() { let _ = socket.set_only_v6(false); }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+impl Listener {
+    async fn bind(addr: &ListenAddr) -> std::io::Result<Self> {
+        match addr {
+            ListenAddr::Any(port) => bind_tcp(SocketAddr::new(IpAddr::from([0u16; 8]), *port))
+                .or_else(|_| bind_tcp(SocketAddr::new(IpAddr::from([0u8; 4]), *port)))
+                .map(Self::Tcp),
+            ListenAddr::Tcp(addr) => bind_tcp(*addr).map(Self::Tcp),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                // Replace a socket left behind by a previous run, but never a live one
+                if std::os::unix::net::UnixStream::connect(path).is_err() {
+                    let _ = std::fs::remove_file(path);
+                }
+                tokio::net::UnixListener::bind(path).map(|l| Self::Unix(l, path.clone()))
+            }
+            #[cfg(not(unix))]
+            ListenAddr::Unix(_) => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "unix sockets are not supported here")),
+        }
+    }
+
+    /// The address actually bound, for the banner.
+    fn describe(&self, scheme: &str) -> String {
+        match self {
+            Self::Tcp(l) => match l.local_addr() {
+                Ok(a) => format!("{scheme}://{a}"),
+                Err(_) => format!("{scheme}://?"),
+            },
+            #[cfg(unix)]
+            Self::Unix(_, path) => format!("unix:{}", path.display()),
+        }
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Tcp(l) => l.local_addr().ok(),
+            #[cfg(unix)]
+            Self::Unix(..) => None,
+        }
+    }
+
+    fn port(&self) -> Option<u16> {
+        self.local_addr().map(|a| a.port())
+    }
+
+    fn is_ipv6(&self) -> bool {
+        matches!(self, Self::Tcp(l) if l.local_addr().is_ok_and(|a| a.is_ipv6()))
+    }
+
+    fn is_wildcard(&self) -> bool {
+        matches!(self, Self::Tcp(l) if l.local_addr().is_ok_and(|a| a.ip().is_unspecified()))
+    }
+
+    fn cleanup(&self) {
+        #[cfg(unix)]
+        if let Self::Unix(_, path) = self { let _ = std::fs::remove_file(path); }
+    }
+}
+
+/// Bind every listener. Those on the main port move together: port 0 takes
+/// whatever the OS hands the first of them, and a busy port is retried up
+/// to `retries` times at port+1, port+2, ...
+async fn bind_all(specs: &[ListenSpec], port: u16, retries: u16) -> Result<Vec<(Arc<Listener>, bool)>, String> {
+    let mut candidate = port;
+    let mut attempt = 0;
+    'retry: loop {
+        let mut chosen = candidate;
+        let mut out = Vec::new();
+        for spec in specs {
+            let moved;
+            let addr = if spec.fixed_port { &spec.addr } else { moved = spec.addr.with_port(chosen); &moved };
+            match Listener::bind(addr).await {
+                Ok(l) => {
+                    if !spec.fixed_port && chosen == 0 { chosen = l.port().unwrap_or(0); }
+                    out.push((Arc::new(l), spec.tls));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AddrInUse && !spec.fixed_port && candidate != 0 && attempt < retries => {
+                    drop(out);
+                    attempt += 1;
+                    candidate = match port.checked_add(attempt) { Some(p) => p, None => return Err(format!("no free port from {port}")) };
+                    continue 'retry;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AddrInUse && attempt > 0 => {
+                    return Err(format!("ports {port}-{candidate} are all in use"));
+                }
+                Err(e) => return Err(format!("failed to bind to {}: {e}", addr.describe())),
+            }
+        }
+        return Ok(out);
+    }
+}
+
+/// Shared by every connection on every listener.
+struct Context {
+    cfg: Arc<ServerConfig>,
+    log: RequestLog,
+    seen_ips: tokio::sync::Mutex<HashSet<IpAddr>>,
+    geo: Option<GeoIp>,
+    trusted: Vec<Cidr>,
+    cf_header: bool, // Cloudflare is in front, so CF-Connecting-IP is real
+    proxy_protocol: bool,
+    rules: IpRules,
+    blocked: std::sync::Mutex<HashSet<IpAddr>>,
+    hsts: bool, // a year of Strict-Transport-Security on HTTPS responses
+    http: Builder<hyper_util::rt::TokioExecutor>,
+    h3: bool, // advertise the QUIC endpoints with Alt-Svc
+    compress: bool,
+    cors: Option<Cors>,
+}
+
+impl Context {
+    /// Apply the IP rules, logging each refused address once.
+    fn admit(&self, ip: Option<IpAddr>) -> bool {
+        if self.rules.permits(ip) { return true; }
+        if let Some(ip) = ip {
+            if self.blocked.lock().unwrap().insert(ip) {
+                eprintln!("  {} {RD}{B}BLOCKED{RST} {CY}{ip}{RST} {D}by ip rules{RST}", ts());
+            }
+        }
+        false
+    }
+}
+
+async fn accept_loop(listener: Arc<Listener>, tls: Option<tokio_rustls::TlsAcceptor>, ctx: Arc<Context>) {
+    // Point browsers at the QUIC endpoint sharing this port
+    let alt_svc = listener.port().filter(|_| ctx.h3 && tls.is_some())
+        .and_then(|p| format!("h3=\":{p}\"; ma=86400").parse().ok());
+    loop {
+        match &*listener {
+            Listener::Tcp(l) => match l.accept().await {
+                Ok((stream, remote)) => {
+                    // IPv4 clients of a dual-stack socket arrive as ::ffff:a.b.c.d
+                    let remote = SocketAddr::new(remote.ip().to_canonical(), remote.port());
+                    let local = stream.local_addr().ok();
+                    tokio::spawn(handle_connection(stream, Some(remote), local, tls.clone(), alt_svc.clone(), ctx.clone()));
+                }
+                Err(e) => eprintln!("  {RD}accept error:{RST} {e}"),
+            },
+            #[cfg(unix)]
+            Listener::Unix(l, _) => match l.accept().await {
+                Ok((stream, _)) => { tokio::spawn(handle_connection(stream, None, None, tls.clone(), alt_svc.clone(), ctx.clone())); }
+                Err(e) => eprintln!("  {RD}accept error:{RST} {e}"),
+            },
+        }
+    }
+}
+
+/// HTTP/2 tuning. Plaintext connections speak it with prior knowledge (h2c);
+/// TLS ones negotiate it with ALPN.
+#[derive(Clone)]
+struct Http2Options {
+    enabled: bool,
+    max_streams: Option<u32>,
+    stream_window: Option<u32>,
+    conn_window: Option<u32>,
+}
+
+/// The connection builder every listener shares.
+fn http_builder(opts: &Http2Options) -> Builder<hyper_util::rt::TokioExecutor> {
+    let mut builder = Builder::new(hyper_util::rt::TokioExecutor::new());
+    if !opts.enabled { return builder.http1_only(); }
+    let mut h2 = builder.http2();
+    h2.timer(hyper_util::rt::TokioTimer::new());
+    if let Some(n) = opts.max_streams { h2.max_concurrent_streams(n); }
+    // Without fixed windows, size them to the measured bandwidth-delay product
+    match (opts.stream_window, opts.conn_window) {
+        (None, None) => { h2.adaptive_window(true); }
+        (stream, conn) => {
+            if let Some(w) = stream { h2.initial_stream_window_size(w); }
+            if let Some(w) = conn { h2.initial_connection_window_size(w); }
+        }
+    }
+    builder
+}
+
+/// HTTP/2 windows are u32 byte counts; anything past 2^31-1 is a protocol error.
+fn h2_window(s: &str) -> Option<u32> {
+    parse_size(s).filter(|n| (1..=i32::MAX as u64).contains(n)).map(|n| n as u32)
+}
+
+/// `64k`, `1m`, `1048576`.
+fn parse_size(s: &str) -> Option<u64> {
+    let lower = s.to_ascii_lowercase();
+    let (n, mult) = match lower.trim_end_matches(['b', 'i']).char_indices().last()? {
+        (i, 'k') => (&lower[..i], 1 << 10),
+        (i, 'm') => (&lower[..i], 1 << 20),
+        (i, 'g') => (&lower[..i], 1 << 30),
+        _ => (lower.as_str(), 1),
+    };
+    n.parse::<u64>().ok()?.checked_mul(mult)
+}
+
+/// A stream with its first byte, already read, put back in front.
+struct Rewind<S> {
+    first: Option<u8>,
+    inner: S,
+}
+
+impl<S: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for Rewind<S> {
+    fn poll_read(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>, buf: &mut tokio::io::ReadBuf<'_>) -> std::task::Poll<std::io::Result<()>> {
+        if buf.remaining() > 0 {
+            if let Some(b) = self.first.take() {
+                buf.put_slice(&[b]);
+                return std::task::Poll::Ready(Ok(()));
+            }
+        }
+        std::pin::Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: tokio::io::AsyncWrite + Unpin> tokio::io::AsyncWrite for Rewind<S> {
+    fn poll_write(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>, buf: &[u8]) -> std::task::Poll<std::io::Result<usize>> {
+        std::pin::Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// A Host header's name and port, if it is nothing but a hostname, an IPv4
+/// address or a bracketed IPv6 address, with an optional port.
+fn split_host(h: &str) -> Option<(&str, Option<u16>)> {
+    let (name, port) = match h.strip_prefix('[') {
+        Some(rest) => {
+            let (v6, _) = rest.split_once(']')?;
+            v6.parse::<std::net::Ipv6Addr>().ok()?;
+            h.split_at(v6.len() + 2)
+        }
+        None => h.split_at(h.find(':').unwrap_or(h.len())),
+    };
+    let port = match port.strip_prefix(':') {
+        Some(p) => Some(p.parse::<u16>().ok()?),
+        None if port.is_empty() => None,
+        None => return None,
+    };
+    let label_ok = |l: &str| !l.is_empty() && !l.starts_with('-') && l.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-');
+    if !name.starts_with('[') && (name.len() > 253 || !name.trim_end_matches('.').split('.').all(label_ok)) { return None; }
+    Some((name, port))
+}
+
+/// Where a plain-HTTP request to a TLS port should have gone. A missing or
+/// malformed Host falls back to the address the client connected to.
+fn https_redirect<B>(req: &Request<B>, local: Option<SocketAddr>) -> Response<Full<Bytes>> {
+    let host = req.headers().get("host").and_then(|h| h.to_str().ok()).and_then(split_host)
+        .map(|(name, port)| (name.to_string(), port))
+        .or_else(|| local.map(|a| (url_host(a.ip().to_canonical()), Some(a.port()))));
+    let authority = match host {
+        Some((name, Some(port))) if port != 443 => format!("{name}:{port}"),
+        Some((name, _)) => name,
+        None => return http_response(StatusCode::BAD_REQUEST, "This port speaks HTTPS", "text/plain"),
+    };
+    let path = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    Response::builder()
+        .status(StatusCode::MOVED_PERMANENTLY)
+        .header("Location", format!("https://{authority}{path}"))
+        .header("Content-Type", "text/plain")
+        .body(Full::new(Bytes::from("This port speaks HTTPS")))
+        .unwrap()
+}
+
+/// Serve one connection. `remote` is `None` for Unix sockets.
+/// Everything between a parsed request and its response that doesn't depend on
+/// the transport: per-request IP rules, the CONNECT line, HSTS and the log.
+/// h1, h2 and h3 requests all come through here.
+async fn dispatch<B: hyper::body::Body>(ctx: &Context, mut req: Request<B>, client: Option<IpAddr>, user: Option<String>, check_ip: bool) -> Response<Full<Bytes>> {
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    if check_ip && !ctx.admit(client) {
+        ctx.log.record(403, &method, &path, client, user.as_deref());
+        return http_response(StatusCode::FORBIDDEN, "403 Forbidden", "text/plain; charset=utf-8");
+    }
+    // First request from an address
+    if let Some(ip) = client {
+        if ctx.seen_ips.lock().await.insert(ip) {
+            match ctx.geo.as_ref().and_then(|g| g.locate(ip)) {
+                Some(loc) => eprintln!("  {} {MG}{B}CONNECT{RST} {CY}{}{RST} {D}({}){RST}", ts(), ip, loc),
+                None => eprintln!("  {} {MG}{B}CONNECT{RST} {CY}{}{RST}", ts(), ip),
+            }
         }
+    }
+    let secure = req.extensions().get::<Https>().is_some_and(|h| h.0);
+    let cors = ctx.cors.as_ref().and_then(|c| {
+        if let Some(resp) = c.preflight(&req) { return Some(Err(resp)); }
+        c.allow(req.headers().get("origin")?.to_str().ok()?).map(Ok)
+    });
+    if let Some(Err(mut resp)) = cors {
+        ctx.cfg.headers.apply(&path, resp.headers_mut());
+        ctx.log.record(resp.status().as_u16(), &method, &path, client, user.as_deref());
+        return resp;
+    }
+    // Range requests get the identity bytes they asked for
+    let encodings = match req.headers().get(hyper::header::ACCEPT_ENCODING).and_then(|v| v.to_str().ok()) {
+        Some(v) if ctx.compress && !req.headers().contains_key(hyper::header::RANGE) => accepted_encodings(v),
+        _ => Vec::new(),
+    };
+    req.extensions_mut().insert(AcceptEncodings(encodings.clone()));
+    let Ok(resp) = serve(ctx.cfg.clone(), req).await;
+    let mut resp = compress_response(resp, &encodings).await;
+    if secure && ctx.hsts {
+        resp.headers_mut().insert("Strict-Transport-Security", hyper::header::HeaderValue::from_static("max-age=31536000"));
+    }
+    if let (Some(c), Some(Ok(allow))) = (&ctx.cors, cors) {
+        c.decorate(resp.headers_mut(), allow);
+    }
+    ctx.cfg.headers.apply(&path, resp.headers_mut());
+    if method != "POST" || !path.ends_with("/__upload") {
+        ctx.log.record(resp.status().as_u16(), &method, &path, client, user.as_deref());
+    }
+    resp
+}
 
-        let root_clone = root.clone();
-        let zip_result = tokio::task::spawn_blocking(move || {
-            let buf = std::io::Cursor::new(Vec::new());
-            let mut zip = zip::ZipWriter::new(buf);
-            let opts = SimpleFileOptions::default()
-                .compression_method(zip::CompressionMethod::Deflated);
+async fn handle_connection<S>(mut stream: S, remote: Option<SocketAddr>, local: Option<SocketAddr>, tls: Option<tokio_rustls::TlsAcceptor>, alt_svc: Option<hyper::header::HeaderValue>, ctx: Arc<Context>)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let mut peer = remote.map(|r| r.ip());
+    if ctx.proxy_protocol {
+        // Only a trusted load balancer may speak for someone else
+        if peer.is_some_and(|ip| !ctx.trusted.iter().any(|c| c.contains(ip))) { return; }
+        match tokio::time::timeout(std::time::Duration::from_secs(5), read_proxy_header(&mut stream)).await {
+            Ok(Ok(Some(src))) => peer = Some(src.ip().to_canonical()),
+            Ok(Ok(None)) => {}
+            Ok(Err(e)) => { eprintln!("  {} {RD}{e}{RST} {D}{}{RST}", ts(), peer_label(peer)); return; }
+            Err(_) => return,
+        }
+    }
+    // Proxied requests are checked one by one, once the client is known
+    let proxied = peer.is_none_or(|ip| ctx.trusted.iter().any(|c| c.contains(ip)));
+    if !proxied && !ctx.admit(peer) { return; }
+    let https = tls.is_some();
+    // Filled in once the TLS handshake has checked the client's certificate
+    let cert_user: Arc<std::sync::OnceLock<String>> = Arc::default();
+    let conn_user = cert_user.clone();
+    let ctx_log = ctx.clone();
+    let http = ctx.http.clone();
 
-            for path_str in &paths {
-                let clean = path_str.trim_start_matches('/');
-                let decoded = percent_decode(clean);
-                let fs_path = root_clone.join(&decoded);
-                let canonical = match fs_path.canonicalize() {
-                    Ok(c) if c.starts_with(&root_clone) => c,
-                    _ if decoded.is_empty() => continue,
-                    _ => continue,
-                };
-                // Use the last path component as the archive entry name
-                let arc_name = canonical.file_name()
-                    .map(|n| n.to_string_lossy().to_string())
-                    .unwrap_or_else(|| decoded.clone());
-                let _ = add_path_to_zip(&mut zip, &canonical, &arc_name, &root_clone, opts);
+    let svc = service_fn(move |mut req: Request<Incoming>| {
+        let ctx = ctx.clone();
+        let client = client_ip(req.headers(), peer, &ctx.trusted, ctx.cf_header);
+        let forwarded_https = proxied && req.headers().get("x-forwarded-proto").is_some_and(|v| v == "https");
+        req.extensions_mut().insert(ClientIp(client));
+        req.extensions_mut().insert(Https(https || forwarded_https));
+        let user = cert_user.get().cloned();
+        req.extensions_mut().insert(CertUser(user.clone()));
+        let alt_svc = alt_svc.clone();
+        async move {
+            let mut resp = dispatch(&ctx, req, client, user, proxied).await;
+            if let Some(v) = alt_svc {
+                resp.headers_mut().insert("Alt-Svc", v);
             }
+            Ok::<_, Infallible>(resp)
+        }
+    });
 
-            zip.finish().map(|c| c.into_inner())
-        }).await;
-
-        match zip_result {
-            Ok(Ok(data)) => {
-                let size = data.len();
-                eprintln!(
-                    "  {} {GR}{B}DOWNLOAD{RST} {CY}ZIP{RST} {D}({}){RST}",
-                    ts(), format_size(size as u64),
-                );
-                return Ok(Response::builder()
-                    .status(StatusCode::OK)
-                    .header("Content-Type", "application/zip")
-                    .header("Content-Disposition", "attachment; filename=\"leak-download.zip\"")
-                    .header("Access-Control-Allow-Origin", "*")
-                    .body(Full::new(Bytes::from(data)))
-                    .unwrap());
+    if let Some(acceptor) = tls {
+        // Every TLS connection opens with a handshake record (0x16); anything
+        // else is someone typing host:port without https://
+        let mut first = [0u8; 1];
+        match tokio::time::timeout(std::time::Duration::from_secs(10), tokio::io::AsyncReadExt::read(&mut stream, &mut first)).await {
+            Ok(Ok(1)) => {}
+            _ => return,
+        }
+        let stream = Rewind { first: Some(first[0]), inner: stream };
+        if first[0] != 0x16 {
+            let log_ctx = ctx_log.clone();
+            let redirect = service_fn(move |req: Request<Incoming>| {
+                let resp = https_redirect(&req, local);
+                log_ctx.log.record(resp.status().as_u16(), req.method().as_str(), req.uri().path(), peer, None);
+                async move { Ok::<_, Infallible>(resp) }
+            });
+            let _ = ctx_log.http.serve_connection(TokioIo::new(stream), redirect).await;
+            return;
+        }
+        // A failed TLS handshake is silently ignored
+        if let Ok(tls_stream) = acceptor.accept(stream).await {
+            let certs = tls_stream.get_ref().1.peer_certificates();
+            if let Some(name) = certs.and_then(|c| c.first()).and_then(|c| cert_username(c)) {
+                let _ = conn_user.set(name);
             }
-            _ => return Ok(http_response(StatusCode::INTERNAL_SERVER_ERROR, "ZIP creation failed", "text/plain")),
+            let io = TokioIo::new(tls_stream);
+            let _ = http.serve_connection(io, svc).await;
         }
+    } else {
+        let io = TokioIo::new(stream);
+        let _ = http.serve_connection(io, svc).await;
     }
+}
 
-    // GET handler
-    let clean = uri_path.trim_start_matches('/');
-    let decoded = percent_decode(clean);
-    let file_path = root.join(&decoded);
-
-    let canonical = match file_path.canonicalize() {
-        Ok(c) if c.starts_with(root) => c,
-        _ if decoded.is_empty() => root.clone(),
-        _ => return Ok(http_response(StatusCode::NOT_FOUND, format!("404 Not Found: {uri_path}"), "text/plain; charset=utf-8")),
-    };
+// ── HTTP/3 ────────────────────────────────────────────────────────────
 
-    if canonical.is_dir() {
-        let index = canonical.join("index.html");
-        if index.exists() {
-            if let Ok(contents) = fs::read(&index).await {
-                return Ok(http_response(StatusCode::OK, contents, "text/html; charset=utf-8"));
+/// Same cap as uploads over h1/h2, enforced while reading the stream.
+const H3_MAX_BODY: usize = 500 * 1024 * 1024;
+
+/// QUIC connections on one UDP socket. IP rules apply per connection, since
+/// there is no proxy in front of QUIC to speak for anyone else.
+async fn quic_accept_loop(endpoint: quinn::Endpoint, ctx: Arc<Context>) {
+    while let Some(incoming) = endpoint.accept().await {
+        let ip = incoming.remote_address().ip().to_canonical();
+        if !ctx.admit(Some(ip)) {
+            incoming.refuse();
+            continue;
+        }
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            // A failed handshake is silently ignored, as over TCP
+            let Ok(conn) = incoming.await else { return };
+            let user = conn.peer_identity()
+                .and_then(|id| id.downcast::<Vec<rustls::pki_types::CertificateDer<'static>>>().ok())
+                .and_then(|certs| certs.first().and_then(|c| cert_username(c)));
+            let Ok(mut h3) = h3::server::Connection::<_, Bytes>::new(h3_quinn::Connection::new(conn)).await else { return };
+            while let Ok(Some(resolver)) = h3.accept().await {
+                let ctx = ctx.clone();
+                let user = user.clone();
+                tokio::spawn(async move {
+                    if let Ok((req, stream)) = resolver.resolve_request().await {
+                        let _ = serve_h3(&ctx, req, stream, ip, user).await;
+                    }
+                });
             }
+        });
+    }
+}
+
+/// Buffer one request body, run it through `dispatch` like any other request
+/// and write the response back on the stream.
+async fn serve_h3<S>(ctx: &Context, req: Request<()>, mut stream: h3::server::RequestStream<S, Bytes>, ip: IpAddr, user: Option<String>) -> Result<(), h3::error::StreamError>
+where
+    S: h3::quic::BidiStream<Bytes>,
+{
+    use bytes::Buf;
+    let mut body = Vec::new();
+    while let Some(mut chunk) = stream.recv_data().await? {
+        if body.len() + chunk.remaining() > H3_MAX_BODY {
+            let resp = http_response(StatusCode::PAYLOAD_TOO_LARGE, "500MB max", "text/plain");
+            return send_h3(&mut stream, resp).await;
+        }
+        while chunk.has_remaining() {
+            let part = chunk.chunk();
+            body.extend_from_slice(part);
+            let n = part.len();
+            chunk.advance(n);
         }
-        let html = render_directory(&canonical, &uri_path, root).await;
-        return Ok(http_response(StatusCode::OK, html, "text/html; charset=utf-8"));
     }
+    let (parts, ()) = req.into_parts();
+    let mut req = Request::from_parts(parts, Full::new(Bytes::from(body)));
+    req.extensions_mut().insert(ClientIp(Some(ip)));
+    req.extensions_mut().insert(Https(true));
+    req.extensions_mut().insert(CertUser(user.clone()));
+    let resp = dispatch(ctx, req, Some(ip), user, false).await;
+    send_h3(&mut stream, resp).await
+}
 
-    match fs::read(&canonical).await {
-        Ok(contents) => Ok(http_response(StatusCode::OK, contents, content_type(&canonical))),
-        Err(_) => Ok(http_response(StatusCode::NOT_FOUND, format!("404 Not Found: {uri_path}"), "text/plain; charset=utf-8")),
+async fn send_h3<S>(stream: &mut h3::server::RequestStream<S, Bytes>, resp: Response<Full<Bytes>>) -> Result<(), h3::error::StreamError>
+where
+    S: h3::quic::BidiStream<Bytes>,
+{
+    let (parts, body) = resp.into_parts();
+    stream.send_response(Response::from_parts(parts, ())).await?;
+    let Ok(data) = body.collect().await.map(|c| c.to_bytes());
+    if !data.is_empty() {
+        stream.send_data(data).await?;
     }
+    stream.finish().await
 }
 
-// ── Tunnel ────────────────────────────────────────────────────────────
+// ── Config file ───────────────────────────────────────────────────────
 
-enum TunnelProvider { Localtunnel, Cloudflared, Serveo }
-impl TunnelProvider {
-    fn name(&self) -> &'static str {
-        match self { Self::Localtunnel=>"localtunnel", Self::Cloudflared=>"cloudflared", Self::Serveo=>"serveo" }
+/// One layer of settings. `leak.toml`, its profiles, the environment and the
+/// command line all produce one of these; later layers win field by field.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Settings {
+    #[serde(default, deserialize_with = "port_or_auto")]
+    port: Option<u16>, // 0 = auto
+    port_retries: Option<u16>,
+    json: Option<bool>,
+    qr: Option<String>,
+    mdns: Option<bool>,
+    name: Option<String>,
+    geo: Option<bool>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    geoip_db: Option<Vec<PathBuf>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    trust_proxy: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    allow: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    deny: Option<Vec<String>>,
+    ip_rules: Option<PathBuf>,
+    proxy_protocol: Option<bool>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    rate_limit: Option<Vec<String>>,
+    max_logins: Option<u32>,
+    lockout: Option<String>,
+    session_idle: Option<String>,
+    session_max: Option<String>,
+    token_store: Option<PathBuf>,
+    client_ca: Option<PathBuf>,
+    totp_store: Option<PathBuf>,
+    no_auth: Option<bool>,
+    i_know: Option<bool>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    bind: Option<Vec<String>>,
+    dir: Option<PathBuf>,
+    auth: Option<String>, // "user:pass"
+    tls: Option<bool>,
+    hsts: Option<bool>,
+    http2: Option<bool>,
+    http3: Option<bool>,
+    compress: Option<bool>,
+    h2_max_streams: Option<u32>,
+    h2_stream_window: Option<String>,
+    h2_conn_window: Option<String>,
+    public: Option<bool>,
+    tunnel: Option<String>,
+    read_only: Option<bool>,
+    symlinks: Option<String>,
+    show_hidden: Option<bool>,
+    spa: Option<bool>,
+    redirects: Option<PathBuf>,
+    headers: Option<PathBuf>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    cors: Option<Vec<String>>,
+    cors_methods: Option<String>,
+    cors_credentials: Option<bool>,
+    gitignore: Option<bool>,
+    index: Option<bool>,
+    index_cache: Option<PathBuf>,
+    #[serde(default, rename = "mount")]
+    mounts: Vec<MountSpec>,
+    log: Option<LogSettings>,
+    #[serde(default, rename = "profile")]
+    profiles: HashMap<String, Settings>,
+}
+
+/// `bind = "127.0.0.1"` and `bind = ["127.0.0.1", "unix:/run/leak.sock"]` both work.
+fn one_or_many<'de, D: serde::Deserializer<'de>, T: Deserialize<'de>>(d: D) -> Result<Option<Vec<T>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> { One(T), Many(Vec<T>) }
+    Ok(Some(match OneOrMany::deserialize(d)? {
+        OneOrMany::One(s) => vec![s],
+        OneOrMany::Many(v) => v,
+    }))
+}
+
+/// `port = 8080` or `port = "auto"`.
+fn port_or_auto<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Option<u16>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Port { Number(u16), Name(String) }
+    match Port::deserialize(d)? {
+        Port::Number(p) => Ok(Some(p)),
+        Port::Name(s) => parse_port(&s).map(Some).ok_or_else(|| serde::de::Error::custom(format!("invalid port `{s}`"))),
     }
 }
 
-async fn which(cmd: &str) -> bool {
-    Command::new("which").arg(cmd).stdout(Stdio::null()).stderr(Stdio::null())
-        .status().await.map(|s| s.success()).unwrap_or(false)
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct LogSettings {
+    quiet: Option<bool>,
+    file: Option<PathBuf>,
 }
 
-async fn detect_tunnel() -> Option<TunnelProvider> {
-    if which("lt").await { Some(TunnelProvider::Localtunnel) }
-    else if which("cloudflared").await { Some(TunnelProvider::Cloudflared) }
-    else if which("ssh").await { Some(TunnelProvider::Serveo) }
-    else { None }
+/// `--mount /prefix=dir[,ro|rw][,public][,auth=user:pass]`, or a `[[mount]]` table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct MountSpec {
+    path: String,
+    dir: PathBuf,
+    read_only: Option<bool>,
+    #[serde(default)]
+    public: bool,
+    auth: Option<String>, // "user:pass"
 }
 
-async fn start_tunnel(prov: &TunnelProvider, port: u16) -> Option<(tokio::process::Child, tokio::sync::oneshot::Receiver<String>)> {
-    let (tx, rx) = tokio::sync::oneshot::channel::<String>();
-    let tx = std::sync::Mutex::new(Some(tx));
+impl MountSpec {
+    fn prefix(&self) -> &str { self.path.trim_matches('/') }
+}
 
-    macro_rules! drain {
-        ($stream:expr) => {
-            if let Some(s) = $stream {
-                tokio::spawn(async move { let mut l = BufReader::new(s).lines(); while let Ok(Some(_)) = l.next_line().await {} });
-            }
+fn parse_mount(spec: &str) -> Result<MountSpec, String> {
+    let (path, rest) = spec.split_once('=').ok_or("expected /prefix=directory")?;
+    let mut parts = rest.split(',');
+    let dir = PathBuf::from(parts.next().unwrap_or_default());
+    let mut m = MountSpec { path: path.to_string(), dir, read_only: None, public: false, auth: None };
+    for opt in parts {
+        match opt {
+            "ro" => m.read_only = Some(true),
+            "rw" => m.read_only = Some(false),
+            "public" => m.public = true,
+            _ => match opt.strip_prefix("auth=") {
+                Some(a) => m.auth = Some(a.to_string()),
+                None => return Err(format!("unknown mount option: {opt}")),
+            },
         }
     }
-    macro_rules! scan_url {
-        ($stream:expr, $pattern:expr) => {{
-            let s = $stream?;
-            let tx = tx;
-            tokio::spawn(async move {
-                let mut lines = BufReader::new(s).lines();
-                while let Ok(Some(line)) = lines.next_line().await {
-                    if let Some(url) = line.split_whitespace().find(|w| w.contains($pattern)) {
-                        if let Some(sender) = tx.lock().unwrap().take() { let _ = sender.send(url.to_string()); }
-                    }
-                }
-            });
-        }}
-    }
+    Ok(m)
+}
 
-    match prov {
-        TunnelProvider::Localtunnel => {
-            let mut c = Command::new("lt").arg("--port").arg(port.to_string())
-                .stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().ok()?;
-            let stdout = c.stdout.take(); let stderr = c.stderr.take();
-            scan_url!(stdout, "https://");
-            drain!(stderr);
-            Some((c, rx))
-        }
-        TunnelProvider::Cloudflared => {
-            let mut c = Command::new("cloudflared").arg("tunnel").arg("--url").arg(format!("http://127.0.0.1:{port}"))
-                .stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().ok()?;
-            let stderr = c.stderr.take(); let stdout = c.stdout.take();
-            scan_url!(stderr, ".trycloudflare.com");
-            drain!(stdout);
-            Some((c, rx))
-        }
-        TunnelProvider::Serveo => {
-            let mut c = Command::new("ssh").arg("-o").arg("StrictHostKeyChecking=no")
-                .arg("-o").arg("ServerAliveInterval=60")
-                .arg("-R").arg(format!("80:localhost:{port}")).arg("serveo.net")
-                .stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().ok()?;
-            let stdout = c.stdout.take(); let stderr = c.stderr.take();
-            scan_url!(stdout, "https://");
-            drain!(stderr);
-            Some((c, rx))
-        }
+fn expand_home(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(tail) => env::var_os("HOME").map(|h| PathBuf::from(h).join(tail)).unwrap_or_else(|| path.to_path_buf()),
+        Err(_) => path.to_path_buf(),
     }
 }
 
-// ── TLS ───────────────────────────────────────────────────────────────
-
-fn generate_self_signed_tls() -> Result<tokio_rustls::TlsAcceptor, Box<dyn std::error::Error>> {
-    let cert_params = rcgen::CertificateParams::new(vec!["localhost".to_string()])?;
-    let key_pair = rcgen::KeyPair::generate()?;
-    let cert = cert_params.self_signed(&key_pair)?;
+fn parse_credentials(val: &str) -> Option<(String, String)> {
+    val.split_once(':').map(|(u, p)| (u.to_string(), p.to_string()))
+}
 
-    let cert_der = cert.der().clone();
-    let key_der = key_pair.serialize_der();
+impl Settings {
+    /// Settings from `top` replace those in `self`; mounts are merged by prefix.
+    fn overlay(self, top: Settings) -> Settings {
+        let mut mounts = self.mounts;
+        for m in top.mounts {
+            mounts.retain(|o| o.prefix() != m.prefix());
+            mounts.push(m);
+        }
+        let log = match (self.log, top.log) {
+            (Some(a), Some(b)) => Some(LogSettings { quiet: b.quiet.or(a.quiet), file: b.file.or(a.file) }),
+            (a, b) => b.or(a),
+        };
+        Settings {
+            port: top.port.or(self.port),
+            port_retries: top.port_retries.or(self.port_retries),
+            json: top.json.or(self.json),
+            qr: top.qr.or(self.qr),
+            mdns: top.mdns.or(self.mdns),
+            name: top.name.or(self.name),
+            geo: top.geo.or(self.geo),
+            geoip_db: top.geoip_db.or(self.geoip_db),
+            trust_proxy: top.trust_proxy.or(self.trust_proxy),
+            allow: top.allow.or(self.allow),
+            deny: top.deny.or(self.deny),
+            ip_rules: top.ip_rules.or(self.ip_rules),
+            rate_limit: top.rate_limit.or(self.rate_limit),
+            max_logins: top.max_logins.or(self.max_logins),
+            lockout: top.lockout.or(self.lockout),
+            session_idle: top.session_idle.or(self.session_idle),
+            session_max: top.session_max.or(self.session_max),
+            token_store: top.token_store.or(self.token_store),
+            client_ca: top.client_ca.or(self.client_ca),
+            totp_store: top.totp_store.or(self.totp_store),
+            no_auth: top.no_auth.or(self.no_auth),
+            i_know: top.i_know.or(self.i_know),
+            proxy_protocol: top.proxy_protocol.or(self.proxy_protocol),
+            bind: top.bind.or(self.bind),
+            dir: top.dir.or(self.dir),
+            auth: top.auth.or(self.auth),
+            tls: top.tls.or(self.tls),
+            hsts: top.hsts.or(self.hsts),
+            http2: top.http2.or(self.http2),
+            http3: top.http3.or(self.http3),
+            compress: top.compress.or(self.compress),
+            h2_max_streams: top.h2_max_streams.or(self.h2_max_streams),
+            h2_stream_window: top.h2_stream_window.or(self.h2_stream_window),
+            h2_conn_window: top.h2_conn_window.or(self.h2_conn_window),
+            public: top.public.or(self.public),
+            tunnel: top.tunnel.or(self.tunnel),
+            read_only: top.read_only.or(self.read_only),
+            symlinks: top.symlinks.or(self.symlinks),
+            show_hidden: top.show_hidden.or(self.show_hidden),
+            spa: top.spa.or(self.spa),
+            redirects: top.redirects.or(self.redirects),
+            headers: top.headers.or(self.headers),
+            cors: top.cors.or(self.cors),
+            cors_methods: top.cors_methods.or(self.cors_methods),
+            cors_credentials: top.cors_credentials.or(self.cors_credentials),
+            gitignore: top.gitignore.or(self.gitignore),
+            index: top.index.or(self.index),
+            index_cache: top.index_cache.or(self.index_cache),
+            mounts,
+            log,
+            profiles: self.profiles,
+        }
+    }
 
-    let certs = vec![rustls::pki_types::CertificateDer::from(cert_der.to_vec())];
-    let key = rustls::pki_types::PrivateKeyDer::try_from(key_der)
-        .map_err(|e| format!("key error: {e}"))?;
+    /// Make relative paths in a config file relative to the file, not the cwd.
+    fn rebase(&mut self, base: &Path) {
+        let fix = |p: &mut PathBuf| { if !p.starts_with("~") && p.is_relative() { *p = base.join(&*p); } };
+        if let Some(ref mut d) = self.dir { fix(d); }
+        if let Some(ref mut d) = self.index_cache { fix(d); }
+        for d in self.geoip_db.iter_mut().flatten() { fix(d); }
+        if let Some(ref mut f) = self.ip_rules { fix(f); }
+        if let Some(ref mut f) = self.log.as_mut().and_then(|l| l.file.as_mut()) { fix(f); }
+        if let Some(ref mut f) = self.token_store { fix(f); }
+        if let Some(ref mut f) = self.client_ca { fix(f); }
+        if let Some(ref mut f) = self.totp_store { fix(f); }
+        if let Some(ref mut f) = self.redirects { fix(f); }
+        if let Some(ref mut f) = self.headers { fix(f); }
+        for m in &mut self.mounts { fix(&mut m.dir); }
+        for p in self.profiles.values_mut() { p.rebase(base); }
+    }
+}
 
-    let config = rustls::ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(certs, key)?;
+fn load_config(path: &Path) -> Result<Settings, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut settings: Settings = toml::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+    if let Some((name, _)) = settings.profiles.iter().find(|(_, p)| !p.profiles.is_empty()) {
+        return Err(format!("{}: profile `{name}` cannot contain profiles", path.display()));
+    }
+    settings.rebase(path.parent().unwrap_or(Path::new(".")));
+    Ok(settings)
+}
 
-    Ok(tokio_rustls::TlsAcceptor::from(Arc::new(config)))
+/// `LEAK_*` environment variables.
+fn env_settings() -> Result<Settings, String> {
+    let var = |k: &str| env::var(k).ok().filter(|v| !v.is_empty());
+    let flag = |k: &str| -> Result<Option<bool>, String> {
+        match var(k) {
+            None => Ok(None),
+            Some(v) => match v.to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => Ok(Some(true)),
+                "0" | "false" | "no" | "off" => Ok(Some(false)),
+                _ => Err(format!("{k}: expected true or false, got `{v}`")),
+            },
+        }
+    };
+    let port = match var("LEAK_PORT") {
+        Some(p) => Some(parse_port(&p).ok_or_else(|| format!("LEAK_PORT: invalid port `{p}`"))?),
+        None => None,
+    };
+    let port_retries = match var("LEAK_PORT_RETRIES") {
+        Some(n) => Some(n.parse().map_err(|_| format!("LEAK_PORT_RETRIES: expected a number, got `{n}`"))?),
+        None => None,
+    };
+    let log = match (flag("LEAK_QUIET")?, var("LEAK_LOG_FILE")) {
+        (None, None) => None,
+        (quiet, file) => Some(LogSettings { quiet, file: file.map(PathBuf::from) }),
+    };
+    Ok(Settings {
+        port,
+        port_retries,
+        json: flag("LEAK_JSON")?,
+        qr: var("LEAK_QR"),
+        mdns: flag("LEAK_MDNS")?,
+        name: var("LEAK_NAME"),
+        geo: flag("LEAK_GEO")?,
+        geoip_db: var("LEAK_GEOIP_DB").map(|v| env::split_paths(&v).collect()),
+        trust_proxy: var("LEAK_TRUST_PROXY").map(|v| v.split(',').map(|t| t.trim().to_string()).collect()),
+        allow: var("LEAK_ALLOW").map(|v| v.split(',').map(|t| t.trim().to_string()).collect()),
+        deny: var("LEAK_DENY").map(|v| v.split(',').map(|t| t.trim().to_string()).collect()),
+        ip_rules: var("LEAK_IP_RULES").map(PathBuf::from),
+        rate_limit: var("LEAK_RATE_LIMIT").map(|v| v.split(',').map(|t| t.trim().to_string()).collect()),
+        max_logins: match var("LEAK_MAX_LOGINS") {
+            Some(n) => Some(n.parse().map_err(|_| format!("LEAK_MAX_LOGINS: expected a number, got `{n}`"))?),
+            None => None,
+        },
+        lockout: var("LEAK_LOCKOUT"),
+        session_idle: var("LEAK_SESSION_IDLE"),
+        session_max: var("LEAK_SESSION_MAX"),
+        token_store: var("LEAK_TOKEN_STORE").map(PathBuf::from),
+        client_ca: var("LEAK_CLIENT_CA").map(PathBuf::from),
+        totp_store: var("LEAK_TOTP_STORE").map(PathBuf::from),
+        no_auth: flag("LEAK_NO_AUTH")?,
+        i_know: flag("LEAK_I_KNOW")?,
+        proxy_protocol: flag("LEAK_PROXY_PROTOCOL")?,
+        bind: var("LEAK_BIND").map(|v| v.split(',').map(|b| b.trim().to_string()).collect()),
+        dir: var("LEAK_DIR").map(PathBuf::from),
+        auth: var("LEAK_AUTH"),
+        tls: flag("LEAK_TLS")?,
+        hsts: flag("LEAK_HSTS")?,
+        http2: flag("LEAK_HTTP2")?,
+        http3: flag("LEAK_HTTP3")?,
+        compress: flag("LEAK_COMPRESS")?,
+        h2_max_streams: match var("LEAK_H2_MAX_STREAMS") {
+            Some(n) => Some(n.parse().map_err(|_| format!("LEAK_H2_MAX_STREAMS: expected a number, got `{n}`"))?),
+            None => None,
+        },
+        h2_stream_window: var("LEAK_H2_STREAM_WINDOW"),
+        h2_conn_window: var("LEAK_H2_CONN_WINDOW"),
+        public: flag("LEAK_PUBLIC")?,
+        tunnel: var("LEAK_TUNNEL"),
+        read_only: flag("LEAK_READ_ONLY")?,
+        symlinks: var("LEAK_SYMLINKS"),
+        show_hidden: flag("LEAK_SHOW_HIDDEN")?,
+        spa: flag("LEAK_SPA")?,
+        redirects: var("LEAK_REDIRECTS").map(PathBuf::from),
+        headers: var("LEAK_HEADERS").map(PathBuf::from),
+        cors: var("LEAK_CORS").map(|v| vec![v]),
+        cors_methods: var("LEAK_CORS_METHODS"),
+        cors_credentials: flag("LEAK_CORS_CREDENTIALS")?,
+        gitignore: flag("LEAK_GITIGNORE")?,
+        index: flag("LEAK_INDEX")?,
+        index_cache: var("LEAK_INDEX_CACHE").map(PathBuf::from),
+        log,
+        ..Settings::default()
+    })
 }
 
 // ── Arg parsing ───────────────────────────────────────────────────────
 
 struct Args {
     port: u16,
+    port_retries: u16,
+    json: bool,
+    qr: String,
+    mdns: bool,
+    name: Option<String>,
+    geo: bool,
+    geoip_db: Vec<PathBuf>,
+    trusted: Vec<Cidr>,
+    cf_header: bool,
+    proxy_protocol: bool,
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+    ip_rules: Option<PathBuf>,
+    rates: HashMap<RouteClass, Rate>,
+    max_logins: u32,
+    lockout: std::time::Duration,
+    session_idle: std::time::Duration,
+    session_max: std::time::Duration,
+    token_store: Option<PathBuf>,
+    client_ca: Option<PathBuf>,
+    totp_store: Option<PathBuf>,
+    hsts: bool,
+    http2: Http2Options,
+    http3: bool,
+    compress: bool,
+    binds: Vec<ListenSpec>,
     dir: PathBuf,
     public: bool,
+    tunnel: Option<TunnelProvider>,
     auth: Option<(String, String)>, // (user, pass)
-    tls: bool,
+    auth_generated: bool,
+    mounts: Vec<MountSpec>,
+    read_only: bool,
+    symlinks: SymlinkPolicy,
+    show_hidden: bool,
+    gitignore: bool,
+    spa: bool,
+    redirects: Option<PathBuf>,
+    headers: Option<PathBuf>,
+    cors: Option<Cors>,
+    index: bool,
+    index_cache: Option<PathBuf>,
+    quiet: bool,
+    log_file: Option<PathBuf>,
+    config: Option<PathBuf>,
+    profile: Option<String>,
+}
+
+/// Flags that consume the following argument as their value.
+const VALUE_FLAGS: &[&str] = &[
+    "--port", "--port-retries", "--qr", "--name", "--geoip-db", "--trust-proxy", "--allow", "--deny", "--ip-rules", "--rate-limit", "--max-logins", "--lockout", "--session-idle", "--session-max", "--token-store", "--client-ca", "--totp-store", "--h2-max-streams", "--h2-stream-window", "--h2-conn-window", "--auth", "--mount", "--redirects", "--headers", "--cors", "--cors-methods", "--symlinks", "--index-cache", "--config", "--profile",
+    "--bind", "--tunnel", "--log-file",
+];
+
+/// Every value given for a repeatable flag, in order.
+fn flag_values<'a>(raw: &'a [String], flag: &str) -> Vec<&'a String> {
+    raw.iter().enumerate().filter(|(_, a)| *a == flag).filter_map(|(i, _)| raw.get(i + 1)).collect()
+}
+
+fn flag_value<'a>(raw: &'a [String], flag: &str) -> Option<&'a String> {
+    flag_values(raw, flag).pop()
+}
+
+fn print_help() {
+    eprintln!();
+    eprintln!("  {B}{CY}leak{RST} {D}v0.5.0{RST}  {D}file server with uploads, tunnels, and TLS{RST}");
+    eprintln!();
+    eprintln!("  {B}Usage:{RST}  leak {GR}[port]{RST} {D}[directory]{RST} {YL}[options]{RST}");
+    eprintln!("          leak discover {YL}[--timeout secs] [--json]{RST}");
+    eprintln!();
+    eprintln!("  {B}Options:{RST}");
+    eprintln!("    {YL}--port n|auto{RST}          {D}port to listen on; auto or 0 picks a free one{RST}");
+    eprintln!("    {YL}--port-retries n{RST}       {D}try the next n ports if busy (default 0; 20 without a port){RST}");
+    eprintln!("    {YL}--json{RST}                 {D}print a JSON startup line on stdout for scripts{RST}");
+    eprintln!("    {YL}--qr which{RST}             {D}QR code for: auto, all, none, an interface, an IP or a number{RST}");
+    eprintln!("    {YL}--name text{RST}            {D}name shown to `leak discover` (default: folder on host){RST}");
+    eprintln!("    {YL}--no-mdns{RST}              {D}don't advertise on the local network{RST}");
+    eprintln!("    {YL}--geoip-db file{RST}        {D}MaxMind/DB-IP .mmdb for client locations (repeatable){RST}");
+    eprintln!("    {YL}--no-geo{RST}               {D}don't look up client locations{RST}");
+    eprintln!("    {YL}--allow cidr{RST}           {D}only accept clients from here (repeatable){RST}");
+    eprintln!("    {YL}--deny cidr{RST}            {D}refuse clients from here (repeatable){RST}");
+    eprintln!("    {YL}--ip-rules file{RST}        {D}more allow/deny lines, reloaded on change or SIGHUP{RST}");
+    eprintln!("    {YL}--rate-limit class=n/unit{RST} {D}per-IP budget for get, upload or archive, e.g. get=20/s:50{RST}");
+    eprintln!("    {D}                           (repeatable; class=off or off to disable){RST}");
+    eprintln!("    {YL}--max-logins n{RST}         {D}failed logins before a lockout (default 5, 0 = never){RST}");
+    eprintln!("    {YL}--lockout time{RST}         {D}first lockout, doubling each time (default 30s){RST}");
+    eprintln!("    {YL}--session-idle time{RST}    {D}log browsers out after this long unused (default 30m){RST}");
+    eprintln!("    {YL}--session-max time{RST}     {D}and after this long regardless (default 12h){RST}");
+    eprintln!("    {YL}--token-store file{RST}     {D}API tokens from `leak token` (default ~/.config/leak/tokens.toml){RST}");
+    eprintln!("    {YL}--totp-store file{RST}      {D}users with a second factor, from `leak totp` (default ~/.config/leak/totp.toml){RST}");
+    eprintln!("    {YL}--client-ca file{RST}       {D}TLS clients need a cert from this CA (see `leak ca`); its CN logs in as that --auth user{RST}");
+    eprintln!("    {YL}--trust-proxy cidr{RST}     {D}take client IPs from X-Forwarded-For etc. sent by these{RST}");
+    eprintln!("    {D}                           (repeatable; also loopback, private, cloudflare){RST}");
+    eprintln!("    {YL}--proxy-protocol{RST}       {D}expect a HAProxy PROXY v1/v2 header on each connection{RST}");
+    eprintln!("    {YL}--public, -p{RST}          {D}expose via tunnel{RST}");
+    eprintln!("    {YL}--tunnel name{RST}          {D}localtunnel, cloudflared or serveo{RST}");
+    eprintln!("    {YL}--auth user:pass{RST}       {D}require basic auth (generated when --public){RST}");
+    eprintln!("    {YL}--no-auth --i-know{RST}     {D}really go --public with no password{RST}");
+    eprintln!("    {YL}--tls{RST}                  {D}enable HTTPS (self-signed); plain HTTP on the port is redirected{RST}");
+    eprintln!("    {YL}--hsts{RST}                 {D}tell browsers to only use HTTPS here for a year{RST}");
+    eprintln!("    {YL}--http3{RST}                {D}also serve HTTP/3 over QUIC on the TLS ports (UDP){RST}");
+    eprintln!("    {YL}--no-compress{RST}          {D}don't gzip/brotli/zstd responses or serve .br/.zst/.gz files{RST}");
+    eprintln!("    {YL}--no-http2{RST}             {D}speak HTTP/1.1 only (h2 is offered via ALPN, h2c by prior knowledge){RST}");
+    eprintln!("    {YL}--h2-max-streams n{RST}     {D}concurrent HTTP/2 streams per connection (default 200){RST}");
+    eprintln!("    {YL}--h2-stream-window size{RST} {D}fixed HTTP/2 flow-control windows, e.g. 1m{RST}");
+    eprintln!("    {YL}--h2-conn-window size{RST}  {D}(default: sized to the connection automatically){RST}");
+    eprintln!("    {YL}--bind addr{RST}            {D}listen here instead of everywhere (repeatable){RST}");
+    eprintln!("    {D}                           ip, ip:port, [v6]:port, unix:/path, https://ip:port{RST}");
+    eprintln!("    {YL}--mount /url=dir[,opts]{RST} {D}serve another directory under /url{RST}");
+    eprintln!("    {D}                           opts: ro, rw, public, auth=user:pass{RST}");
+    eprintln!("    {YL}--read-only{RST}            {D}disable uploads (mounts inherit this unless rw){RST}");
+    eprintln!("    {YL}--symlinks mode{RST}        {D}follow, confine (default) or deny{RST}");
+    eprintln!("    {YL}--show-hidden{RST}          {D}serve dotfiles (.leakignore still applies){RST}");
+    eprintln!("    {YL}--spa{RST}                  {D}single-page app: unknown paths get index.html{RST}");
+    eprintln!("    {YL}--redirects file{RST}       {D}redirect/rewrite rules (default: _redirects in the directory){RST}");
+    eprintln!("    {YL}--headers file{RST}         {D}extra response headers per path (default: _headers in the directory){RST}");
+    eprintln!("    {YL}--cors origins{RST}         {D}let these sites call the server from a browser (or *){RST}");
+    eprintln!("    {YL}--cors-methods list{RST}    {D}methods allowed cross-origin (default GET, HEAD, POST, OPTIONS){RST}");
+    eprintln!("    {YL}--cors-credentials{RST}     {D}allow cookies and auth on cross-origin requests{RST}");
+    eprintln!("    {YL}--gitignore{RST}            {D}also hide paths matched by .gitignore{RST}");
+    eprintln!("    {YL}--index{RST}                {D}full-text search over file contents{RST}");
+    eprintln!("    {YL}--index-cache dir{RST}      {D}where the index is persisted{RST}");
+    eprintln!("    {YL}--quiet{RST}                {D}don't print a line per request{RST}");
+    eprintln!("    {YL}--log-file path{RST}        {D}append requests to a log file{RST}");
+    eprintln!("    {YL}--config path{RST}          {D}settings file (default: leak.toml in the directory){RST}");
+    eprintln!("    {YL}--profile name{RST}         {D}apply a [profile.name] section of the settings file{RST}");
+    eprintln!();
+    eprintln!("  {B}Examples:{RST}");
+    eprintln!("    {D}${RST} leak {GR}8080{RST}");
+    eprintln!("    {D}${RST} leak {GR}8080{RST} {YL}--public{RST}");
+    eprintln!("    {D}${RST} leak {GR}443{RST} ./dist {YL}--tls --auth admin:secret{RST}");
+    eprintln!("    {D}${RST} leak {GR}8080{RST} {YL}--mount /builds=~/builds --mount /logs=/var/log/app,ro,auth=ops:pw{RST}");
+    eprintln!("    {D}${RST} leak {YL}--profile demo{RST}");
+    eprintln!();
+    eprintln!("  {D}Settings come from leak.toml, then LEAK_* environment variables, then flags.{RST}");
+    eprintln!();
+}
+
+/// Settings given as flags and positional arguments.
+fn cli_settings(raw: &[String]) -> Result<Settings, String> {
+    let on = |flag: &str| raw.iter().any(|a| a == flag).then_some(true);
+
+    let skip_flags: HashSet<usize> = {
+        let mut s = HashSet::new();
+        for (i, a) in raw.iter().enumerate() {
+            if a.starts_with('-') { s.insert(i); if VALUE_FLAGS.contains(&a.as_str()) { s.insert(i + 1); } }
+        }
+        s
+    };
+    let positional: Vec<&String> = raw.iter().enumerate()
+        .filter(|(i, _)| !skip_flags.contains(i))
+        .map(|(_, v)| v).collect();
+
+    // The port comes first but is optional: `leak ./dist` serves ./dist
+    let (port, dir) = match positional.first().and_then(|p| parse_port(p)) {
+        Some(p) => (Some(p), positional.get(1)),
+        None => (None, positional.first()),
+    };
+    if positional.len() > 1 + usize::from(port.is_some()) {
+        return Err(format!("unexpected argument: {}", positional[positional.len() - 1]));
+    }
+    let port = match flag_value(raw, "--port") {
+        Some(p) => Some(parse_port(p).ok_or_else(|| format!("invalid port: {p}"))?),
+        None => port,
+    };
+    let port_retries = match flag_value(raw, "--port-retries") {
+        Some(n) => Some(n.parse().map_err(|_| format!("--port-retries expects a number, got {n}"))?),
+        None => None,
+    };
+    let mut mounts = Vec::new();
+    for spec in flag_values(raw, "--mount") {
+        mounts.push(parse_mount(spec).map_err(|e| format!("--mount {spec}: {e}"))?);
+    }
+    let log = match (on("--quiet"), flag_value(raw, "--log-file")) {
+        (None, None) => None,
+        (quiet, file) => Some(LogSettings { quiet, file: file.map(PathBuf::from) }),
+    };
+
+    Ok(Settings {
+        port,
+        port_retries,
+        json: on("--json"),
+        qr: flag_value(raw, "--qr").cloned(),
+        mdns: on("--no-mdns").map(|_| false),
+        name: flag_value(raw, "--name").cloned(),
+        geo: on("--no-geo").map(|_| false),
+        geoip_db: Some(flag_values(raw, "--geoip-db").into_iter().map(PathBuf::from).collect::<Vec<_>>()).filter(|d| !d.is_empty()),
+        trust_proxy: Some(flag_values(raw, "--trust-proxy").into_iter().cloned().collect::<Vec<_>>()).filter(|t| !t.is_empty()),
+        allow: Some(flag_values(raw, "--allow").into_iter().cloned().collect::<Vec<_>>()).filter(|t| !t.is_empty()),
+        deny: Some(flag_values(raw, "--deny").into_iter().cloned().collect::<Vec<_>>()).filter(|t| !t.is_empty()),
+        ip_rules: flag_value(raw, "--ip-rules").map(PathBuf::from),
+        rate_limit: Some(flag_values(raw, "--rate-limit").into_iter().cloned().collect::<Vec<_>>()).filter(|t| !t.is_empty()),
+        max_logins: match flag_value(raw, "--max-logins") {
+            Some(n) => Some(n.parse().map_err(|_| format!("--max-logins expects a number, got {n}"))?),
+            None => None,
+        },
+        lockout: flag_value(raw, "--lockout").cloned(),
+        session_idle: flag_value(raw, "--session-idle").cloned(),
+        session_max: flag_value(raw, "--session-max").cloned(),
+        token_store: flag_value(raw, "--token-store").map(PathBuf::from),
+        client_ca: flag_value(raw, "--client-ca").map(PathBuf::from),
+        totp_store: flag_value(raw, "--totp-store").map(PathBuf::from),
+        no_auth: on("--no-auth"),
+        i_know: on("--i-know"),
+        proxy_protocol: on("--proxy-protocol"),
+        bind: Some(flag_values(raw, "--bind").into_iter().cloned().collect::<Vec<_>>()).filter(|b| !b.is_empty()),
+        dir: dir.map(|d| PathBuf::from(d.as_str())),
+        auth: flag_value(raw, "--auth").cloned(),
+        tls: on("--tls"),
+        hsts: on("--hsts"),
+        http2: on("--no-http2").map(|_| false),
+        http3: on("--http3"),
+        compress: on("--no-compress").map(|_| false),
+        h2_max_streams: match flag_value(raw, "--h2-max-streams") {
+            Some(n) => Some(n.parse().map_err(|_| format!("--h2-max-streams expects a number, got {n}"))?),
+            None => None,
+        },
+        h2_stream_window: flag_value(raw, "--h2-stream-window").cloned(),
+        h2_conn_window: flag_value(raw, "--h2-conn-window").cloned(),
+        public: on("--public").or(on("-p")),
+        tunnel: flag_value(raw, "--tunnel").cloned(),
+        read_only: on("--read-only"),
+        symlinks: flag_value(raw, "--symlinks").cloned(),
+        show_hidden: on("--show-hidden"),
+        spa: on("--spa"),
+        redirects: flag_value(raw, "--redirects").map(PathBuf::from),
+        headers: flag_value(raw, "--headers").map(PathBuf::from),
+        cors: Some(flag_values(raw, "--cors").into_iter().cloned().collect::<Vec<_>>()).filter(|c| !c.is_empty()),
+        cors_methods: flag_value(raw, "--cors-methods").cloned(),
+        cors_credentials: on("--cors-credentials"),
+        gitignore: on("--gitignore"),
+        index: on("--index").or(on("--index-cache")),
+        index_cache: flag_value(raw, "--index-cache").map(PathBuf::from),
+        mounts,
+        log,
+        ..Settings::default()
+    })
 }
 
 fn parse_args() -> Args {
     let raw: Vec<String> = env::args().skip(1).collect();
+    let die = |msg: String| -> ! { eprintln!("{RD}{B}Error:{RST} {msg}"); std::process::exit(1); };
 
-    if raw.iter().any(|a| a == "--help" || a == "-h") || raw.is_empty() {
-        eprintln!();
-        eprintln!("  {B}{CY}leak{RST} {D}v0.5.0{RST}  {D}file server with uploads, tunnels, and TLS{RST}");
-        eprintln!();
-        eprintln!("  {B}Usage:{RST}  leak {GR}<port>{RST} {D}[directory]{RST} {YL}[options]{RST}");
-        eprintln!();
-        eprintln!("  {B}Options:{RST}");
-        eprintln!("    {YL}--public, -p{RST}          {D}expose via tunnel{RST}");
-        eprintln!("    {YL}--auth user:pass{RST}       {D}require basic auth{RST}");
-        eprintln!("    {YL}--tls{RST}                  {D}enable HTTPS (self-signed){RST}");
-        eprintln!();
-        eprintln!("  {B}Examples:{RST}");
-        eprintln!("    {D}${RST} leak {GR}8080{RST}");
-        eprintln!("    {D}${RST} leak {GR}8080{RST} {YL}--public{RST}");
-        eprintln!("    {D}${RST} leak {GR}443{RST} ./dist {YL}--tls --auth admin:secret{RST}");
-        eprintln!();
+    if raw.iter().any(|a| a == "--help" || a == "-h") {
+        print_help();
+        std::process::exit(0);
+    }
+
+    let cli = cli_settings(&raw).unwrap_or_else(|e| die(e));
+    let envs = env_settings().unwrap_or_else(|e| die(e));
+
+    // An explicit --config must exist; otherwise look for leak.toml in the served directory
+    let config = match flag_value(&raw, "--config").cloned().or_else(|| env::var("LEAK_CONFIG").ok()) {
+        Some(p) => Some(PathBuf::from(p)),
+        None => {
+            let dir = cli.dir.clone().or_else(|| envs.dir.clone())
+                .unwrap_or_else(|| env::current_dir().expect("cannot read current directory"));
+            Some(expand_home(&dir).join("leak.toml")).filter(|p| p.is_file())
+        }
+    };
+    if raw.is_empty() && config.is_none() && envs.port.is_none() && envs.dir.is_none() {
+        print_help();
         std::process::exit(0);
     }
 
-    let public = raw.iter().any(|a| a == "--public" || a == "-p");
-    let tls = raw.iter().any(|a| a == "--tls");
+    let mut file = match config {
+        Some(ref p) => load_config(p).unwrap_or_else(|e| die(e)),
+        None => Settings::default(),
+    };
+    let profile = flag_value(&raw, "--profile").cloned().or_else(|| env::var("LEAK_PROFILE").ok());
+    if let Some(ref name) = profile {
+        let mut profiles = std::mem::take(&mut file.profiles);
+        match profiles.remove(name) {
+            Some(p) => file = file.overlay(p),
+            None if config.is_none() => die(format!("--profile {name} needs a leak.toml (or --config)")),
+            None => {
+                let mut names: Vec<&String> = profiles.keys().collect();
+                names.sort();
+                let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+                die(format!(
+                    "unknown profile `{name}` in {} (available: {})",
+                    config.as_ref().unwrap().display(),
+                    if names.is_empty() { "none".to_string() } else { names.join(", ") },
+                ))
+            }
+        }
+    }
+    let s = file.overlay(envs).overlay(cli);
+
+    // Without a port, start at 8080 and walk up to the next free one
+    let (port, port_retries) = match s.port {
+        Some(p) => (p, s.port_retries.unwrap_or(0)),
+        None => (8080, s.port_retries.unwrap_or(20)),
+    };
+    let tls = s.tls.unwrap_or(false);
+    let binds = match s.bind {
+        Some(ref specs) => specs.iter()
+            .map(|b| ListenSpec::parse(b, port, tls).unwrap_or_else(|e| die(format!("--bind {b}: {e}"))))
+            .collect(),
+        None => vec![ListenSpec { addr: ListenAddr::Any(port), tls, fixed_port: false }],
+    };
+    let window = |flag: &str, val: &Option<String>| val.as_ref().map(|v| {
+        h2_window(v).unwrap_or_else(|| die(format!("{flag} {v}: expected a size like 256k or 4m, under 2g")))
+    });
+    if s.h2_max_streams == Some(0) {
+        die("--h2-max-streams 0 would refuse every HTTP/2 request".to_string());
+    }
+    let http2 = Http2Options {
+        enabled: s.http2.unwrap_or(true),
+        max_streams: s.h2_max_streams,
+        stream_window: window("--h2-stream-window", &s.h2_stream_window),
+        conn_window: window("--h2-conn-window", &s.h2_conn_window),
+    };
+    let client_ca = s.client_ca.as_deref().map(expand_home);
+    if client_ca.is_some() && !binds.iter().any(|b: &ListenSpec| b.tls) {
+        die("--client-ca needs --tls or an https:// --bind".to_string());
+    }
+    let cors = s.cors.as_ref().map(|list| {
+        let origins: Vec<String> = list.iter().flat_map(|v| v.split(','))
+            .map(|o| o.trim().trim_end_matches('/').to_ascii_lowercase())
+            .filter(|o| !o.is_empty())
+            .collect();
+        if let Some(bad) = origins.iter().find(|o| *o != "*" && !o.starts_with("http://") && !o.starts_with("https://")) {
+            die(format!("--cors {bad}: expected an origin like https://app.example.com, or *"));
+        }
+        Cors {
+            origins,
+            methods: s.cors_methods.clone().unwrap_or_else(|| "GET, HEAD, POST, OPTIONS".to_string()),
+            credentials: s.cors_credentials.unwrap_or(false),
+        }
+    });
+    let http3 = s.http3.unwrap_or(false);
+    if http3 && !binds.iter().any(|b: &ListenSpec| b.tls) {
+        die("--http3 needs --tls or an https:// --bind".to_string());
+    }
+    let tunnel = s.tunnel.as_deref().map(|t| TunnelProvider::parse(t)
+        .unwrap_or_else(|| die(format!("unknown tunnel `{t}` (localtunnel, cloudflared or serveo)"))));
+    let auth = s.auth.as_deref().map(|a| parse_credentials(a)
+        .unwrap_or_else(|| die("auth expects user:pass".to_string())));
+    let symlinks = match s.symlinks {
+        Some(ref v) => SymlinkPolicy::parse(v).unwrap_or_else(|| die("symlinks expects follow, confine or deny".to_string())),
+        None => SymlinkPolicy::Confine,
+    };
+
+    let mut mounts = s.mounts;
+    let mut seen = HashSet::new();
+    for m in &mut mounts {
+        let prefix = m.prefix();
+        if prefix.is_empty() || prefix.contains('/') || prefix.starts_with('.') || prefix.starts_with("__") {
+            die(format!("invalid mount prefix /{prefix} (use a single path segment)"));
+        }
+        if !seen.insert(prefix.to_string()) { die(format!("/{prefix} is mounted twice")); }
+        if m.auth.as_deref().is_some_and(|a| parse_credentials(a).is_none()) {
+            die(format!("mount /{prefix}: auth expects user:pass"));
+        }
+        m.dir = expand_home(&m.dir);
+    }
+
+    let dir = s.dir.map(|d| expand_home(&d))
+        .unwrap_or_else(|| env::current_dir().expect("cannot read current directory"));
+    let log = s.log.unwrap_or_default();
+
+    let public = s.public.unwrap_or(false) || tunnel.is_some();
+
+    let (auth, auth_generated) = public_auth(public, auth, s.no_auth.unwrap_or(false), s.i_know.unwrap_or(false))
+        .unwrap_or_else(|e| die(e));
+    let mut trusted = Vec::new();
+    for spec in s.trust_proxy.iter().flatten() {
+        trusted.extend(parse_cidrs(spec).unwrap_or_else(|| die(format!("--trust-proxy {spec}: expected a CIDR, loopback, private or cloudflare"))));
+    }
+    let cf_header = s.trust_proxy.iter().flatten().any(|t| t == "cloudflare") || matches!(tunnel, Some(TunnelProvider::Cloudflared));
+    // Tunnel clients connect from this machine and forward the visitor's address
+    if public && !trusted.iter().any(|c| c.contains(IpAddr::from([127, 0, 0, 1]))) {
+        trusted.extend(parse_cidrs("loopback").unwrap_or_default());
+    }
+    let proxy_protocol = s.proxy_protocol.unwrap_or(false);
+    let cidrs = |flag: &str, specs: &Option<Vec<String>>| -> Vec<Cidr> {
+        specs.iter().flatten().flat_map(|spec| parse_cidrs(spec)
+            .unwrap_or_else(|| die(format!("{flag} {spec}: expected a CIDR, loopback or private"))))
+            .collect()
+    };
+    let (allow, deny) = (cidrs("--allow", &s.allow), cidrs("--deny", &s.deny));
+    let mut rates = default_rates();
+    for spec in s.rate_limit.iter().flatten() {
+        if spec == "off" { rates.clear(); continue; }
+        match parse_rate(spec) {
+            Some((class, Some(rate))) => { rates.insert(class, rate); }
+            Some((class, None)) => { rates.remove(&class); }
+            None => die(format!("--rate-limit {spec}: expected get|upload|archive=N/s|m|h[:burst] or off")),
+        }
+    }
+    let lockout = match s.lockout {
+        Some(ref d) => parse_duration(d).unwrap_or_else(|| die(format!("--lockout {d}: expected a duration like 30s or 5m"))),
+        None => std::time::Duration::from_secs(30),
+    };
+    let session_time = |flag: &str, val: &Option<String>, default: u64| match val {
+        Some(d) => parse_duration(d).unwrap_or_else(|| die(format!("{flag} {d}: expected a duration like 30m or 12h"))),
+        None => std::time::Duration::from_secs(default),
+    };
+    let session_idle = session_time("--session-idle", &s.session_idle, 30 * 60);
+    let session_max = session_time("--session-max", &s.session_max, 12 * 3600);
+    if proxy_protocol && trusted.is_empty() {
+        die("--proxy-protocol needs --trust-proxy for the load balancer's address".to_string());
+    }
+
+    Args {
+        port,
+        port_retries,
+        json: s.json.unwrap_or(false),
+        qr: s.qr.unwrap_or_else(|| "auto".to_string()),
+        mdns: s.mdns.unwrap_or(true),
+        name: s.name,
+        geo: s.geo.unwrap_or(true),
+        geoip_db: s.geoip_db.unwrap_or_default().iter().map(|p| expand_home(p)).collect(),
+        trusted,
+        cf_header,
+        proxy_protocol,
+        allow,
+        deny,
+        ip_rules: s.ip_rules.map(|p| expand_home(&p)),
+        rates,
+        max_logins: s.max_logins.unwrap_or(5),
+        lockout,
+        session_idle,
+        session_max,
+        token_store: s.token_store.map(|p| expand_home(&p)).or_else(|| default_config_dir().map(|d| d.join("tokens.toml"))),
+        client_ca,
+        totp_store: s.totp_store.map(|p| expand_home(&p)).or_else(|| default_config_dir().map(|d| d.join("totp.toml"))),
+        hsts: s.hsts.unwrap_or(false),
+        http2,
+        http3,
+        compress: s.compress.unwrap_or(true),
+        binds,
+        dir,
+        public,
+        tunnel,
+        auth,
+        auth_generated,
+        mounts,
+        read_only: s.read_only.unwrap_or(false),
+        symlinks,
+        show_hidden: s.show_hidden.unwrap_or(false),
+        spa: s.spa.unwrap_or(false),
+        redirects: s.redirects.map(|p| expand_home(&p)),
+        headers: s.headers.map(|p| expand_home(&p)),
+        cors,
+        gitignore: s.gitignore.unwrap_or(false),
+        index: s.index.unwrap_or(false) || s.index_cache.is_some(),
+        index_cache: s.index_cache,
+        quiet: log.quiet.unwrap_or(false),
+        log_file: log.file,
+        config,
+        profile,
+    }
+}
+
+// ── Local addresses ───────────────────────────────────────────────────
+
+/// Interface name prefixes for bridges, VPNs and VM networks. They are still
+/// listed, just after the physical NICs, so the QR code goes somewhere a phone
+/// on the same Wi-Fi can reach.
+const VIRTUAL_IFACES: &[&str] = &[
+    "docker", "br-", "veth", "virbr", "vmnet", "vboxnet", "lxc", "lxd", "cni", "flannel",
+    "tun", "tap", "utun", "wg", "tailscale", "zt", "awdl", "llw", "bridge",
+];
+
+struct LocalAddr {
+    iface: String,
+    ip: IpAddr,
+}
+
+/// Every usable non-loopback address, physical interfaces and IPv4 first.
+/// IPv6 link-local addresses are left out: their URLs need a zone id that
+/// browsers don't accept.
+fn local_addresses() -> Vec<LocalAddr> {
+    let mut addrs: Vec<LocalAddr> = if_addrs::get_if_addrs().unwrap_or_default().into_iter()
+        .filter(|i| i.is_oper_up() && reachable(i.ip()))
+        .map(|i| LocalAddr { ip: i.ip(), iface: i.name })
+        .collect();
+    sort_addresses(&mut addrs);
+    addrs
+}
+
+/// Whether another machine could use this address in a URL.
+fn reachable(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => !v4.is_loopback() && !v4.is_link_local() && !v4.is_unspecified(),
+        IpAddr::V6(v6) => !v6.is_loopback() && !v6.is_unicast_link_local() && !v6.is_unspecified(),
+    }
+}
+
+fn is_virtual_iface(name: &str) -> bool {
+    VIRTUAL_IFACES.iter().any(|p| name.starts_with(p))
+}
+
+/// Physical interfaces before bridges and VPNs, IPv4 before IPv6.
+fn sort_addresses(addrs: &mut [LocalAddr]) {
+    addrs.sort_by_key(|a| (is_virtual_iface(&a.iface), a.ip.is_ipv6()));
+}
+
+fn url_host(ip: IpAddr) -> String {
+    match ip { IpAddr::V4(v4) => v4.to_string(), IpAddr::V6(v6) => format!("[{v6}]") }
+}
+
+/// Which network URL(s) get a QR code: `auto` (the first), `all`, `none`,
+/// an interface name, an address, or a 1-based position in the list.
+fn pick_qr(choice: &str, urls: &[(String, String)]) -> Result<Vec<usize>, String> {
+    match choice {
+        "auto" => Ok((0..urls.len().min(1)).collect()),
+        "all" => Ok((0..urls.len()).collect()),
+        "none" => Ok(Vec::new()),
+        _ => {
+            if let Ok(n) = choice.parse::<usize>() {
+                return if (1..=urls.len()).contains(&n) { Ok(vec![n - 1]) } else { Err(format!("--qr {n}: only {} network address(es)", urls.len())) };
+            }
+            let hits: Vec<usize> = urls.iter().enumerate()
+                .filter(|(_, (iface, url))| iface == choice || url.contains(&format!("//{choice}:")) || url.contains(&format!("//[{choice}]:")))
+                .map(|(i, _)| i).collect();
+            if hits.is_empty() {
+                let have: Vec<String> = urls.iter().map(|(iface, url)| format!("{iface} {url}")).collect();
+                Err(format!("--qr {choice}: no such interface or address (have: {})", have.join(", ")))
+            } else { Ok(hits) }
+        }
+    }
+}
+
+// ── mDNS ──────────────────────────────────────────────────────────────
+
+const MDNS_LEAK: &str = "_leak._tcp.local.";
+
+fn machine_name() -> String {
+    let raw = std::fs::read_to_string("/proc/sys/kernel/hostname").ok()
+        .or_else(|| env::var("HOSTNAME").ok())
+        .or_else(|| env::var("COMPUTERNAME").ok())
+        .unwrap_or_default();
+    let host = raw.trim().split('.').next().unwrap_or("").to_string();
+    if host.is_empty() { "localhost".to_string() } else { host }
+}
+
+/// A DNS label: lowercase letters, digits and dashes.
+fn dns_label(name: &str) -> String {
+    let label: String = name.to_lowercase().chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let label = label.trim_matches('-').to_string();
+    if label.is_empty() { "leak".to_string() } else { label.chars().take(63).collect() }
+}
+
+/// Advertise every TCP listener as `_leak._tcp` and `_http._tcp` (or
+/// `_https._tcp`), with TXT records describing how to connect. Loopback is
+/// included so instances on the same machine can find each other.
+fn advertise(name: &str, listeners: &[(Arc<Listener>, bool)], auth: bool) -> Result<(mdns_sd::ServiceDaemon, String), String> {
+    let daemon = mdns_sd::ServiceDaemon::new().map_err(|e| e.to_string())?;
+    let _ = daemon.enable_interface(vec![mdns_sd::IfKind::LoopbackV4, mdns_sd::IfKind::LoopbackV6]);
+    let host = format!("{}.local.", dns_label(name));
+    let tcp: Vec<_> = listeners.iter().filter_map(|(l, tls)| match &**l {
+        Listener::Tcp(t) => t.local_addr().ok().map(|a| (a, *tls)),
+        #[cfg(unix)]
+        Listener::Unix(..) => None,
+    }).collect();
+    for (addr, tls) in &tcp {
+        let instance = if tcp.len() > 1 { format!("{name} ({})", addr.port()) } else { name.to_string() };
+        let props = txt_records(*tls, auth);
+        let web = if *tls { "_https._tcp.local." } else { "_http._tcp.local." };
+        for ty in [MDNS_LEAK, web] {
+            let info = if addr.ip().is_unspecified() {
+                mdns_sd::ServiceInfo::new(ty, &instance, &host, "", addr.port(), &props[..]).map(|i| i.enable_addr_auto())
+            } else {
+                mdns_sd::ServiceInfo::new(ty, &instance, &host, addr.ip(), addr.port(), &props[..])
+            };
+            daemon.register(info.map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok((daemon, host.trim_end_matches('.').to_string()))
+}
+
+/// What a listener tells `leak discover` about itself.
+fn txt_records(tls: bool, auth: bool) -> [(&'static str, &'static str); 4] {
+    [
+        ("path", "/"),
+        ("tls", if tls { "1" } else { "0" }),
+        ("auth", if auth { "1" } else { "0" }),
+        ("version", env!("CARGO_PKG_VERSION")),
+    ]
+}
 
-    let auth = raw.iter().position(|a| a == "--auth")
-        .and_then(|i| raw.get(i + 1))
-        .and_then(|val| {
-            let parts: Vec<&str> = val.splitn(2, ':').collect();
-            if parts.len() == 2 { Some((parts[0].to_string(), parts[1].to_string())) } else { None }
-        });
+/// A server `leak discover` heard from.
+struct Discovered {
+    name: String,
+    host: String,
+    port: u16,
+    tls: bool,
+    auth: bool,
+    urls: Vec<String>,
+}
 
-    let skip_flags: std::collections::HashSet<usize> = {
-        let mut s = std::collections::HashSet::new();
-        for (i, a) in raw.iter().enumerate() {
-            if a.starts_with('-') { s.insert(i); if a == "--auth" { s.insert(i + 1); } }
+impl Discovered {
+    fn from_info(info: &mdns_sd::ServiceInfo) -> Self {
+        let tls = info.get_property_val_str("tls") == Some("1");
+        let path = info.get_property_val_str("path").unwrap_or("/");
+        let scheme = if tls { "https" } else { "http" };
+        // fe80:: addresses need a zone id that URLs can't carry
+        let mut ips: Vec<&IpAddr> = info.get_addresses().iter()
+            .filter(|ip| !matches!(ip, IpAddr::V6(v6) if v6.is_unicast_link_local()))
+            .collect();
+        ips.sort_by_key(|ip| (ip.is_loopback(), ip.is_ipv6(), **ip));
+        Discovered {
+            name: info.get_fullname().trim_end_matches(MDNS_LEAK).trim_end_matches('.').to_string(),
+            host: info.get_hostname().trim_end_matches('.').to_string(),
+            port: info.get_port(),
+            tls,
+            auth: info.get_property_val_str("auth") == Some("1"),
+            urls: ips.iter().map(|ip| format!("{scheme}://{}:{}{path}", url_host(**ip), info.get_port())).collect(),
         }
-        s
-    };
-    let positional: Vec<&String> = raw.iter().enumerate()
-        .filter(|(i, _)| !skip_flags.contains(i))
-        .map(|(_, v)| v).collect();
+    }
 
-    let port: u16 = match positional.first() {
-        Some(p) => p.parse().unwrap_or_else(|_| { eprintln!("{RD}{B}Error:{RST} invalid port: {p}"); std::process::exit(1); }),
-        None => { eprintln!("{RD}{B}Error:{RST} port required. Run {B}leak --help{RST}"); std::process::exit(1); }
-    };
+    /// One line of `leak discover --json`.
+    fn json(&self) -> String {
+        let urls: Vec<String> = self.urls.iter().map(|u| format!("\"{}\"", json_escape(u))).collect();
+        format!(
+            "{{\"name\":\"{}\",\"host\":\"{}\",\"port\":{},\"tls\":{},\"auth\":{},\"urls\":[{}]}}",
+            json_escape(&self.name), json_escape(&self.host), self.port, self.tls, self.auth, urls.join(","),
+        )
+    }
+}
 
-    let dir = positional.get(1).map(|d| PathBuf::from(d.as_str()))
-        .unwrap_or_else(|| env::current_dir().expect("cannot read current directory"));
+/// `leak discover`: list leak servers that answer on the local network.
+fn discover(raw: &[String]) {
+    let die = |msg: String| -> ! { eprintln!("{RD}{B}Error:{RST} {msg}"); std::process::exit(1); };
+    let secs: f64 = match flag_value(raw, "--timeout") {
+        Some(t) => t.parse().unwrap_or_else(|_| die(format!("--timeout expects seconds, got {t}"))),
+        None => 3.0,
+    };
+    let json = raw.iter().any(|a| a == "--json");
 
-    Args { port, dir, public, auth, tls }
-}
+    let daemon = mdns_sd::ServiceDaemon::new().unwrap_or_else(|e| die(format!("mDNS unavailable: {e}")));
+    let _ = daemon.enable_interface(vec![mdns_sd::IfKind::LoopbackV4, mdns_sd::IfKind::LoopbackV6]);
+    let rx = daemon.browse(MDNS_LEAK).unwrap_or_else(|e| die(format!("mDNS browse failed: {e}")));
+    if !json { eprintln!("\n  {D}Looking for leak servers ({secs}s)...{RST}\n"); }
 
-// ── Local IP detection ────────────────────────────────────────────────
+    let deadline = Instant::now() + std::time::Duration::from_secs_f64(secs);
+    let mut found: HashMap<String, mdns_sd::ServiceInfo> = HashMap::new();
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        match rx.recv_timeout(left) {
+            Ok(mdns_sd::ServiceEvent::ServiceResolved(info)) => { found.insert(info.get_fullname().to_string(), info); }
+            Ok(mdns_sd::ServiceEvent::ServiceRemoved(_, name)) => { found.remove(&name); }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    let _ = daemon.shutdown();
 
-fn get_local_ip() -> Option<String> {
-    let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
-    socket.connect("8.8.8.8:80").ok()?;
-    Some(socket.local_addr().ok()?.ip().to_string())
+    let mut servers: Vec<_> = found.into_values().collect();
+    servers.sort_by(|a, b| a.get_fullname().cmp(b.get_fullname()));
+    for server in servers.iter().map(Discovered::from_info) {
+        if json {
+            println!("{}", server.json());
+            continue;
+        }
+        let mut flags = Vec::new();
+        if server.tls { flags.push("tls"); }
+        if server.auth { flags.push("auth"); }
+        eprintln!("  {B}{GR}●{RST} {B}{}{RST} {D}{}{RST}", server.name, flags.join(" "));
+        eprintln!("    {D}{}:{}{RST}", server.host, server.port);
+        for url in &server.urls { eprintln!("    {CY}{url}{RST}"); }
+        eprintln!();
+    }
+    if servers.is_empty() && !json { eprintln!("  {D}No leak servers found.{RST}\n"); }
 }
 
 // ── Main ──────────────────────────────────────────────────────────────
 
 #[tokio::main]
 async fn main() {
+    let raw: Vec<String> = env::args().skip(1).collect();
+    if raw.first().is_some_and(|a| a == "discover") {
+        discover(&raw[1..]);
+        return;
+    }
+    if raw.first().is_some_and(|a| a == "token") {
+        token_command(&raw[1..]);
+        return;
+    }
+    if raw.first().is_some_and(|a| a == "ca") {
+        ca_command(&raw[1..]);
+        return;
+    }
+    if raw.first().is_some_and(|a| a == "totp") {
+        totp_command(&raw[1..]);
+        return;
+    }
     let args = parse_args();
 
     let root = fs::canonicalize(&args.dir).await.unwrap_or_else(|_| {
@@ -1130,59 +6079,283 @@ async fn main() {
         base64::engine::general_purpose::STANDARD.encode(format!("{u}:{p}"))
     });
 
-    let cfg = Arc::new(ServerConfig { root: root.clone(), auth: auth_b64 });
+    let index_cache = args.index_cache.clone().or_else(default_cache_dir);
+    let new_mount = |prefix: String, root: PathBuf, upload: bool, access: Access| {
+        let hidden = Arc::new(HiddenPolicy::new(&root, args.show_hidden, args.gitignore));
+        let search = args.index.then(|| start_content_index(root.clone(), args.symlinks, hidden.clone(), index_cache.clone()));
+        Mount { prefix, root, upload, access, hidden, search }
+    };
+    let mut mounts = vec![new_mount(String::new(), root.clone(), !args.read_only, Access::Inherit)];
+    for spec in &args.mounts {
+        let dir = fs::canonicalize(&spec.dir).await.unwrap_or_else(|_| {
+            eprintln!("{RD}{B}Error:{RST} directory not found: {}", spec.dir.display());
+            std::process::exit(1);
+        });
+        let access = match (&spec.auth, spec.public) {
+            (Some(a), _) => Access::Basic(base64::engine::general_purpose::STANDARD.encode(a)),
+            (None, true) => Access::Public,
+            (None, false) => Access::Inherit,
+        };
+        mounts.push(new_mount(spec.prefix().to_string(), dir, !spec.read_only.unwrap_or(args.read_only), access));
+    }
+
+    let tokens = args.token_store.clone().map(TokenStore::open).transpose().unwrap_or_else(|e| {
+        eprintln!("{RD}{B}Error:{RST} {e}");
+        std::process::exit(1);
+    });
+    let totp = args.totp_store.clone().map(TotpStore::open).transpose().unwrap_or_else(|e| {
+        eprintln!("{RD}{B}Error:{RST} {e}");
+        std::process::exit(1);
+    });
+    let cfg = Arc::new(ServerConfig {
+        mounts,
+        auth: auth_b64,
+        symlinks: args.symlinks,
+        guard: Guard::new(args.rates.clone(), args.max_logins, args.lockout),
+        sessions: Sessions::new(args.session_idle, args.session_max),
+        tokens,
+        totp,
+        spa: args.spa,
+        redirects: Redirects::open(args.redirects.clone().unwrap_or_else(|| root.join("_redirects"))),
+        headers: HeaderRules::open(args.headers.clone().unwrap_or_else(|| root.join("_headers"))),
+    });
+
+    let listeners = bind_all(&args.binds, args.port, args.port_retries).await.unwrap_or_else(|e| {
+        eprintln!("{RD}{B}Error:{RST} {e}");
+        std::process::exit(1);
+    });
+
+    let geo = match (args.geo, args.geoip_db.is_empty()) {
+        (false, _) => None,
+        (true, true) => Some(GeoIp::discover()).filter(|g| !g.readers.is_empty()),
+        (true, false) => Some(GeoIp::open(&args.geoip_db).unwrap_or_else(|e| {
+            eprintln!("{RD}{B}Error:{RST} cannot open GeoIP database {e}");
+            std::process::exit(1);
+        })),
+    };
 
-    let scheme = if args.tls { "https" } else { "http" };
-    let addr = SocketAddr::from(([0, 0, 0, 0], args.port));
-    let listener = TcpListener::bind(addr).await.unwrap_or_else(|e| {
-        eprintln!("{RD}{B}Error:{RST} failed to bind to port {}: {e}", args.port);
+    let rules = IpRules::new(args.allow.clone(), args.deny.clone(), args.ip_rules.clone()).unwrap_or_else(|e| {
+        eprintln!("{RD}{B}Error:{RST} {e}");
         std::process::exit(1);
     });
 
     // TLS setup
-    let tls_acceptor = if args.tls {
-        match generate_self_signed_tls() {
-            Ok(a) => Some(a),
+    let tls_config = if listeners.iter().any(|(_, tls)| *tls) {
+        match generate_self_signed_tls(args.client_ca.as_deref()) {
+            Ok(c) => Some(c),
             Err(e) => { eprintln!("{RD}{B}Error:{RST} TLS setup failed: {e}"); std::process::exit(1); }
         }
     } else { None };
+    let tls_acceptor = tls_config.as_ref().map(|c| {
+        let mut c = c.clone();
+        c.alpn_protocols = if args.http2.enabled { vec![b"h2".to_vec(), b"http/1.1".to_vec()] } else { vec![b"http/1.1".to_vec()] };
+        tokio_rustls::TlsAcceptor::from(Arc::new(c))
+    });
+    // QUIC endpoints on the same ports as the TLS listeners, over UDP
+    let mut quic = Vec::new();
+    if let (true, Some(c)) = (args.http3, &tls_config) {
+        let mut c = c.clone();
+        c.alpn_protocols = vec![b"h3".to_vec()];
+        let crypto = match quinn::crypto::rustls::QuicServerConfig::try_from(c) {
+            Ok(q) => Arc::new(q),
+            Err(e) => { eprintln!("{RD}{B}Error:{RST} QUIC setup failed: {e}"); std::process::exit(1); }
+        };
+        for (l, _) in listeners.iter().filter(|(_, tls)| *tls) {
+            let Some(addr) = l.local_addr() else { continue };
+            match quinn::Endpoint::server(quinn::ServerConfig::with_crypto(crypto.clone()), addr) {
+                Ok(e) => quic.push(e),
+                Err(e) => { eprintln!("{RD}{B}Error:{RST} failed to bind UDP {addr}: {e}"); std::process::exit(1); }
+            }
+        }
+    }
 
     // Banner
     eprintln!();
     eprintln!("  {B}{CY}leak{RST} {D}v0.5.0{RST}");
     eprintln!();
 
-    let local_url = format!("{scheme}://127.0.0.1:{}", args.port);
-    eprintln!("  {B}{GR}●{RST} {B}Local:{RST}   {CY}{local_url}{RST}");
-
-    if let Some(ip) = get_local_ip() {
-        let net_url = format!("{scheme}://{ip}:{}", args.port);
-        eprintln!("  {B}{GR}●{RST} {B}Network:{RST} {CY}{net_url}{RST}");
-        // QR code for network URL
-        render_qr_terminal(&net_url);
+    // Generated credentials ride along in the QR code as a login link; chosen ones don't
+    let qr_key = cfg.auth.as_deref().filter(|_| args.auth_generated).map(|a| cfg.sessions.link_key(a));
+    let qr_url = move |url: &str| match qr_key {
+        Some(ref k) => login_link(url, k),
+        None => url.to_string(),
+    };
+    let local = local_addresses();
+    let mut urls = Vec::new();
+    let mut net_urls = Vec::new(); // (interface, url)
+    for (l, tls) in &listeners {
+        let scheme = if *tls { "https" } else { "http" };
+        match l.port().filter(|_| l.is_wildcard()) {
+            Some(port) => {
+                let local_url = format!("{scheme}://127.0.0.1:{port}");
+                eprintln!("  {B}{GR}●{RST} {B}Local:{RST}   {CY}{local_url}{RST}");
+                urls.push(local_url);
+                // A 0.0.0.0 listener (no IPv6 on this host) can't answer on v6 addresses
+                let v6 = l.is_ipv6();
+                for a in local.iter().filter(|a| v6 || a.ip.is_ipv4()) {
+                    let net_url = format!("{scheme}://{}:{port}", url_host(a.ip));
+                    eprintln!("  {B}{GR}●{RST} {B}Network:{RST} {CY}{net_url}{RST} {D}{}{RST}", a.iface);
+                    net_urls.push((a.iface.clone(), net_url.clone()));
+                    urls.push(net_url);
+                }
+            }
+            None => {
+                let url = l.describe(scheme);
+                eprintln!("  {B}{GR}●{RST} {B}Listen:{RST}  {CY}{url}{RST}");
+                urls.push(url);
+            }
+        }
+    }
+    let qr = pick_qr(&args.qr, &net_urls).unwrap_or_else(|e| {
+        eprintln!("{RD}{B}Error:{RST} {e}");
+        std::process::exit(1);
+    });
+    for &i in &qr {
+        if qr.len() > 1 { eprintln!("\n  {D}{}{RST}", net_urls[i].1); }
+        render_qr_terminal(&qr_url(&net_urls[i].1));
+    }
+    if args.json {
+        let port = listeners.iter().find_map(|(l, _)| l.port());
+        let urls: Vec<String> = urls.iter().map(|u| format!("\"{}\"", json_escape(u))).collect();
+        println!(
+            "{{\"event\":\"listening\",\"pid\":{},\"port\":{},\"urls\":[{}]{}}}",
+            std::process::id(), port.map_or("null".to_string(), |p| p.to_string()), urls.join(","),
+            match args.auth {
+                Some((ref u, ref p)) if args.auth_generated => format!(
+                    ",\"user\":\"{}\",\"password\":\"{}\",\"login_key\":\"{}\"",
+                    json_escape(u), json_escape(p), cfg.auth.as_deref().map(|a| cfg.sessions.link_key(a)).unwrap_or_default(),
+                ),
+                _ => String::new(),
+            },
+        );
     }
 
     eprintln!("  {D}  Root:    {}{RST}", root.display());
-    if args.auth.is_some() { eprintln!("  {D}  Auth:    enabled{RST}"); }
-    if args.tls { eprintln!("  {D}  TLS:     self-signed{RST}"); }
+    if let Some(ref path) = args.config {
+        match args.profile {
+            Some(ref name) => eprintln!("  {D}  Config:  {} (profile {name}){RST}", path.display()),
+            None => eprintln!("  {D}  Config:  {}{RST}", path.display()),
+        }
+    }
+    for m in cfg.mounts.iter().skip(1) {
+        let mut flags = vec![if m.upload { "uploads" } else { "read-only" }];
+        match m.access { Access::Inherit => {}, Access::Public => flags.push("public"), Access::Basic(_) => flags.push("own auth") }
+        eprintln!("  {D}  Mount:   /{} → {} ({}){RST}", m.prefix, m.root.display(), flags.join(", "));
+    }
+    match args.auth {
+        Some((ref u, ref p)) if args.auth_generated => {
+            eprintln!("  {B}{YL}●{RST} {B}Login:{RST}   {B}{u}{RST} / {B}{YL}{p}{RST} {D}(generated for --public){RST}");
+        }
+        Some(_) => eprintln!("  {D}  Auth:    enabled{RST}"),
+        None if args.public => eprintln!("  {RD}●{RST} {B}Auth:{RST}    {RD}none: anyone with the URL has access{RST}"),
+        None => {}
+    }
+    let any_auth = cfg.mounts.iter().any(|m| cfg.required_auth(m).is_some());
+    eprintln!("  {D}  Limits:  {}{RST}", cfg.guard.summary(any_auth));
+    if let Some(ref t) = cfg.tokens {
+        match t.active() {
+            0 => {}
+            n => eprintln!("  {D}  Tokens:  {n} active ({}){RST}", t.path.display()),
+        }
+    }
+    if let Some(ref t) = cfg.totp {
+        match t.count() {
+            0 => {}
+            n => eprintln!("  {D}  2FA:     {n} user{} ({}){RST}", if n == 1 { "" } else { "s" }, t.path.display()),
+        }
+    }
+    if args.read_only { eprintln!("  {D}  Uploads: disabled{RST}"); }
+    if tls_acceptor.is_some() {
+        let ca = args.client_ca.as_ref().map(|ca| format!(" · client certs from {}", ca.display())).unwrap_or_default();
+        let hsts = if args.hsts { " · HSTS" } else { "" };
+        let h3 = if args.http3 { " · HTTP/3" } else { "" };
+        eprintln!("  {D}  TLS:     self-signed{ca}{hsts}{h3}{RST}");
+    }
+    match cfg.redirects.count() {
+        0 => {}
+        n => eprintln!("  {D}  Rules:   {n} redirect{} from {}{RST}", if n != 1 { "s" } else { "" }, cfg.redirects.path.display()),
+    }
+    match cfg.headers.count() {
+        0 => {}
+        n => eprintln!("  {D}  Headers: {n} path{} from {}{RST}", if n != 1 { "s" } else { "" }, cfg.headers.path.display()),
+    }
+    if let Some(ref c) = args.cors {
+        let creds = if c.credentials { " · with credentials" } else { "" };
+        eprintln!("  {D}  CORS:    {}{creds}{RST}", c.origins.join(", "));
+    }
+    if args.spa { eprintln!("  {D}  Site:    single-page app (unknown paths get index.html){RST}"); }
+    if args.symlinks != SymlinkPolicy::Confine { eprintln!("  {D}  Links:   {}{RST}", args.symlinks.name()); }
+    match (args.show_hidden, cfg.mounts.iter().map(|m| m.hidden.rule_count()).sum::<usize>()) {
+        (false, 0) => {}
+        (show, n) => eprintln!(
+            "  {D}  Hidden:  {}{n} ignore rule{}{RST}",
+            if show { "dotfiles shown, " } else { "" }, if n != 1 { "s" } else { "" },
+        ),
+    }
+    if args.index {
+        match index_cache {
+            Some(ref dir) => eprintln!("  {D}  Index:   cached in {}{RST}", dir.display()),
+            None => eprintln!("  {D}  Index:   in memory only{RST}"),
+        }
+    }
+    if !rules.is_empty() {
+        eprintln!("  {D}  IPs:     {}{RST}", rules.summary());
+        if let Some(ref f) = rules.file { eprintln!("  {D}           from {} (reloads on change or SIGHUP){RST}", f.display()); }
+    }
+    if !args.trusted.is_empty() {
+        let nets: Vec<String> = args.trusted.iter().map(|c| c.to_string()).collect();
+        let pp = if args.proxy_protocol { " (PROXY protocol)" } else { "" };
+        let cf = if args.cf_header { " (CF-Connecting-IP)" } else { "" };
+        eprintln!("  {D}  Proxies: {}{pp}{cf}{RST}", nets.join(", "));
+    }
+    match geo {
+        Some(ref g) => {
+            let files: Vec<String> = g.readers.iter()
+                .map(|(p, _)| p.file_name().unwrap_or_default().to_string_lossy().into_owned()).collect();
+            eprintln!("  {D}  GeoIP:   {}{RST}", files.join(", "));
+        }
+        None if !args.geo => eprintln!("  {D}  GeoIP:   off{RST}"),
+        None => {}
+    }
+    let mdns = if args.mdns {
+        let name = args.name.clone().unwrap_or_else(|| {
+            let dir = root.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_else(|| "files".into());
+            format!("{dir} on {}", machine_name())
+        });
+        match advertise(&name, &listeners, cfg.auth.is_some()) {
+            Ok((daemon, host)) => {
+                eprintln!("  {D}  mDNS:    {name} ({host}){RST}");
+                Some(daemon)
+            }
+            Err(e) => { eprintln!("  {D}  mDNS:    unavailable ({e}){RST}"); None }
+        }
+    } else { None };
 
     // Tunnel
     let mut _tunnel_child: Option<tokio::process::Child> = None;
     if args.public {
-        match detect_tunnel().await {
+        match detect_tunnel(args.tunnel).await {
             Some(provider) => {
                 eprintln!("  {D}  Tunnel:  connecting via {}...{RST}", provider.name());
-                match start_tunnel(&provider, args.port).await {
+                let port = listeners.iter().find(|(l, tls)| !tls && l.port().is_some()).and_then(|(l, _)| l.port()).unwrap_or(args.port);
+                match start_tunnel(&provider, port).await {
                     Some((child, rx)) => {
                         _tunnel_child = Some(child);
                         match tokio::time::timeout(std::time::Duration::from_secs(15), rx).await {
-                            Ok(Ok(url)) => eprintln!("\x1b[1A\x1b[2K  {B}{MG}●{RST} {B}Public:{RST}  {CY}{B}{url}{RST}"),
+                            Ok(Ok(url)) => {
+                                eprintln!("\x1b[1A\x1b[2K  {B}{MG}●{RST} {B}Public:{RST}  {CY}{B}{url}{RST}");
+                                if args.json { println!("{{\"event\":\"public\",\"url\":\"{}\"}}", json_escape(&url)); }
+                            }
                             _ => eprintln!("\x1b[1A\x1b[2K  {YL}●{RST} {B}Public:{RST}  {D}timed out{RST}"),
                         }
                     }
                     None => eprintln!("  {RD}●{RST} {B}Public:{RST}  {D}failed to start {}{RST}", provider.name()),
                 }
             }
+            None if args.tunnel.is_some() => {
+                let t = args.tunnel.unwrap();
+                eprintln!("  {RD}●{RST} {B}Public:{RST}  {D}{} not found (needs {}){RST}", t.name(), t.command());
+            }
             None => {
                 eprintln!("  {RD}●{RST} {B}Public:{RST}  {D}no tunnel tool found{RST}");
                 eprintln!("  {D}  Install: npm i -g localtunnel | brew install cloudflared{RST}");
@@ -1190,80 +6363,988 @@ async fn main() {
         }
     }
 
+    // Enter shows the QR code for the next network address
+    let cycle = qr.len() == 1 && net_urls.len() > 1 && std::io::IsTerminal::is_terminal(&std::io::stdin());
+
     eprintln!();
-    eprintln!("  {D}Ctrl+C to stop{RST}");
+    if cycle { eprintln!("  {D}Enter for the next QR code · Ctrl+C to stop{RST}"); }
+    else { eprintln!("  {D}Ctrl+C to stop{RST}"); }
     eprintln!();
 
-    // Track seen IPs for geolocation
-    let seen_ips: Arc<tokio::sync::Mutex<std::collections::HashSet<String>>> =
-        Arc::new(tokio::sync::Mutex::new(std::collections::HashSet::new()));
+    if cycle {
+        let mut next = qr[0];
+        std::thread::spawn(move || {
+            let mut line = String::new();
+            while std::io::stdin().read_line(&mut line).is_ok_and(|n| n > 0) {
+                next = (next + 1) % net_urls.len();
+                let (iface, url) = &net_urls[next];
+                eprintln!("  {B}{GR}●{RST} {B}Network:{RST} {CY}{url}{RST} {D}{iface}{RST}");
+                render_qr_terminal(&qr_url(url));
+                line.clear();
+            }
+        });
+    }
+
+    let log = RequestLog {
+        quiet: args.quiet,
+        file: args.log_file.as_ref().map(|path| {
+            let file = std::fs::OpenOptions::new().create(true).append(true).open(path).unwrap_or_else(|e| {
+                eprintln!("{RD}{B}Error:{RST} cannot open log file {}: {e}", path.display());
+                std::process::exit(1);
+            });
+            std::sync::Mutex::new(file)
+        }),
+    };
+    let ctx = Arc::new(Context {
+        cfg: cfg.clone(),
+        log,
+        seen_ips: tokio::sync::Mutex::new(HashSet::new()),
+        geo,
+        trusted: args.trusted.clone(),
+        cf_header: args.cf_header,
+        proxy_protocol: args.proxy_protocol,
+        rules,
+        blocked: std::sync::Mutex::new(HashSet::new()),
+        hsts: args.hsts,
+        http: http_builder(&args.http2),
+        h3: args.http3,
+        compress: args.compress,
+        cors: args.cors.clone(),
+    });
 
-    loop {
-        tokio::select! {
-            result = listener.accept() => {
-                let (stream, remote) = match result {
-                    Ok(conn) => conn,
-                    Err(e) => { eprintln!("  {RD}accept error:{RST} {e}"); continue; }
-                };
+    // SIGHUP re-reads the IP rules file
+    #[cfg(unix)]
+    if ctx.rules.file.is_some() {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let Ok(mut hup) = signal::unix::signal(signal::unix::SignalKind::hangup()) else { return };
+            while hup.recv().await.is_some() {
+                match ctx.rules.reload() {
+                    Ok(()) => eprintln!("  {} {D}ip rules reloaded ({}){RST}", ts(), ctx.rules.summary()),
+                    Err(e) => eprintln!("  {} {RD}ip rules not reloaded:{RST} {e}", ts()),
+                }
+                ctx.blocked.lock().unwrap().clear();
+            }
+        });
+    }
 
-                let cfg = cfg.clone();
-                let tls_acceptor = tls_acceptor.clone();
-                let seen_ips = seen_ips.clone();
+    for (l, tls) in &listeners {
+        let acceptor = if *tls { tls_acceptor.clone() } else { None };
+        tokio::spawn(accept_loop(l.clone(), acceptor, ctx.clone()));
+    }
+    for endpoint in quic {
+        tokio::spawn(quic_accept_loop(endpoint, ctx.clone()));
+    }
 
-                tokio::spawn(async move {
-                    // Geo-lookup for new IPs
-                    let ip_str = remote.ip().to_string();
-                    {
-                        let mut seen = seen_ips.lock().await;
-                        if !seen.contains(&ip_str) {
-                            seen.insert(ip_str.clone());
-                            let ip_clone = ip_str.clone();
-                            tokio::spawn(async move {
-                                if let Some(loc) = geolocate_ip(&ip_clone).await {
-                                    eprintln!("  {} {MG}{B}CONNECT{RST} {CY}{}{RST} {D}({}){RST}", ts(), ip_clone, loc);
-                                } else {
-                                    eprintln!("  {} {MG}{B}CONNECT{RST} {CY}{}{RST}", ts(), ip_clone);
-                                }
-                            });
-                        }
-                    }
+    let _ = signal::ctrl_c().await;
+    eprintln!("\n  {D}Shutting down...{RST}");
+    if let Some(ref mut child) = _tunnel_child { let _ = child.kill().await; }
+    for s in cfg.mounts.iter().filter_map(|m| m.search.as_ref()) { s.save(); }
+    for (l, _) in &listeners { l.cleanup(); }
+    // Goodbye packets, so browsers drop us right away
+    if let Some(daemon) = mdns {
+        if let Ok(rx) = daemon.shutdown() { let _ = rx.recv_timeout(std::time::Duration::from_millis(500)); }
+    }
+}
 
-                    let svc = service_fn(move |req: Request<Incoming>| {
-                        let cfg = cfg.clone();
-                        let method = req.method().to_string();
-                        let path = req.uri().path().to_string();
-                        let remote = remote;
-                        async move {
-                            let resp = serve(cfg, req).await;
-                            let code = resp.as_ref().map(|r| r.status().as_u16()).unwrap_or(500);
-                            if method != "POST" || !path.ends_with("/__upload") {
-                                println!("  {} {} {} {CY}{}{RST} {D}{}{RST}", ts(), status_style(code), method_style(&method), path, remote.ip());
-                            }
-                            resp
-                        }
-                    });
-
-                    if let Some(acceptor) = tls_acceptor {
-                        match acceptor.accept(stream).await {
-                            Ok(tls_stream) => {
-                                let io = TokioIo::new(tls_stream);
-                                let _ = Builder::new(hyper_util::rt::TokioExecutor::new())
-                                    .http1().serve_connection(io, svc).await;
-                            }
-                            Err(_) => {} // TLS handshake failed, ignore
-                        }
-                    } else {
-                        let io = TokioIo::new(stream);
-                        let _ = Builder::new(hyper_util::rt::TokioExecutor::new())
-                            .http1().serve_connection(io, svc).await;
-                    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory under the system temp dir, private to this test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("leak-test-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write `(path, contents)` pairs under `dir`, creating parent directories.
+    fn write_tree(dir: &Path, files: &[(&str, &str)]) {
+        for (path, contents) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+    }
+
+    /// A scratch tree: `<tmp>/root/{docs/a.txt, inside -> docs, outside -> ../outside}`
+    /// and `<tmp>/outside/secret.txt`.
+    fn scratch(name: &str) -> (PathBuf, PathBuf) {
+        let base = temp_dir(name);
+        write_tree(&base, &[("root/docs/a.txt", "a"), ("outside/secret.txt", "secret")]);
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(base.join("root/docs"), base.join("root/inside")).unwrap();
+            std::os::unix::fs::symlink(base.join("outside"), base.join("root/outside")).unwrap();
+        }
+        let root = base.join("root").canonicalize().unwrap();
+        (base, root)
+    }
+
+    /// A server over `root` plus `(prefix, dir, access)` mounts, with `auth` as "user:pass".
+    fn server(root: &Path, mounts: Vec<(&str, PathBuf, Access)>, auth: Option<&str>) -> Arc<ServerConfig> {
+        let mount = |prefix: &str, root: PathBuf, access| Mount {
+            prefix: prefix.into(), hidden: Arc::new(HiddenPolicy::new(&root, false, false)), root, upload: false, access, search: None,
+        };
+        let mut all = vec![mount("", root.to_path_buf(), Access::Inherit)];
+        all.extend(mounts.into_iter().map(|(p, d, a)| mount(p, d, a)));
+        Arc::new(ServerConfig {
+            mounts: all,
+            auth: auth.map(|a| base64::engine::general_purpose::STANDARD.encode(a)),
+            symlinks: SymlinkPolicy::Confine,
+            guard: Guard::new(HashMap::new(), 5, std::time::Duration::from_secs(30)),
+            sessions: Sessions::new(std::time::Duration::from_secs(60), std::time::Duration::from_secs(3600)),
+            tokens: None,
+            totp: None,
+            spa: false,
+            redirects: Redirects::open(root.join("_redirects")),
+            headers: HeaderRules::open(root.join("_headers")),
+        })
+    }
+
+    /// `GET path` through `serve` with the given request headers.
+    async fn get(cfg: &Arc<ServerConfig>, path: &str, headers: &[(&str, &str)]) -> Response<String> {
+        let mut req = Request::get(path);
+        for (name, value) in headers { req = req.header(*name, *value); }
+        let Ok(resp) = serve(cfg.clone(), req.body(Full::new(Bytes::new())).unwrap()).await;
+        let (parts, body) = resp.into_parts();
+        let body = http_body_util::BodyExt::collect(body).await.unwrap().to_bytes();
+        Response::from_parts(parts, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[test]
+    fn private_ranges_are_not_public() {
+        for ip in ["10.1.2.3", "172.16.0.1", "172.31.255.255", "192.168.1.1", "127.0.0.1", "100.64.0.1", "169.254.1.1", "::1", "fd00::1", "fe80::1", "::ffff:10.0.0.1"] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["172.15.0.1", "172.32.0.1", "172.217.0.1", "8.8.8.8", "100.128.0.1", "2606:4700::1111", "::ffff:1.1.1.1"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn forwarded_headers_only_count_from_trusted_peers() {
+        let trusted = parse_cidrs("loopback").unwrap();
+        let mut h = hyper::HeaderMap::new();
+        h.insert("x-forwarded-for", "203.0.113.9, 198.51.100.7, 127.0.0.1".parse().unwrap());
+        let lo = Some(IpAddr::from([127, 0, 0, 1]));
+        // The first untrusted hop from the right is the client
+        assert_eq!(client_ip(&h, lo, &trusted, false), "198.51.100.7".parse().ok());
+        // An untrusted peer can't claim to be anyone else
+        assert_eq!(client_ip(&h, "192.0.2.1".parse().ok(), &trusted, false), "192.0.2.1".parse().ok());
+        h.insert("forwarded", "for=\"[2001:db8::1]:4711\";proto=https".parse().unwrap());
+        assert_eq!(client_ip(&h, lo, &trusted, false), "2001:db8::1".parse().ok());
+        h.insert("cf-connecting-ip", "192.0.2.55".parse().unwrap());
+        assert_eq!(client_ip(&h, lo, &trusted, true), "192.0.2.55".parse().ok());
+        assert_eq!(client_ip(&h, lo, &trusted, false), "2001:db8::1".parse().ok());
+        assert!(parse_cidrs("cloudflare").unwrap().iter().any(|c| c.contains("104.16.1.1".parse().unwrap())));
+    }
+
+    #[test]
+    fn forged_cloudflare_header_cannot_dodge_lockout() {
+        // --public trusts loopback, where tunnel clients connect from
+        let trusted = parse_cidrs("loopback").unwrap();
+        let lo = Some(IpAddr::from([127, 0, 0, 1]));
+        let guard = Guard::new(HashMap::new(), 2, std::time::Duration::from_secs(30));
+        for n in 0..4 {
+            let mut h = hyper::HeaderMap::new();
+            h.insert("x-forwarded-for", "203.0.113.9".parse().unwrap());
+            h.insert("cf-connecting-ip", format!("192.0.2.{n}").parse().unwrap());
+            let ip = client_ip(&h, lo, &trusted, false).unwrap();
+            assert_eq!(ip, IpAddr::from([203, 0, 113, 9]));
+            guard.login_failed(ip);
+        }
+        assert!(guard.locked("203.0.113.9".parse().unwrap()).is_some());
+    }
+
+    #[test]
+    fn cidr_rules() {
+        let net = Cidr::parse("172.16.0.0/12").unwrap();
+        assert!(net.contains("172.31.1.1".parse().unwrap()));
+        assert!(net.contains("::ffff:172.16.0.9".parse().unwrap()));
+        assert!(!net.contains("172.32.0.1".parse().unwrap()));
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains("8.8.8.8".parse().unwrap()));
+        assert!(Cidr::parse("2001:db8::/32").unwrap().contains("2001:db8:1::1".parse().unwrap()));
+        assert_eq!(Cidr::parse("10.0.0.0/33"), None);
+        let (allow, deny) = parse_rules_file("# office\nallow 10.0.0.0/8\ndeny 10.9.9.9 # abuse\n").unwrap();
+        let rules = IpRules::new(allow, deny, None).unwrap();
+        assert!(rules.permits("10.1.1.1".parse().ok()));
+        assert!(!rules.permits("10.9.9.9".parse().ok()));
+        assert!(!rules.permits("192.0.2.1".parse().ok()));
+        assert!(rules.permits(None));
+    }
+
+    #[test]
+    fn rate_limits_and_lockouts() {
+        assert_eq!(parse_rate("archive=off"), Some((RouteClass::Archive, None)));
+        assert_eq!(parse_rate("get=2/s:3").unwrap().1.unwrap().burst, 3.0);
+        assert_eq!(parse_rate("get=0/s"), None);
+        assert_eq!(parse_rate("post=1/s"), None);
+
+        let rates = HashMap::from([parse_rate("get=1/h:2").map(|(c, r)| (c, r.unwrap())).unwrap()]);
+        let guard = Guard::new(rates, 2, std::time::Duration::from_secs(30));
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        assert!(guard.take(ip, RouteClass::Get).is_ok());
+        assert!(guard.take(ip, RouteClass::Get).is_ok());
+        assert!(guard.take(ip, RouteClass::Get).unwrap_err() > std::time::Duration::from_secs(3000));
+        assert!(guard.take(ip, RouteClass::Upload).is_ok());
+
+        guard.login_failed(ip);
+        assert!(guard.locked(ip).is_none());
+        guard.login_failed(ip);
+        assert!(guard.locked(ip).unwrap() > std::time::Duration::from_secs(25));
+        // The next lockout is twice as long
+        guard.login_failed(ip);
+        guard.login_failed(ip);
+        assert!(guard.locked(ip).unwrap() > std::time::Duration::from_secs(55));
+        guard.login_ok(ip);
+        assert!(guard.locked(ip).is_none());
+
+        // A fresh IPv6 address from the same /64 is the same client
+        let a: IpAddr = "2001:db8:1:2::a".parse().unwrap();
+        let b: IpAddr = "2001:db8:1:2:ffff::b".parse().unwrap();
+        assert_eq!(guard_key(a), guard_key(b));
+        assert_ne!(guard_key(a), guard_key("2001:db8:1:3::a".parse().unwrap()));
+        assert_eq!(guard_key("::ffff:192.0.2.1".parse().unwrap()), ip);
+        guard.login_failed(a);
+        guard.login_failed(b);
+        assert!(guard.locked("2001:db8:1:2::c".parse().unwrap()).is_some());
+        assert!(guard.take(a, RouteClass::Get).is_ok() && guard.take(b, RouteClass::Get).is_ok());
+        assert!(guard.take("2001:db8:1:2::d".parse().unwrap(), RouteClass::Get).is_err());
+    }
+
+    #[test]
+    fn session_cookies() {
+        let sessions = Sessions::new(std::time::Duration::from_secs(60), std::time::Duration::from_secs(3600));
+        let cookie = sessions.login(None, "dTpw");
+        let s = sessions.lookup(&cookie).unwrap();
+        assert!(s.creds.contains("dTpw"));
+        // A second login moves to a new session, taking the first one's creds
+        let second = sessions.login(Some(&s), "djpx");
+        assert_ne!(second, cookie);
+        assert!(sessions.lookup(&cookie).is_none());
+        let s2 = sessions.lookup(&second).unwrap();
+        assert_eq!(s2.creds.len(), 2);
+        assert_ne!(s2.csrf, s.csrf);
+        let cookie = second;
+
+        let (id, _) = cookie.split_once('.').unwrap();
+        assert!(sessions.lookup(&format!("{id}.forged")).is_none());
+        assert!(sessions.lookup(id).is_none());
+        sessions.logout(id);
+        assert!(sessions.lookup(&cookie).is_none());
+
+        let expired = Sessions::new(std::time::Duration::ZERO, std::time::Duration::from_secs(3600));
+        let cookie = expired.login(None, "dTpw");
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        assert!(expired.lookup(&cookie).is_none());
+
+        // QR login links are tied to the credential and the server's key
+        let key = sessions.link_key("dTpw");
+        assert_eq!(key, sessions.link_key("dTpw"));
+        assert_ne!(key, sessions.link_key("djpx"));
+        assert_ne!(key, expired.link_key("dTpw"));
+        assert_eq!(login_link("http://10.0.0.2:8080/", &key), format!("http://10.0.0.2:8080/__login?key={key}"));
+
+        assert_eq!(safe_next("/docs/?q=1"), "/docs/?q=1");
+        assert_eq!(safe_next("//evil.example/"), "/");
+        assert_eq!(safe_next("https://evil.example/"), "/");
+        assert_eq!(safe_next("/\\evil.example"), "/");
+    }
+
+    #[test]
+    fn token_scopes() {
+        let g = Grant { scope: "artifacts".into(), perm: Perm::Upload };
+        assert!(g.covers("artifacts") && g.covers("artifacts/x/__upload"));
+        assert!(!g.covers("artifacts2") && !g.covers("") && !g.covers("secret/artifacts"));
+        assert!(Grant { scope: String::new(), perm: Perm::Read }.covers("anything"));
+        assert!(Perm::Upload.allows(RouteClass::Upload) && !Perm::Upload.allows(RouteClass::Get));
+        assert!(!Perm::Read.allows(RouteClass::Upload) && Perm::Read.allows(RouteClass::Archive));
+        assert_eq!(parse_duration("30d"), Some(std::time::Duration::from_secs(30 * 86400)));
+    }
+
+    #[test]
+    fn client_cert_names() {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(vec!["ops-box.internal".to_string()]).unwrap();
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params.distinguished_name.push(rcgen::DnType::OrganizationName, "Ops");
+        let san_only = params.clone().self_signed(&key).unwrap();
+        assert_eq!(cert_username(san_only.der()).as_deref(), Some("ops-box.internal"));
+        params.distinguished_name.push(rcgen::DnType::CommonName, "alice");
+        assert_eq!(cert_username(params.self_signed(&key).unwrap().der()).as_deref(), Some("alice"));
+        assert_eq!(cert_username(&[0x30, 0x82, 0xff]), None);
+        assert_eq!(auth_user("YWxpY2U6cHc=").as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn totp_codes() {
+        // RFC 6238 appendix B, SHA-1, last six digits
+        let secret = base32_encode(b"12345678901234567890");
+        assert_eq!(secret, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(base32_decode("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").unwrap(), b"12345678901234567890");
+        assert_eq!(totp_step(&secret, "287082", 59), Some(1));
+        assert_eq!(totp_step(&secret, "081804", 1111111109), Some(37037036));
+        // One step of drift either way, no more
+        assert_eq!(totp_step(&secret, "287082", 89), Some(1));
+        assert_eq!(totp_step(&secret, "287082", 120), None);
+        assert_eq!(totp_step(&secret, "28708", 59), None);
+        assert_eq!(base32_encode(b"f"), "MY");
+        assert_eq!(normalize_recovery(" AB2C-d3ef "), "ab2cd3ef");
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1048576"), Some(1 << 20));
+        assert_eq!(parse_size("64k"), Some(64 << 10));
+        assert_eq!(parse_size("4MiB"), Some(4 << 20));
+        assert_eq!(parse_size("1g"), Some(1 << 30));
+        assert_eq!(parse_size("lots"), None);
+    }
+
+    #[test]
+    fn byte_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Ok((0, 99))));
+        assert_eq!(parse_range("bytes=900-", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=500-5000", 1000), Some(Ok((500, 999))));
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+    }
+
+    #[test]
+    fn redirect_rules() {
+        let (rules, errors) = parse_redirects("# moved docs\n/blog/:year/:slug  /posts/:slug?y=:year\n/docs/*  /v2/:splat  302\n/old.php  https://example.com/\n/app/*  /index.html  200\n/bad\n/x /y 418\n/y /z 301!\n");
+        assert_eq!(rules.len(), 4);
+        assert_eq!(errors.len(), 3);
+        assert!(errors[2].starts_with("line 8: drop the `!`"));
+        let hit = |path: &str| rules.iter().find_map(|r| r.matches(path).map(|c| (r.status, r.target(&c))));
+        assert_eq!(hit("/blog/2024/hello"), Some((301, "/posts/hello?y=2024".to_string())));
+        assert_eq!(hit("/blog/2024"), None);
+        assert_eq!(hit("/docs/a/b.html"), Some((302, "/v2/a/b.html".to_string())));
+        assert_eq!(hit("/docs"), Some((302, "/v2/".to_string())));
+        assert_eq!(hit("/old.php"), Some((301, "https://example.com/".to_string())));
+        assert_eq!(hit("/app/settings/profile"), Some((200, "/index.html".to_string())));
+    }
+
+    #[test]
+    fn header_rules() {
+        let (blocks, errors) = parse_headers_file("/assets/*\n  Cache-Control: max-age=31536000\n/*.html\n  X-Frame-Options: DENY\n  bad line\n  Broken Name: x\n");
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].headers.len(), 1);
+        assert_eq!(errors.len(), 2);
+        assert!(header_path_matches("/assets/*", "/assets/js/app.js"));
+        assert!(header_path_matches("/assets/*", "/assets"));
+        assert!(!header_path_matches("/assets/*", "/assetsx"));
+        assert!(header_path_matches("/*", "/anything/at/all"));
+        assert!(header_path_matches("/*.html", "/about.html"));
+        assert!(!header_path_matches("/*.html", "/docs/about.html"));
+
+        let cors = Cors { origins: vec!["https://app.example.com".into()], methods: "GET".into(), credentials: false };
+        assert_eq!(cors.allow("https://APP.example.com/").unwrap(), "https://APP.example.com/");
+        assert!(cors.allow("https://evil.example").is_none());
+        let any = Cors { origins: vec!["*".into()], methods: "GET".into(), credentials: true };
+        assert_eq!(any.allow("https://x.test").unwrap(), "https://x.test");
+    }
+
+    #[test]
+    fn accept_encoding() {
+        use Encoding::*;
+        assert_eq!(accepted_encodings("gzip, deflate, br, zstd"), vec![Br, Zstd, Gzip]);
+        assert_eq!(accepted_encodings("gzip;q=1.0, br;q=0.5"), vec![Gzip, Br]);
+        assert_eq!(accepted_encodings("*;q=0.1, br;q=0"), vec![Zstd, Gzip]);
+        assert_eq!(accepted_encodings("identity"), vec![]);
+        assert!(compressible("application/javascript; charset=utf-8"));
+        assert!(!compressible("image/png"));
+    }
+
+    #[tokio::test]
+    async fn compression_leaves_other_responses_alone() {
+        let text = "leak ".repeat(400);
+        let packed = compress_response(http_response(StatusCode::OK, text.clone(), "text/plain"), &[Encoding::Gzip]).await;
+        assert_eq!(packed.headers()["content-encoding"], "gzip");
+        assert_eq!(packed.headers()["vary"], "Accept-Encoding");
+        // No acceptable encoding, an image, or too little to bother with
+        for (body, ctype, encodings) in [
+            (text.clone(), "text/plain", &[][..]),
+            (text.clone(), "image/png", &[Encoding::Gzip][..]),
+            ("short".to_string(), "text/plain", &[Encoding::Gzip][..]),
+        ] {
+            let resp = compress_response(http_response(StatusCode::OK, body, ctype), encodings).await;
+            assert!(!resp.headers().contains_key("vary") && !resp.headers().contains_key("content-encoding"), "{ctype}");
+        }
+    }
+
+    #[test]
+    fn proxy_protocol_headers() {
+        let v1 = parse_proxy_v1(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n").unwrap();
+        assert_eq!(v1, "192.0.2.1:56324".parse().ok());
+        assert_eq!(parse_proxy_v1(b"PROXY UNKNOWN\r\n"), Some(None));
+        assert_eq!(parse_proxy_v1(b"PROXY TCP4 nope\r\n"), None);
+        let body = [192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb];
+        assert_eq!(parse_proxy_v2(0x21, 0x11, &body), Some("192.0.2.1:56324".parse().ok()));
+        assert_eq!(parse_proxy_v2(0x20, 0x00, &[]), Some(None));
+        assert_eq!(parse_proxy_v2(0x21, 0x11, &body[..4]), None);
+    }
+
+    #[test]
+    fn percent_decode_is_strict_and_single_pass() {
+        assert_eq!(percent_decode("a%20b"), "a b");
+        assert_eq!(percent_decode("%2e%2E"), "..");
+        assert_eq!(percent_decode("%252e"), "%2e");
+        assert_eq!(percent_decode("%+f"), "%+f");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%4"), "%4");
+    }
+
+    #[test]
+    fn traversal_payloads_are_rejected() {
+        for raw in [
+            "/../etc/passwd",
+            "/..%2f..%2fetc%2fpasswd",
+            "/%2e%2e/%2e%2e/etc/passwd",
+            "/docs/..%2F..%2F..%2Fetc%2Fpasswd",
+            "/%2E%2E%5C..%2Fetc",
+            "/docs/a.txt%00.png",
+            "/%00",
+        ] {
+            assert_eq!(decode_request_path(raw), Err(ResolveError::Invalid), "{raw}");
+        }
+    }
+
+    #[test]
+    fn double_encoding_stays_literal() {
+        assert_eq!(decode_request_path("/%252e%252e%252fetc").unwrap(), "%2e%2e%2fetc");
+        let (base, root) = scratch("double");
+        let rel = decode_request_path("/%252e%252e/outside/secret.txt").unwrap();
+        assert_eq!(resolve_path(&root, &rel, SymlinkPolicy::Follow).unwrap_err(), ResolveError::NotFound);
+        let _ = std::fs::remove_dir_all(base);
+    }
+
+    #[test]
+    fn clean_paths_resolve() {
+        assert_eq!(decode_request_path("/").unwrap(), "");
+        assert_eq!(decode_request_path("//docs/./a%20b.txt").unwrap(), "docs/a b.txt");
+        let (base, root) = scratch("clean");
+        let r = resolve_path(&root, "docs/a.txt", SymlinkPolicy::Deny).unwrap();
+        assert_eq!(r.real, root.join("docs/a.txt"));
+        assert_eq!(resolve_path(&root, "docs/missing", SymlinkPolicy::Confine).unwrap_err(), ResolveError::NotFound);
+        let _ = std::fs::remove_dir_all(base);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_policies() {
+        let (base, root) = scratch("links");
+        // confine: links inside the root work, links leaving it are refused
+        assert!(resolve_path(&root, "inside/a.txt", SymlinkPolicy::Confine).is_ok());
+        assert_eq!(resolve_path(&root, "outside/secret.txt", SymlinkPolicy::Confine).unwrap_err(), ResolveError::OutsideRoot);
+        // follow: both work
+        assert!(resolve_path(&root, "outside/secret.txt", SymlinkPolicy::Follow).is_ok());
+        // deny: any link in the path is refused
+        assert_eq!(resolve_path(&root, "inside/a.txt", SymlinkPolicy::Deny).unwrap_err(), ResolveError::Symlink);
+        assert_eq!(resolve_path(&root, "outside", SymlinkPolicy::Deny).unwrap_err(), ResolveError::Symlink);
+
+        assert!(walk_allowed(&root, &root.join("inside"), SymlinkPolicy::Confine));
+        assert!(!walk_allowed(&root, &root.join("outside"), SymlinkPolicy::Confine));
+        assert!(!walk_allowed(&root, &root.join("inside"), SymlinkPolicy::Deny));
+        assert!(walk_allowed(&root, &root.join("outside"), SymlinkPolicy::Follow));
+        let _ = std::fs::remove_dir_all(base);
+    }
+
+    #[test]
+    fn content_index() {
+        let words = |t: &str| token_spans(t).into_iter().map(|(s, e)| t[s..e].to_string()).collect::<Vec<_>>();
+        assert_eq!(words("fn main() { x = résumé_v2; }"), ["fn", "main", "résumé", "v2"]);
+
+        let doc = |path: &str, text: &str| IndexedDoc { path: path.into(), mtime: 1, size: text.len() as u64, terms: 0, text: text.into() };
+        let mut idx = ContentIndex::default();
+        idx.insert(doc("notes/deploy.md", "How to deploy: run the deploy script, then deploy again."));
+        idx.insert(doc("notes/todo.txt", "Remember to deploy once."));
+        idx.insert(doc("src/main.rs", "fn main() { println!(\"hello\"); }"));
+        let (hits, total) = idx.search("Deploy", "", 10, |_| true);
+        assert_eq!(total, 2);
+        assert_eq!(hits[0].path, "notes/deploy.md");
+        assert!(hits[0].score > hits[1].score);
+        assert!(hits[1].snippet.contains("<mark>deploy</mark>"));
+        // scoped to a directory, and filtered by the caller
+        assert_eq!(idx.search("deploy", "src", 10, |_| true).1, 0);
+        assert_eq!(idx.search("deploy", "notes", 10, |p| !p.ends_with(".md")).1, 1);
+        assert_eq!(idx.search("!!", "", 10, |_| true).1, 0);
+
+        assert_eq!(highlight_snippet("a <b> & Match", &["match".into()]), "a &lt;b&gt; &amp; <mark>Match</mark>");
+        let long = format!("{} needle {}", "x ".repeat(100), "y ".repeat(200));
+        let snip = highlight_snippet(&long, &["needle".into()]);
+        assert!(snip.starts_with('…') && snip.ends_with('…') && snip.contains("<mark>needle</mark>"));
+
+        idx.remove_tree("notes");
+        assert_eq!(idx.len(), 1);
+        assert_eq!(idx.search("deploy", "", 10, |_| true).1, 0);
+        idx.insert(doc("a b/é.txt", "unicode path\nwith newline"));
+
+        let dir = temp_dir("index");
+        let file = dir.join("cache");
+        save_index(&idx, &file).unwrap();
+        let back = load_index(&file).unwrap();
+        assert_eq!(back.len(), 2);
+        assert!(back.is_fresh("a b/é.txt", 1, 25));
+        assert_eq!(back.search("newline", "", 10, |_| true).0[0].path, "a b/é.txt");
+        #[cfg(unix)]
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&file).unwrap().permissions()) & 0o777, 0o600);
+        // Lengths running past the end, or past usize, are a damaged cache
+        let data = std::fs::read(&file).unwrap();
+        std::fs::write(&file, &data[..data.len() - 1]).unwrap();
+        assert!(load_index(&file).is_none());
+        let mut huge = INDEX_MAGIC.to_vec();
+        huge.extend_from_slice(format!("{} 0 0 {}\nab", usize::MAX, usize::MAX).as_bytes());
+        std::fs::write(&file, huge).unwrap();
+        assert!(load_index(&file).is_none());
+        std::fs::write(&file, b"not an index").unwrap();
+        assert!(load_index(&file).is_none());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn leakignore_globs() {
+        let g = |p: &str, t: &str| glob_match(p.as_bytes(), t.as_bytes());
+        assert!(g("*.log", "app.log"));
+        assert!(!g("*.log", "logs/app.log"));
+        assert!(g("**/*.log", "logs/deep/app.log"));
+        assert!(g("**/*.log", "app.log"));
+        assert!(g("build/**", "build/a/b"));
+        assert!(g("file?.txt", "file1.txt") && !g("file?.txt", "file/.txt"));
+        assert!(g("[a-c]x", "bx") && !g("[!a-c]x", "bx"));
+        assert!(g("\\*star", "*star") && !g("\\*star", "xstar"));
+
+        let rules = parse_ignore_file("# comment\n\n*.log\n!keep.log\nsecrets/\n/build\n\\#literal\n");
+        assert_eq!(rules.len(), 5);
+        assert!(rules[1].negate && rules[2].dir_only && rules[3].anchored);
+        assert_eq!(rules[4].pattern, "#literal");
+
+        let root = temp_dir("ignore");
+        write_tree(&root, &[(".leakignore", "*.log\n!keep.log\nsecrets/\n/build\n"), ("docs/a.txt", "a")]);
+        let hidden = HiddenPolicy::new(&root, false, false);
+        assert_eq!(hidden.rule_count(), 4);
+        assert!(hidden.is_hidden("debug.log", false));
+        assert!(hidden.is_hidden("docs/debug.log", false));
+        assert!(!hidden.is_hidden("keep.log", false));
+        assert!(hidden.is_hidden("secrets", true));
+        assert!(!hidden.is_hidden("secrets", false));
+        // children of an ignored directory cannot be brought back
+        assert!(hidden.is_hidden("secrets/keep.log", false));
+        assert!(hidden.is_hidden("build/out.bin", false));
+        assert!(!hidden.is_hidden("docs/build", true));
+        assert!(hidden.is_hidden(".env", false) && hidden.is_hidden(".leakignore", false));
+        assert!(!HiddenPolicy::new(&root, true, false).is_hidden(".env", false));
+        assert!(hidden.hides_path(&root, &root.join("docs/x.log")));
+        assert!(!hidden.hides_path(&root, &root.join("docs/a.txt")));
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn mounts_and_access() {
+        let (base, root) = scratch("mounts");
+        write_tree(&base, &[("ops/f.txt", "ops"), ("pub/f.txt", "pub"), ("root/ops/shadowed.txt", "x")]);
+        let cfg = server(&root, vec![
+            ("ops", base.join("ops"), Access::Basic(base64::engine::general_purpose::STANDARD.encode("ops:pw"))),
+            ("pub", base.join("pub"), Access::Public),
+        ], Some("me:secret"));
+
+        assert_eq!(cfg.mount_for("ops/f.txt").0.prefix, "ops");
+        assert_eq!(cfg.mount_for("ops/f.txt").1, "f.txt");
+        assert_eq!(cfg.mount_for("ops").1, "");
+        assert_eq!(cfg.mount_for("opsx/f.txt").0.prefix, "");
+        assert_eq!(cfg.mount_for("docs/a.txt").1, "docs/a.txt");
+        // mounts shadow real directories of the same name
+        assert_eq!(cfg.resolve("/ops/f.txt").unwrap().1.real, base.join("ops/f.txt").canonicalize().unwrap());
+        assert!(cfg.resolve("/ops/shadowed.txt").is_err());
+        assert!(cfg.resolve("/pub/../ops/f.txt").is_err());
+
+        // a mount's own credentials are the only ones it takes
+        let b64 = |c: &str| base64::engine::general_purpose::STANDARD.encode(c);
+        assert_eq!(cfg.required_auth(&cfg.mounts[0]), Some(b64("me:secret").as_str()));
+        assert_eq!(cfg.required_auth(&cfg.mounts[1]), Some(b64("ops:pw").as_str()));
+        assert_eq!(cfg.required_auth(&cfg.mounts[2]), None);
+        let open = server(&root, vec![("ops", base.join("ops"), Access::Inherit)], None);
+        assert_eq!(open.required_auth(&open.mounts[1]), None);
+        let _ = std::fs::remove_dir_all(base);
+    }
+
+    #[test]
+    fn settings_layers() {
+        let dir = temp_dir("layers");
+        let file = dir.join("leak.toml");
+        std::fs::write(&file, "port = 7000\nsymlinks = \"deny\"\nauth = \"file:pw\"\nread-only = true\nindex-cache = \"cache\"\n\n[[mount]]\npath = \"/a\"\ndir = \"a\"\n\n[profile.ci]\nport = 7100\n").unwrap();
+        let mut from_file = load_config(&file).unwrap();
+        assert_eq!(from_file.index_cache.as_deref(), Some(dir.join("cache").as_path()));
+        assert_eq!(from_file.mounts[0].dir, dir.join("a"));
+        let ci = from_file.profiles.remove("ci").unwrap();
+
+        env::set_var("LEAK_PORT", "7200");
+        env::set_var("LEAK_SYMLINKS", "follow");
+        let envs = env_settings();
+        env::set_var("LEAK_SYMLINKS", "");
+        env::set_var("LEAK_TLS", "maybe");
+        let bad_env = env_settings().err();
+        env::remove_var("LEAK_PORT");
+        env::remove_var("LEAK_SYMLINKS");
+        env::remove_var("LEAK_TLS");
+        assert_eq!(bad_env.unwrap(), "LEAK_TLS: expected true or false, got `maybe`");
+        let envs = envs.unwrap();
+
+        let cli = cli_settings(&["--port".into(), "7300".into(), "--mount".into(), "/a=other".into()]).unwrap();
+        let s = from_file.overlay(ci).overlay(envs).overlay(cli);
+        assert_eq!(s.port, Some(7300));
+        assert_eq!(s.symlinks.as_deref(), Some("follow"));
+        assert_eq!(s.auth.as_deref(), Some("file:pw"));
+        assert_eq!(s.read_only, Some(true));
+        assert_eq!(s.mounts.len(), 1);
+        assert_eq!(s.mounts[0].dir, PathBuf::from("other"));
+
+        std::fs::write(&file, "port = 1\nmax-upload = 5\n").unwrap();
+        assert!(load_config(&file).err().unwrap().contains("unknown field `max-upload`"));
+        std::fs::write(&file, "[profile.a.profile.b]\nport = 1\n").unwrap();
+        assert!(load_config(&file).err().unwrap().contains("profile `a` cannot contain profiles"));
+        assert_eq!(cli_settings(&["--port".into(), "http".into()]).err().unwrap(), "invalid port: http");
+        assert_eq!(cli_settings(&["80".into(), "dir".into(), "extra".into()]).err().unwrap(), "unexpected argument: extra");
+        assert!(cli_settings(&["--mount".into(), "/x=dir,fast".into()]).err().unwrap().contains("unknown mount option: fast"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn bind_specs() {
+        let tcp = |spec: &str| match ListenSpec::parse(spec, 8080, false).unwrap() {
+            ListenSpec { addr: ListenAddr::Tcp(a), tls, fixed_port } => (a.to_string(), tls, fixed_port),
+            _ => panic!("{spec} is not a TCP address"),
+        };
+        assert_eq!(tcp("127.0.0.1"), ("127.0.0.1:8080".into(), false, false));
+        assert_eq!(tcp("localhost:9000"), ("127.0.0.1:9000".into(), false, true));
+        assert_eq!(tcp("::1"), ("[::1]:8080".into(), false, false));
+        assert_eq!(tcp("[::1]:443"), ("[::1]:443".into(), false, true));
+        assert_eq!(tcp("https://[fe80::1]"), ("[fe80::1]:8080".into(), true, false));
+        assert_eq!(tcp("http://10.0.0.1:80/"), ("10.0.0.1:80".into(), false, true));
+        assert!(matches!(ListenSpec::parse("*:auto", 8080, true).unwrap(), ListenSpec { addr: ListenAddr::Any(0), tls: true, fixed_port: true }));
+        assert!(matches!(ListenSpec::parse("https://*", 8080, false).unwrap(), ListenSpec { addr: ListenAddr::Any(8080), tls: true, fixed_port: false }));
+        match ListenSpec::parse("unix:/run/leak.sock", 8080, true).unwrap() {
+            ListenSpec { addr: ListenAddr::Unix(p), tls: true, fixed_port: true } => assert_eq!(p, PathBuf::from("/run/leak.sock")),
+            _ => panic!("expected a unix socket"),
+        }
+        assert_eq!(ListenSpec::parse("unix:", 8080, false).err().unwrap(), "missing socket path");
+        assert_eq!(ListenSpec::parse("[::1", 8080, false).err().unwrap(), "unclosed [");
+        assert_eq!(ListenSpec::parse("[::1]80", 8080, false).err().unwrap(), "expected [address]:port");
+        assert_eq!(ListenSpec::parse("1.2.3.4:http", 8080, false).err().unwrap(), "invalid port http");
+        assert_eq!(ListenSpec::parse("example.com", 8080, false).err().unwrap(), "not an IP address: example.com");
+    }
+
+    #[tokio::test]
+    async fn port_retries() {
+        assert_eq!(parse_port("auto"), Some(0));
+        assert_eq!(parse_port("8080"), Some(8080));
+        assert_eq!(parse_port("65536"), None);
+
+        let local = |fixed_port| vec![ListenSpec { addr: ListenAddr::Tcp("127.0.0.1:0".parse().unwrap()), tls: false, fixed_port }];
+        let bound = bind_all(&local(false), 0, 0).await.unwrap();
+        let busy = bound[0].0.port().unwrap();
+        assert_ne!(busy, 0);
+        // the main port walks up past a busy one, as far as the retries allow
+        let err = bind_all(&local(false), busy, 0).await.err().unwrap();
+        assert!(err.starts_with(&format!("failed to bind to 127.0.0.1:{busy}")));
+        if let Ok(next) = TcpListener::bind(("127.0.0.1", busy + 1)).await {
+            assert_eq!(bind_all(&local(false), busy, 1).await.err().unwrap(), format!("ports {busy}-{} are all in use", busy + 1));
+            drop(next);
+            let moved = bind_all(&local(false), busy, 1).await.unwrap();
+            assert_eq!(moved[0].0.port(), Some(busy + 1));
+        }
+        // a --bind with its own port stays put
+        let mut fixed = local(true);
+        fixed[0].addr = ListenAddr::Tcp(SocketAddr::from(([127, 0, 0, 1], busy)));
+        assert!(bind_all(&fixed, busy, 5).await.is_err());
+    }
+
+    #[test]
+    fn qr_targets() {
+        for name in ["docker0", "br-1a2b", "veth3f", "virbr0", "vmnet8", "vboxnet0", "lxdbr0", "cni0", "tun0", "tap1", "utun3", "wg0", "tailscale0", "ztabc", "awdl0", "llw0", "bridge100"] {
+            assert!(is_virtual_iface(name), "{name}");
+        }
+        for name in ["eth0", "en0", "wlan0", "wlp2s0", "enp3s0", "eno1"] {
+            assert!(!is_virtual_iface(name), "{name}");
+        }
+        for ip in ["127.0.0.1", "::1", "169.254.3.4", "fe80::1", "0.0.0.0", "::ffff:127.0.0.1"] {
+            assert!(!reachable(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["192.168.1.5", "10.0.0.7", "100.64.0.2", "fd00::5", "2001:db8::1"] {
+            assert!(reachable(ip.parse().unwrap()), "{ip}");
+        }
+
+        let mut addrs: Vec<LocalAddr> = [("docker0", "172.17.0.1"), ("wlan0", "fd00::5"), ("tailscale0", "100.64.0.2"), ("wlan0", "192.168.1.5"), ("br-1a2b", "172.18.0.1"), ("eth0", "10.0.0.7")]
+            .into_iter().map(|(iface, ip)| LocalAddr { iface: iface.into(), ip: ip.parse().unwrap() }).collect();
+        sort_addresses(&mut addrs);
+        let order: Vec<String> = addrs.iter().map(|a| format!("{} {}", a.iface, url_host(a.ip))).collect();
+        assert_eq!(order, ["wlan0 192.168.1.5", "eth0 10.0.0.7", "wlan0 [fd00::5]", "docker0 172.17.0.1", "tailscale0 100.64.0.2", "br-1a2b 172.18.0.1"]);
+
+        let urls: Vec<(String, String)> = addrs.iter().map(|a| (a.iface.clone(), format!("http://{}:8080", url_host(a.ip)))).collect();
+        assert_eq!(pick_qr("auto", &urls).unwrap(), [0]);
+        assert_eq!(pick_qr("auto", &[]).unwrap(), Vec::<usize>::new());
+        assert_eq!(pick_qr("all", &urls).unwrap(), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(pick_qr("none", &urls).unwrap(), Vec::<usize>::new());
+        assert_eq!(pick_qr("wlan0", &urls).unwrap(), [0, 2]);
+        assert_eq!(pick_qr("10.0.0.7", &urls).unwrap(), [1]);
+        assert_eq!(pick_qr("fd00::5", &urls).unwrap(), [2]);
+        assert_eq!(pick_qr("4", &urls).unwrap(), [3]);
+        assert_eq!(pick_qr("7", &urls).unwrap_err(), "--qr 7: only 6 network address(es)");
+        assert!(pick_qr("0", &urls).is_err());
+        assert!(pick_qr("10.0.0", &urls).unwrap_err().starts_with("--qr 10.0.0: no such interface or address"));
+    }
+
+    #[test]
+    fn discovery_records() {
+        assert_eq!(dns_label("Kim's MacBook Pro"), "kim-s-macbook-pro");
+        assert_eq!(dns_label("--"), "leak");
+        assert_eq!(dns_label(&"x".repeat(80)).len(), 63);
+        let txt = txt_records(true, false);
+        assert_eq!(txt[..3], [("path", "/"), ("tls", "1"), ("auth", "0")]);
+        assert_eq!(txt[3], ("version", env!("CARGO_PKG_VERSION")));
+
+        let info = mdns_sd::ServiceInfo::new(MDNS_LEAK, "box \"a\" (8443)", "box.local.", "127.0.0.1,fe80::1,fd00::2,192.168.1.5", 8443, &txt_records(true, true)[..]).unwrap();
+        let found = Discovered::from_info(&info);
+        assert_eq!(found.name, "box \"a\" (8443)");
+        assert_eq!(found.host, "box.local");
+        // routable IPv4 first, loopback last, no link-local
+        assert_eq!(found.urls, ["https://192.168.1.5:8443/", "https://[fd00::2]:8443/", "https://127.0.0.1:8443/"]);
+        assert_eq!(found.json(), r#"{"name":"box \"a\" (8443)","host":"box.local","port":8443,"tls":true,"auth":true,"urls":["https://192.168.1.5:8443/","https://[fd00::2]:8443/","https://127.0.0.1:8443/"]}"#);
+
+        // What `advertise` registers is what `discover` reads back
+        let info = mdns_sd::ServiceInfo::new(MDNS_LEAK, "plain", "plain.local.", "10.0.0.7", 8080, &txt_records(false, false)[..]).unwrap();
+        assert_eq!(info.get_property_val_str("version"), Some(env!("CARGO_PKG_VERSION")));
+        assert_eq!(info.get_property_val_str("path"), Some("/"));
+        let found = Discovered::from_info(&info);
+        assert!(!found.tls && !found.auth);
+        assert_eq!(found.json(), r#"{"name":"plain","host":"plain.local","port":8080,"tls":false,"auth":false,"urls":["http://10.0.0.7:8080/"]}"#);
+    }
+
+    #[test]
+    fn generated_credentials() {
+        let given = || Some(("me".to_string(), "pw".to_string()));
+        // on the LAN nothing is made up
+        assert_eq!(public_auth(false, None, false, false).unwrap(), (None, false));
+        assert_eq!(public_auth(false, given(), false, false).unwrap(), (given(), false));
+        // public always has a password: the one given, or a fresh one
+        assert_eq!(public_auth(true, given(), false, false).unwrap(), (given(), false));
+        let (auth, generated) = public_auth(true, None, false, false).unwrap();
+        let (user, pass) = auth.unwrap();
+        assert!(generated);
+        assert_eq!(user, "leak");
+        assert_eq!(pass.len(), 19);
+        assert_ne!(pass, public_auth(true, None, false, false).unwrap().0.unwrap().1);
+        // --no-auth wins over --auth, but public needs --i-know as well
+        assert_eq!(public_auth(false, given(), true, false).unwrap(), (None, false));
+        assert!(public_auth(true, None, true, false).unwrap_err().contains("add --i-know"));
+        assert_eq!(public_auth(true, given(), true, true).unwrap(), (None, false));
+    }
+
+    #[test]
+    fn plain_http_on_tls_port() {
+        let req = |host: Option<&str>, uri: &str| {
+            let mut r = Request::get(uri);
+            if let Some(h) = host { r = r.header("host", h); }
+            r.body(()).unwrap()
+        };
+        let resp = https_redirect(&req(Some("files.lan:8443"), "/docs/a%20b.txt?dl=1"), None);
+        assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(resp.headers()["location"], "https://files.lan:8443/docs/a%20b.txt?dl=1");
+        let location = |host: Option<&str>, local: Option<&str>| {
+            let resp = https_redirect(&req(host, "/"), local.map(|l| l.parse().unwrap()));
+            resp.headers().get("location").map(|l| l.to_str().unwrap().to_string())
+        };
+        assert_eq!(location(Some("[::1]:8443"), None).as_deref(), Some("https://[::1]:8443/"));
+        assert_eq!(location(Some("files.lan:443"), None).as_deref(), Some("https://files.lan/"));
+        assert_eq!(location(Some("files.lan"), None).as_deref(), Some("https://files.lan/"));
+        // Anything else in Host can't steer the redirect
+        for bad in ["evil.example@files.lan", "files.lan/evil", "files.lan:x", "[::1", "a b", ""] {
+            assert_eq!(location(Some(bad), Some("192.0.2.7:8443")).as_deref(), Some("https://192.0.2.7:8443/"), "{bad}");
+        }
+        assert_eq!(location(None, Some("[::ffff:192.0.2.7]:443")).as_deref(), Some("https://192.0.2.7/"));
+        assert_eq!(https_redirect(&req(None, "/"), None).status(), StatusCode::BAD_REQUEST);
+        assert_eq!(https_redirect(&req(Some("a b"), "/"), None).status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn h2c_prior_knowledge() {
+        assert_eq!(h2_window("256k"), Some(256 << 10));
+        assert_eq!(h2_window("2147483647"), Some(i32::MAX as u32));
+        assert_eq!(h2_window("2g"), None);
+        assert_eq!(h2_window("0"), None);
+        assert_eq!(h2_window("fast"), None);
+
+        // a plain-text connection that starts with the HTTP/2 preface, against each builder
+        let fetch = |enabled: bool| async move {
+            let opts = Http2Options { enabled, max_streams: Some(8), stream_window: h2_window("1m"), conn_window: None };
+            let http = http_builder(&opts);
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                let svc = service_fn(|req: Request<Incoming>| async move {
+                    Ok::<_, std::convert::Infallible>(http_response(StatusCode::OK, format!("{:?}", req.version()), "text/plain"))
                 });
-            }
-            _ = signal::ctrl_c() => {
-                eprintln!("\n  {D}Shutting down...{RST}");
-                if let Some(ref mut child) = _tunnel_child { let _ = child.kill().await; }
-                break;
-            }
+                let _ = http.serve_connection(TokioIo::new(stream), svc).await;
+            });
+            let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let (mut client, conn) = h2::client::handshake(stream).await.ok()?;
+            tokio::spawn(conn);
+            let (resp, _) = client.send_request(Request::get("http://localhost/").body(()).unwrap(), true).ok()?;
+            let mut body = resp.await.ok()?.into_body();
+            let data = body.data().await?.ok()?;
+            // The server's SETTINGS have arrived by the time a response has
+            Some((String::from_utf8_lossy(&data).into_owned(), client.current_max_send_streams()))
+        };
+        assert_eq!(fetch(true).await, Some(("HTTP/2.0".to_string(), 8)));
+        assert_eq!(fetch(false).await, None);
+    }
+
+    #[tokio::test]
+    async fn range_responses() {
+        let (base, root) = scratch("ranges");
+        write_tree(&root, &[("docs/nums.txt", "0123456789")]);
+        assert_eq!(read_slice(&root.join("docs/nums.txt"), 3, 4).await.unwrap(), b"3456");
+        assert_eq!(read_slice(&root.join("docs/nums.txt"), 8, 10).await.unwrap(), b"89");
+
+        let cfg = server(&root, vec![], None);
+        let content_range = |resp: &Response<String>| resp.headers().get("content-range").map(|v| v.to_str().unwrap().to_string());
+        let resp = get(&cfg, "/docs/nums.txt", &[("range", "bytes=2-4")]).await;
+        assert_eq!((resp.status(), resp.body().as_str()), (StatusCode::PARTIAL_CONTENT, "234"));
+        assert_eq!(content_range(&resp).as_deref(), Some("bytes 2-4/10"));
+        let resp = get(&cfg, "/docs/nums.txt", &[("range", "bytes=-3")]).await;
+        assert_eq!((resp.status(), resp.body().as_str()), (StatusCode::PARTIAL_CONTENT, "789"));
+        assert_eq!(content_range(&resp).as_deref(), Some("bytes 7-9/10"));
+        let resp = get(&cfg, "/docs/nums.txt", &[("range", "bytes=10-")]).await;
+        assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(content_range(&resp).as_deref(), Some("bytes */10"));
+        // several ranges at once are answered with the whole file
+        let resp = get(&cfg, "/docs/nums.txt", &[("range", "bytes=0-1,4-5")]).await;
+        assert_eq!((resp.status(), resp.body().as_str()), (StatusCode::OK, "0123456789"));
+        assert_eq!(content_range(&resp), None);
+        assert_eq!(resp.headers()["accept-ranges"], "bytes");
+        let _ = std::fs::remove_dir_all(base);
+    }
+
+    #[tokio::test]
+    async fn clean_urls_and_spa_fallback() {
+        let (base, root) = scratch("site");
+        write_tree(&base, &[
+            ("root/index.html", "home"), ("root/about.html", "about page"), ("root/about", "plain file"),
+            ("root/team.html", "team"), ("root/404.html", "custom 404"), ("app/index.html", "app shell"),
+        ]);
+        let site = |spa| {
+            let mut cfg = Arc::into_inner(server(&root, vec![("app", base.join("app"), Access::Inherit)], None)).unwrap();
+            cfg.spa = spa;
+            cfg
+        };
+
+        let cfg = site(false);
+        assert_eq!(static_fallback(&cfg, "/team").unwrap().0, "/team.html");
+        assert!(static_fallback(&cfg, "/nope").is_none());
+        assert!(static_fallback(&cfg, "/team/").is_none());
+
+        let cfg = site(true);
+        // a page of that name comes first, then the shell of the mount the path is in
+        assert_eq!(static_fallback(&cfg, "/team").unwrap().0, "/team.html");
+        assert_eq!(static_fallback(&cfg, "/users/42").unwrap().0, "/index.html");
+        assert_eq!(static_fallback(&cfg, "/app/settings").unwrap().0, "/app/index.html");
+        // anything that looks like a file stays a 404
+        assert!(static_fallback(&cfg, "/missing.js").is_none());
+
+        let cfg = Arc::new(cfg);
+        let page = |resp: Response<String>| (resp.status(), resp.into_body());
+        assert_eq!(page(get(&cfg, "/about", &[]).await), (StatusCode::OK, "plain file".into()));
+        assert_eq!(page(get(&cfg, "/team", &[]).await), (StatusCode::OK, "team".into()));
+        assert_eq!(page(get(&cfg, "/users/42", &[]).await), (StatusCode::OK, "home".into()));
+        assert_eq!(page(get(&cfg, "/missing.js", &[]).await), (StatusCode::NOT_FOUND, "custom 404".into()));
+        let resp = get(&cfg, "/docs", &[]).await;
+        assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(resp.headers()["location"], "/docs/");
+        // Never `//host/`: the Location is rebuilt from the clean path
+        std::fs::create_dir(root.join("a b")).unwrap();
+        for (path, location) in [("//docs", "/docs/"), ("//docs/", "/docs/"), ("/a%20b?x=1", "/a%20b/?x=1"), ("//", "/")] {
+            let resp = get(&cfg, path, &[]).await;
+            assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY, "{path}");
+            assert_eq!(resp.headers()["location"], location, "{path}");
+        }
+        let _ = std::fs::remove_dir_all(base);
+    }
+
+    #[tokio::test]
+    async fn listing_hides_locked_mounts() {
+        let (base, root) = scratch("listing");
+        write_tree(&base, &[("ops/f.txt", "ops"), ("pub/f.txt", "pub")]);
+        let basic = |creds: &str| format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(creds));
+        let ops = || ("ops", base.join("ops"), Access::Basic(base64::engine::general_purpose::STANDARD.encode("ops:pw")));
+        let cfg = server(&root, vec![ops(), ("pub", base.join("pub"), Access::Public)], Some("me:secret"));
+        let status = |resp: Response<String>| resp.status();
+        assert_eq!(status(get(&cfg, "/pub/f.txt", &[]).await), StatusCode::OK);
+        assert_eq!(status(get(&cfg, "/ops/f.txt", &[("authorization", &basic("me:secret"))]).await), StatusCode::UNAUTHORIZED);
+        assert_eq!(status(get(&cfg, "/ops/f.txt", &[("authorization", &basic("ops:pw"))]).await), StatusCode::OK);
+
+        let listed = |resp: Response<String>, prefix: &str| resp.body().contains(&format!("data-href=\"/{prefix}/\""));
+        let page = get(&cfg, "/", &[("authorization", &basic("me:secret"))]).await;
+        assert!(listed(page, "pub"));
+        let page = get(&cfg, "/", &[("authorization", &basic("me:secret"))]).await;
+        assert!(!listed(page, "ops"));
+        let open = server(&root, vec![ops()], None);
+        assert!(!listed(get(&open, "/", &[]).await, "ops"));
+        assert!(listed(get(&open, "/", &[("authorization", &basic("ops:pw"))]).await, "ops"));
+        let _ = std::fs::remove_dir_all(base);
+    }
+
+    #[tokio::test]
+    async fn login_link_asks_enrolled_users_for_a_code() {
+        let (base, root) = scratch("login-link");
+        let store = base.join("totp.toml");
+        let record = TotpRecord { user: "me".into(), secret: base32_encode(b"12345678901234567890"), recovery: vec![], created: 0 };
+        write_totp(&store, &[record]).unwrap();
+        let link = |cfg: &Arc<ServerConfig>| format!("/__login?key={}", cfg.sessions.link_key(cfg.auth.as_deref().unwrap()));
+
+        let cfg = server(&root, vec![], Some("me:secret"));
+        let resp = get(&cfg, &link(&cfg), &[]).await;
+        assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+        assert!(resp.headers().contains_key("set-cookie"));
+
+        let mut enrolled = Arc::into_inner(server(&root, vec![], Some("me:secret"))).unwrap();
+        enrolled.totp = Some(TotpStore::open(store).unwrap());
+        let cfg = Arc::new(enrolled);
+        let resp = get(&cfg, &link(&cfg), &[]).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(!resp.headers().contains_key("set-cookie"));
+        assert!(resp.body().contains("name=\"challenge\""));
+        let _ = std::fs::remove_dir_all(base);
+    }
+
+    #[tokio::test]
+    async fn bearer_tokens() {
+        let (base, root) = scratch("bearer");
+        // Without a token store a Bearer header is just an unknown credential
+        let cfg = server(&root, vec![], None);
+        let bearer = [("authorization", "Bearer leak_abc_def")];
+        assert_eq!(get(&cfg, "/docs/a.txt", &bearer).await.status(), StatusCode::OK);
+
+        let store = base.join("tokens.toml");
+        let record = TokenRecord {
+            id: "abc".into(), name: None, hash: token_hash("def"), scope: String::new(), perm: Perm::Read,
+            created: 0, expires: None, last_used: None,
+        };
+        write_tokens(&store, &[record]).unwrap();
+        let tokens = TokenStore::open(store.clone()).unwrap();
+        assert!(tokens.verify("leak_abc_def").is_some());
+        assert!(tokens.loaded.read().unwrap().tokens[0].last_used.is_some());
+        // The write-back happens in the background
+        for _ in 0..100 {
+            if read_tokens(&store).unwrap()[0].last_used.is_some() { break; }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
         }
+        assert!(read_tokens(&store).unwrap()[0].last_used.is_some());
+        assert!(tokens.verify("leak_abc_wrong").is_none());
+        let _ = std::fs::remove_dir_all(base);
     }
 }